@@ -93,5 +93,35 @@ This is a [link](example.com). **Cool!**
     });
 }
 
-criterion_group!(benches, criterion_benchmark);
+/// Compares the scanners using 256-bit AVX2 vectors, 128-bit SSSE3 vectors and
+/// no vectors at all. Widths the processor doesn't support fall back to the
+/// narrower ones. Needs the `bench-simd-width` feature, which lets the
+/// benchmark limit the width for the whole process.
+#[cfg(all(target_arch = "x86_64", feature = "bench-simd-width"))]
+fn simd_widths_benchmark(c: &mut Criterion) {
+    let input = from_utf8(CRDT_BYTES).unwrap();
+    let events: Vec<_> = Parser::new_ext(input, Options::empty()).collect();
+    let mut buf = String::with_capacity(input.len() * 3 / 2);
+
+    for &width in [0, 128, 256].iter() {
+        pulldown_cmark::set_max_simd_width(width);
+
+        c.bench_function(&format!("crdt_parse_simd_{}", width), |b| {
+            b.iter(|| Parser::new_ext(input, Options::empty()).count())
+        });
+
+        c.bench_function(&format!("crdt_html_simd_{}", width), |b| {
+            b.iter(|| {
+                buf.clear();
+                html::push_html(&mut buf, events.clone().into_iter());
+            })
+        });
+    }
+    pulldown_cmark::set_max_simd_width(256);
+}
+
+#[cfg(not(all(target_arch = "x86_64", feature = "bench-simd-width")))]
+fn simd_widths_benchmark(_c: &mut Criterion) {}
+
+criterion_group!(benches, criterion_benchmark, simd_widths_benchmark);
 criterion_main!(benches);
@@ -2,7 +2,7 @@ use criterion::{criterion_group, criterion_main};
 
 mod to_html {
     use criterion::{BenchmarkId, Criterion, Throughput};
-    use pulldown_cmark::{html, Options, Parser};
+    use pulldown_cmark::{html, Options, Parser, ParserPool};
 
     pub fn pathological_codeblocks1(c: &mut Criterion) {
         let mut group = c.benchmark_group("pathological_codeblocks1");
@@ -35,6 +35,41 @@ mod to_html {
         group.finish();
     }
 
+    pub fn many_small_documents(c: &mut Criterion) {
+        let mut group = c.benchmark_group("many_small_documents");
+        let comments = (0..1000)
+            .map(|i| {
+                format!(
+                    "Thanks @user{}, *nice* catch. See [the docs](https://example.com/{}).\n\n- `fix`\n- test\n",
+                    i, i
+                )
+            })
+            .collect::<Vec<_>>();
+        let bytes = comments.iter().map(String::len).sum::<usize>();
+        group.throughput(Throughput::Bytes(bytes as u64));
+        let mut s = String::new();
+        group.bench_function("new", |b| {
+            b.iter(|| {
+                for comment in &comments {
+                    s.clear();
+                    html::push_html(&mut s, Parser::new_ext(comment, Options::empty()));
+                }
+            })
+        });
+        let mut pool = ParserPool::new();
+        group.bench_function("pool", |b| {
+            b.iter(|| {
+                for comment in &comments {
+                    s.clear();
+                    let mut parser = pool.parser(comment, Options::empty());
+                    html::push_html(&mut s, &mut parser);
+                    pool.recycle(parser);
+                }
+            })
+        });
+        group.finish();
+    }
+
     fn render_html(text: &str, opts: Options) -> String {
         let mut s = String::with_capacity(text.len() * 3 / 2);
         let p = Parser::new_ext(text, opts);
@@ -46,6 +81,7 @@ mod to_html {
 criterion_group!(
     benches,
     to_html::pathological_codeblocks1,
-    to_html::advanced_pathological_codeblocks
+    to_html::advanced_pathological_codeblocks,
+    to_html::many_small_documents
 );
 criterion_main!(benches);
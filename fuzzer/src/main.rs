@@ -227,6 +227,8 @@ fn regression_test() -> i32 {
     check_pattern("a***".into());
     check_pattern("[[]()".into());
     check_pattern("[a](<".into());
+    // every `[TOC]` placeholder copies the headings of the document
+    check_pattern("[TOC]\n\n# h\n".into());
     exit_code
 }
 
@@ -0,0 +1,880 @@
+//! Owned syntax tree.
+//!
+//! A [`Document`] is built from a stream of events, usually a [`Parser`], by
+//! collecting it, or with [`Document::try_from_events`] for events that may not
+//! be well-formed. It owns all of its text, so it can be kept around, changed
+//! and serialized independently of the source. The tree converts back into events
+//! with [`Document::events`], for example to render it with [`push_html`].
+//!
+//! The tree keeps the structure of the events, but not how they are split up:
+//! the lines of a code block or HTML block end up in a single string. Rendering
+//! the events of a document gives the same result as rendering the events it
+//! was built from.
+//!
+//! [`Parser`]: crate::Parser
+//! [`push_html`]: crate::html::push_html
+//!
+//! # Example
+//! ```rust
+//! use pulldown_cmark::ast::{Block, Document, Inline};
+//! use pulldown_cmark::{html, Parser};
+//!
+//! let markdown_input = "Hello *world*\n";
+//! let mut document: Document = Parser::new(markdown_input).collect();
+//!
+//! if let Block::Paragraph { content, .. } = &mut document.blocks[0] {
+//!     content.push(Inline::Text("!".to_owned()));
+//! }
+//! let mut html_output = String::new();
+//! html::push_html(&mut html_output, document.events());
+//! assert_eq!("<p>Hello <em>world</em>!</p>\n", html_output);
+//! ```
+
+use core::fmt;
+use core::iter::{FromIterator, Peekable};
+use core::mem::{self, Discriminant};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::prelude::*;
+use crate::{
+    Alignment, CodeBlockKind, CowStr, Event, HeadingLevel, LinkType, ListMarker, ListNumbering, Tag,
+};
+
+/// The root of the tree.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Document {
+    /// The top-level blocks of the document.
+    pub blocks: Vec<Block>,
+}
+
+/// A block-level element.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Block {
+    /// Inline content that isn't wrapped in a paragraph, like the text of a
+    /// tight list item.
+    Plain(Vec<Inline>),
+    /// A paragraph, see [`Tag::Paragraph`].
+    Paragraph {
+        id: Option<String>,
+        content: Vec<Inline>,
+    },
+    /// A heading, see [`Tag::Heading`].
+    Heading {
+        level: HeadingLevel,
+        id: Option<String>,
+        classes: Vec<String>,
+        content: Vec<Inline>,
+    },
+    /// A block quote.
+    BlockQuote(Vec<Block>),
+    /// A code block. The info string is `None` for indented code blocks.
+    CodeBlock { info: Option<String>, text: String },
+    /// A list, see [`Tag::List`].
+    List {
+        start: Option<u64>,
+        numbering: ListNumbering,
+        marker: ListMarker,
+        tight: bool,
+        items: Vec<ListItem>,
+    },
+    /// A footnote definition.
+    FootnoteDefinition { label: String, blocks: Vec<Block> },
+    /// A table. Each row is made of cells, which are made of inline content.
+    Table {
+        alignments: Vec<Alignment>,
+        head: Vec<Vec<Inline>>,
+        rows: Vec<Vec<Vec<Inline>>>,
+    },
+    /// A line block, made of lines of inline content.
+    LineBlock(Vec<Vec<Inline>>),
+    /// A custom block, see [`Tag::Custom`].
+    Custom {
+        name: String,
+        data: String,
+        text: String,
+    },
+    /// An HTML block.
+    Html(String),
+    /// A raw block, see [`Event::Raw`].
+    Raw { format: String, content: String },
+    /// A comment, see [`Event::Comment`].
+    Comment(String),
+    /// A link reference definition, see [`Event::LinkDefinition`].
+    LinkDefinition {
+        label: String,
+        dest: String,
+        title: Option<String>,
+    },
+    /// A horizontal rule.
+    Rule,
+}
+
+/// An item of a [`Block::List`].
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ListItem {
+    /// Whether the task list marker of the item is checked, if it has one.
+    pub checked: Option<bool>,
+    /// The block identifier of the item, see [`Tag::Item`].
+    pub id: Option<String>,
+    /// The content of the item.
+    pub blocks: Vec<Block>,
+}
+
+/// An inline element.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Inline {
+    Text(String),
+    Code(String),
+    Html(String),
+    /// Raw inline content, see [`Event::Raw`].
+    Raw {
+        format: String,
+        content: String,
+    },
+    Comment(String),
+    Hashtag(String),
+    /// A custom inline element, see [`Event::Custom`].
+    Custom {
+        name: String,
+        data: String,
+    },
+    FootnoteReference(String),
+    SoftBreak,
+    HardBreak,
+    Emphasis(Vec<Inline>),
+    Strong(Vec<Inline>),
+    Strikethrough(Vec<Inline>),
+    Link {
+        link_type: LinkType,
+        dest: String,
+        title: String,
+        content: Vec<Inline>,
+    },
+    Image {
+        link_type: LinkType,
+        dest: String,
+        title: String,
+        content: Vec<Inline>,
+    },
+}
+
+impl<'a> FromIterator<Event<'a>> for Document {
+    /// Builds a document from events.
+    ///
+    /// # Panics
+    ///
+    /// Panics if an event can't occur where it is, see
+    /// [`Document::try_from_events`].
+    fn from_iter<I: IntoIterator<Item = Event<'a>>>(events: I) -> Self {
+        match Document::try_from_events(events) {
+            Ok(document) => document,
+            Err(error) => panic!("{}", error),
+        }
+    }
+}
+
+impl Document {
+    /// Builds a document from events, or fails if one of them can't occur
+    /// where it is, like a table cell outside of a table or an end event that
+    /// doesn't end the open element, or if the events stop inside an element.
+    /// The events of a parser always succeed.
+    pub fn try_from_events<'a>(
+        events: impl IntoIterator<Item = Event<'a>>,
+    ) -> Result<Self, UnexpectedEvent> {
+        let mut builder = Builder {
+            events: events.into_iter().peekable(),
+            count: 0,
+        };
+        let blocks = builder.blocks()?;
+        if builder.next().is_some() {
+            return Err(builder.unexpected());
+        }
+        Ok(Document { blocks })
+    }
+
+    /// Returns the events of the document, borrowing its text.
+    pub fn events(&self) -> impl Iterator<Item = Event<'_>> {
+        let mut events = Vec::new();
+        push_blocks(&mut events, &self.blocks);
+        events.into_iter()
+    }
+}
+
+/// The error of [`Document::try_from_events`]. Contains the index of the event
+/// that can't occur where it is, which is the number of events when they stop
+/// inside an element.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct UnexpectedEvent(pub usize);
+
+impl fmt::Display for UnexpectedEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unexpected event at index {}", self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for UnexpectedEvent {}
+
+struct Builder<I: Iterator> {
+    events: Peekable<I>,
+    // the number of events taken so far
+    count: usize,
+}
+
+impl<'a, I: Iterator<Item = Event<'a>>> Builder<I> {
+    fn next(&mut self) -> Option<Event<'a>> {
+        let event = self.events.next()?;
+        self.count += 1;
+        Some(event)
+    }
+
+    /// Returns the error for the event taken last.
+    fn unexpected(&self) -> UnexpectedEvent {
+        UnexpectedEvent(self.count - 1)
+    }
+
+    /// Returns whether the next event ends an element, or fails if the events
+    /// stop inside the element.
+    fn at_end(&mut self) -> Result<bool, UnexpectedEvent> {
+        match self.events.peek() {
+            Some(Event::End(_)) => Ok(true),
+            Some(_) => Ok(false),
+            None => Err(UnexpectedEvent(self.count)),
+        }
+    }
+
+    /// Builds blocks up to the end of the enclosing element, which is left to
+    /// the caller.
+    fn blocks(&mut self) -> Result<Vec<Block>, UnexpectedEvent> {
+        let mut blocks = Vec::new();
+        while let Some(event) = self.events.peek() {
+            if let Event::End(_) = event {
+                break;
+            }
+            let event = self.next().unwrap();
+            let block = match event {
+                Event::Start(tag) if !is_inline_tag(&tag) => self.block(tag)?,
+                Event::Html(html) => {
+                    let mut html = html.into_string();
+                    while let Some(Event::Html(next)) = self.events.peek() {
+                        html.push_str(next);
+                        self.next();
+                    }
+                    match self.events.peek() {
+                        // the inline HTML that starts a tight list item
+                        Some(event) if is_inline_event(event) => self.plain(Inline::Html(html))?,
+                        _ => Block::Html(html),
+                    }
+                }
+                Event::Raw { format, content } => {
+                    let format = format.into_string();
+                    let content = content.into_string();
+                    match self.events.peek() {
+                        Some(event) if is_inline_event(event) => {
+                            self.plain(Inline::Raw { format, content })?
+                        }
+                        _ => Block::Raw { format, content },
+                    }
+                }
+                Event::Comment(text) => match self.events.peek() {
+                    Some(event) if is_inline_event(event) => {
+                        self.plain(Inline::Comment(text.into_string()))?
+                    }
+                    _ => Block::Comment(text.into_string()),
+                },
+                Event::LinkDefinition { label, dest, title } => Block::LinkDefinition {
+                    label: label.into_string(),
+                    dest: dest.into_string(),
+                    title: title.map(CowStr::into_string),
+                },
+                Event::Rule => Block::Rule,
+                event => {
+                    let first = self.inline(event)?;
+                    self.plain(first)?
+                }
+            };
+            blocks.push(block);
+        }
+        Ok(blocks)
+    }
+
+    /// Builds the inline content that isn't wrapped in a paragraph and starts
+    /// with `first`.
+    fn plain(&mut self, first: Inline) -> Result<Block, UnexpectedEvent> {
+        let mut content = vec![first];
+        while self.events.peek().map(is_inline_event) == Some(true) {
+            let event = self.next().unwrap();
+            content.push(self.inline(event)?);
+        }
+        Ok(Block::Plain(content))
+    }
+
+    /// Takes the end of the element whose content has been built, which has
+    /// to be of the same kind as its start.
+    fn end(&mut self, kind: Discriminant<Tag<'a>>) -> Result<(), UnexpectedEvent> {
+        match self.next() {
+            Some(Event::End(tag)) if mem::discriminant(&tag) == kind => Ok(()),
+            Some(_) => Err(self.unexpected()),
+            None => Err(UnexpectedEvent(self.count)),
+        }
+    }
+
+    /// Builds the block started by `tag`, consuming its end.
+    fn block(&mut self, tag: Tag<'a>) -> Result<Block, UnexpectedEvent> {
+        let kind = mem::discriminant(&tag);
+        let block = match tag {
+            Tag::Paragraph(id) => Block::Paragraph {
+                id: id.map(CowStr::into_string),
+                content: self.inlines(kind)?,
+            },
+            Tag::Heading(level, id, classes) => Block::Heading {
+                level,
+                id: id.map(CowStr::into_string),
+                classes: classes.into_iter().map(CowStr::into_string).collect(),
+                content: self.inlines(kind)?,
+            },
+            Tag::BlockQuote => {
+                let blocks = self.blocks()?;
+                self.end(kind)?;
+                Block::BlockQuote(blocks)
+            }
+            Tag::CodeBlock(info) => {
+                let text = self.text(kind)?;
+                let info = match info {
+                    CodeBlockKind::Indented => None,
+                    CodeBlockKind::Fenced(info) => Some(info.into_string()),
+                };
+                Block::CodeBlock { info, text }
+            }
+            Tag::List(start, numbering, marker, tight) => {
+                let mut items = Vec::new();
+                while !self.at_end()? {
+                    match self.next() {
+                        Some(Event::Start(Tag::Item(id))) => items.push(self.list_item(id)?),
+                        _ => return Err(self.unexpected()),
+                    }
+                }
+                self.end(kind)?;
+                Block::List {
+                    start,
+                    numbering,
+                    marker,
+                    tight,
+                    items,
+                }
+            }
+            Tag::FootnoteDefinition(label) => {
+                let blocks = self.blocks()?;
+                self.end(kind)?;
+                Block::FootnoteDefinition {
+                    label: label.into_string(),
+                    blocks,
+                }
+            }
+            Tag::Table(alignments) => {
+                let mut head = Vec::new();
+                let mut rows = Vec::new();
+                while !self.at_end()? {
+                    match self.next() {
+                        Some(Event::Start(Tag::TableHead)) => {
+                            head = self.children(Tag::TableHead, Tag::TableCell)?
+                        }
+                        Some(Event::Start(Tag::TableRow)) => {
+                            rows.push(self.children(Tag::TableRow, Tag::TableCell)?)
+                        }
+                        _ => return Err(self.unexpected()),
+                    }
+                }
+                self.end(kind)?;
+                Block::Table {
+                    alignments,
+                    head,
+                    rows,
+                }
+            }
+            Tag::LineBlock => Block::LineBlock(self.children(Tag::LineBlock, Tag::Line)?),
+            Tag::Custom(name, data) => Block::Custom {
+                name: name.into_string(),
+                data: data.into_string(),
+                text: self.text(kind)?,
+            },
+            _ => return Err(self.unexpected()),
+        };
+        Ok(block)
+    }
+
+    /// Returns the text of a code block or custom block, and takes its end.
+    fn text(&mut self, kind: Discriminant<Tag<'a>>) -> Result<String, UnexpectedEvent> {
+        let mut text = String::new();
+        while !self.at_end()? {
+            match self.next() {
+                Some(Event::Text(line)) => text.push_str(&line),
+                _ => return Err(self.unexpected()),
+            }
+        }
+        self.end(kind)?;
+        Ok(text)
+    }
+
+    fn list_item(&mut self, id: Option<CowStr<'a>>) -> Result<ListItem, UnexpectedEvent> {
+        let mut checked = None;
+        if let Some(&Event::TaskListMarker(is_checked)) = self.events.peek() {
+            checked = Some(is_checked);
+            self.next();
+        }
+        let blocks = self.blocks()?;
+        self.end(mem::discriminant(&Tag::Item(None)))?;
+        Ok(ListItem {
+            checked,
+            id: id.map(CowStr::into_string),
+            blocks,
+        })
+    }
+
+    /// Builds the inline content of each child element given by `tag`, like the
+    /// cells of a table row, up to the end of the enclosing `parent` element.
+    fn children(
+        &mut self,
+        parent: Tag<'a>,
+        tag: Tag<'a>,
+    ) -> Result<Vec<Vec<Inline>>, UnexpectedEvent> {
+        let kind = mem::discriminant(&tag);
+        let mut children = Vec::new();
+        while !self.at_end()? {
+            match self.next() {
+                Some(Event::Start(child)) if child == tag => children.push(self.inlines(kind)?),
+                _ => return Err(self.unexpected()),
+            }
+        }
+        self.end(mem::discriminant(&parent))?;
+        Ok(children)
+    }
+
+    /// Builds inline content up to the end of the enclosing element, and
+    /// takes the end.
+    fn inlines(&mut self, kind: Discriminant<Tag<'a>>) -> Result<Vec<Inline>, UnexpectedEvent> {
+        let mut inlines = Vec::new();
+        while !self.at_end()? {
+            let event = self.next().unwrap();
+            inlines.push(self.inline(event)?);
+        }
+        self.end(kind)?;
+        Ok(inlines)
+    }
+
+    fn inline(&mut self, event: Event<'a>) -> Result<Inline, UnexpectedEvent> {
+        let inline = match event {
+            Event::Text(text) => Inline::Text(text.into_string()),
+            Event::Code(text) => Inline::Code(text.into_string()),
+            Event::Html(html) => Inline::Html(html.into_string()),
+            Event::Raw { format, content } => Inline::Raw {
+                format: format.into_string(),
+                content: content.into_string(),
+            },
+            Event::Comment(text) => Inline::Comment(text.into_string()),
+            Event::Hashtag(tag) => Inline::Hashtag(tag.into_string()),
+            Event::Custom(name, data) => Inline::Custom {
+                name: name.into_string(),
+                data: data.into_string(),
+            },
+            Event::FootnoteReference(label) => Inline::FootnoteReference(label.into_string()),
+            Event::SoftBreak => Inline::SoftBreak,
+            Event::HardBreak => Inline::HardBreak,
+            Event::Start(tag) => self.inline_element(tag)?,
+            Event::End(_)
+            | Event::Rule
+            | Event::TaskListMarker(_)
+            | Event::LinkDefinition { .. } => return Err(self.unexpected()),
+        };
+        Ok(inline)
+    }
+
+    /// Builds the inline element started by `tag`, consuming its end.
+    fn inline_element(&mut self, tag: Tag<'a>) -> Result<Inline, UnexpectedEvent> {
+        let kind = mem::discriminant(&tag);
+        let inline = match tag {
+            Tag::Emphasis => Inline::Emphasis(self.inlines(kind)?),
+            Tag::Strong => Inline::Strong(self.inlines(kind)?),
+            Tag::Strikethrough => Inline::Strikethrough(self.inlines(kind)?),
+            Tag::Link(link_type, dest, title) => Inline::Link {
+                link_type,
+                dest: dest.into_string(),
+                title: title.into_string(),
+                content: self.inlines(kind)?,
+            },
+            Tag::Image(link_type, dest, title) => Inline::Image {
+                link_type,
+                dest: dest.into_string(),
+                title: title.into_string(),
+                content: self.inlines(kind)?,
+            },
+            _ => return Err(self.unexpected()),
+        };
+        Ok(inline)
+    }
+}
+
+fn is_inline_tag(tag: &Tag) -> bool {
+    matches!(
+        tag,
+        Tag::Emphasis | Tag::Strong | Tag::Strikethrough | Tag::Link(..) | Tag::Image(..)
+    )
+}
+
+fn is_inline_event(event: &Event) -> bool {
+    match event {
+        Event::Start(tag) => is_inline_tag(tag),
+        Event::End(_) | Event::Rule | Event::TaskListMarker(_) | Event::LinkDefinition { .. } => {
+            false
+        }
+        _ => true,
+    }
+}
+
+fn borrowed(text: &Option<String>) -> Option<CowStr<'_>> {
+    text.as_deref().map(CowStr::Borrowed)
+}
+
+fn push_element<'a>(
+    events: &mut Vec<Event<'a>>,
+    tag: Tag<'a>,
+    push_content: impl FnOnce(&mut Vec<Event<'a>>),
+) {
+    events.push(Event::Start(tag.clone()));
+    push_content(events);
+    events.push(Event::End(tag));
+}
+
+fn push_blocks<'a>(events: &mut Vec<Event<'a>>, blocks: &'a [Block]) {
+    for block in blocks {
+        match block {
+            Block::Plain(content) => push_inlines(events, content),
+            Block::Paragraph { id, content } => {
+                push_element(events, Tag::Paragraph(borrowed(id)), |events| {
+                    push_inlines(events, content)
+                })
+            }
+            Block::Heading {
+                level,
+                id,
+                classes,
+                content,
+            } => {
+                let classes = classes.iter().map(|class| class.as_str().into()).collect();
+                let tag = Tag::Heading(*level, borrowed(id), classes);
+                push_element(events, tag, |events| push_inlines(events, content))
+            }
+            Block::BlockQuote(blocks) => push_element(events, Tag::BlockQuote, |events| {
+                push_blocks(events, blocks)
+            }),
+            Block::CodeBlock { info, text } => {
+                let kind = match info {
+                    None => CodeBlockKind::Indented,
+                    Some(info) => CodeBlockKind::Fenced(info.as_str().into()),
+                };
+                push_element(events, Tag::CodeBlock(kind), |events| {
+                    if !text.is_empty() {
+                        events.push(Event::Text(text.as_str().into()));
+                    }
+                })
+            }
+            Block::List {
+                start,
+                numbering,
+                marker,
+                tight,
+                items,
+            } => {
+                let tag = Tag::List(*start, *numbering, *marker, *tight);
+                push_element(events, tag, |events| {
+                    for item in items {
+                        push_element(events, Tag::Item(borrowed(&item.id)), |events| {
+                            if let Some(checked) = item.checked {
+                                events.push(Event::TaskListMarker(checked));
+                            }
+                            push_blocks(events, &item.blocks)
+                        })
+                    }
+                })
+            }
+            Block::FootnoteDefinition { label, blocks } => {
+                let tag = Tag::FootnoteDefinition(label.as_str().into());
+                push_element(events, tag, |events| push_blocks(events, blocks))
+            }
+            Block::Table {
+                alignments,
+                head,
+                rows,
+            } => push_element(events, Tag::Table(alignments.clone()), |events| {
+                push_element(events, Tag::TableHead, |events| {
+                    push_children(events, Tag::TableCell, head)
+                });
+                for row in rows {
+                    push_element(events, Tag::TableRow, |events| {
+                        push_children(events, Tag::TableCell, row)
+                    });
+                }
+            }),
+            Block::LineBlock(lines) => push_element(events, Tag::LineBlock, |events| {
+                push_children(events, Tag::Line, lines)
+            }),
+            Block::Custom { name, data, text } => {
+                let tag = Tag::Custom(name.as_str().into(), data.as_str().into());
+                push_element(events, tag, |events| {
+                    if !text.is_empty() {
+                        events.push(Event::Text(text.as_str().into()));
+                    }
+                })
+            }
+            Block::Html(html) => events.push(Event::Html(html.as_str().into())),
+            Block::Raw { format, content } => events.push(Event::Raw {
+                format: format.as_str().into(),
+                content: content.as_str().into(),
+            }),
+            Block::Comment(text) => events.push(Event::Comment(text.as_str().into())),
+            Block::LinkDefinition { label, dest, title } => events.push(Event::LinkDefinition {
+                label: label.as_str().into(),
+                dest: dest.as_str().into(),
+                title: title.as_ref().map(|title| title.as_str().into()),
+            }),
+            Block::Rule => events.push(Event::Rule),
+        }
+    }
+}
+
+fn push_children<'a>(events: &mut Vec<Event<'a>>, tag: Tag<'a>, children: &'a [Vec<Inline>]) {
+    for child in children {
+        push_element(events, tag.clone(), |events| push_inlines(events, child));
+    }
+}
+
+fn push_inlines<'a>(events: &mut Vec<Event<'a>>, inlines: &'a [Inline]) {
+    for inline in inlines {
+        let (tag, content) = match inline {
+            Inline::Text(text) => {
+                events.push(Event::Text(text.as_str().into()));
+                continue;
+            }
+            Inline::Code(text) => {
+                events.push(Event::Code(text.as_str().into()));
+                continue;
+            }
+            Inline::Html(html) => {
+                events.push(Event::Html(html.as_str().into()));
+                continue;
+            }
+            Inline::Raw { format, content } => {
+                events.push(Event::Raw {
+                    format: format.as_str().into(),
+                    content: content.as_str().into(),
+                });
+                continue;
+            }
+            Inline::Comment(text) => {
+                events.push(Event::Comment(text.as_str().into()));
+                continue;
+            }
+            Inline::Hashtag(tag) => {
+                events.push(Event::Hashtag(tag.as_str().into()));
+                continue;
+            }
+            Inline::Custom { name, data } => {
+                events.push(Event::Custom(name.as_str().into(), data.as_str().into()));
+                continue;
+            }
+            Inline::FootnoteReference(label) => {
+                events.push(Event::FootnoteReference(label.as_str().into()));
+                continue;
+            }
+            Inline::SoftBreak => {
+                events.push(Event::SoftBreak);
+                continue;
+            }
+            Inline::HardBreak => {
+                events.push(Event::HardBreak);
+                continue;
+            }
+            Inline::Emphasis(content) => (Tag::Emphasis, content),
+            Inline::Strong(content) => (Tag::Strong, content),
+            Inline::Strikethrough(content) => (Tag::Strikethrough, content),
+            Inline::Link {
+                link_type,
+                dest,
+                title,
+                content,
+            } => (
+                Tag::Link(*link_type, dest.as_str().into(), title.as_str().into()),
+                content,
+            ),
+            Inline::Image {
+                link_type,
+                dest,
+                title,
+                content,
+            } => (
+                Tag::Image(*link_type, dest.as_str().into(), title.as_str().into()),
+                content,
+            ),
+        };
+        push_element(events, tag, |events| push_inlines(events, content));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{html, Options, Parser};
+
+    fn render<'a>(events: impl Iterator<Item = Event<'a>>) -> String {
+        let mut s = String::new();
+        html::push_html(&mut s, events);
+        s
+    }
+
+    #[test]
+    fn renders_like_events() {
+        let texts = [
+            "# *Title* {#id .c}\n\npara **strong** ~~del~~ `code` <b>x</b>\\\nnext\n",
+            "> quote\n\n- [x] tight\n  - nested\n- item\n\n1. loose\n\n   para\n",
+            "```rust\nfn main() {}\n```\n\n    indented\n\n<div>\nhtml\n</div>\n",
+            "| a | *b* |\n|:--|--:|\n| 1 | [l](/u \"t\") |\n\ntext[^n] ![i](/i)\n\n[^n]: note\n\n---\n",
+            "| line\n|   block\n\n%% comment %%\n\n`x`{=html} #tag ^id\n",
+        ];
+        for text in texts.iter() {
+            let options = Options::all() - Options::ENABLE_TOC;
+            let document: Document = Parser::new_ext(text, options).collect();
+            assert_eq!(
+                render(Parser::new_ext(text, options)),
+                render(document.events())
+            );
+        }
+    }
+
+    #[test]
+    fn structure() {
+        let document: Document = Parser::new("- a *b*\n\n  c\n").collect();
+        let item = ListItem {
+            checked: None,
+            id: None,
+            blocks: vec![
+                Block::Paragraph {
+                    id: None,
+                    content: vec![
+                        Inline::Text("a ".to_owned()),
+                        Inline::Emphasis(vec![Inline::Text("b".to_owned())]),
+                    ],
+                },
+                Block::Paragraph {
+                    id: None,
+                    content: vec![Inline::Text("c".to_owned())],
+                },
+            ],
+        };
+        let expected = Document {
+            blocks: vec![Block::List {
+                start: None,
+                numbering: ListNumbering::Decimal,
+                marker: ListMarker::Dash,
+                tight: false,
+                items: vec![item],
+            }],
+        };
+        assert_eq!(expected, document);
+    }
+
+    #[test]
+    fn tight_item_starting_with_html() {
+        let text = "- <b>a</b> b\n- %% c %% d\n";
+        let options = Options::ENABLE_COMMENTS | Options::ENABLE_COMMENT_EVENTS;
+        let document: Document = Parser::new_ext(text, options).collect();
+        let items = match &document.blocks[0] {
+            Block::List { items, .. } => items,
+            block => panic!("{:?}", block),
+        };
+        assert_eq!(
+            vec![Block::Plain(vec![
+                Inline::Html("<b>".to_owned()),
+                Inline::Text("a".to_owned()),
+                Inline::Html("</b>".to_owned()),
+                Inline::Text(" b".to_owned()),
+            ])],
+            items[0].blocks
+        );
+        assert_eq!(
+            vec![Block::Plain(vec![
+                Inline::Comment(" c ".to_owned()),
+                Inline::Text(" d".to_owned()),
+            ])],
+            items[1].blocks
+        );
+    }
+
+    #[test]
+    fn unexpected_events() {
+        let cell = Tag::TableCell;
+        let events = vec![
+            Event::Start(Tag::Paragraph(None)),
+            Event::Text("a".into()),
+            Event::End(Tag::Paragraph(None)),
+            Event::Start(cell.clone()),
+            Event::End(cell),
+        ];
+        assert_eq!(Err(UnexpectedEvent(3)), Document::try_from_events(events));
+        let events = vec![Event::Text("a".into()), Event::End(Tag::Emphasis)];
+        assert_eq!(Err(UnexpectedEvent(1)), Document::try_from_events(events));
+        let events = vec![
+            Event::Start(Tag::Emphasis),
+            Event::Rule,
+            Event::End(Tag::Emphasis),
+        ];
+        assert_eq!(Err(UnexpectedEvent(1)), Document::try_from_events(events));
+    }
+
+    #[test]
+    fn mismatched_end() {
+        let events = vec![
+            Event::Start(Tag::Paragraph(None)),
+            Event::Start(Tag::Emphasis),
+            Event::Text("a".into()),
+            Event::End(Tag::Strong),
+            Event::End(Tag::Paragraph(None)),
+        ];
+        assert_eq!(Err(UnexpectedEvent(3)), Document::try_from_events(events));
+        let events = vec![
+            Event::Start(Tag::BlockQuote),
+            Event::Start(Tag::Paragraph(None)),
+            Event::Text("a".into()),
+            Event::End(Tag::Paragraph(None)),
+            Event::End(Tag::Item(None)),
+        ];
+        assert_eq!(Err(UnexpectedEvent(4)), Document::try_from_events(events));
+    }
+
+    #[test]
+    fn premature_end() {
+        let events = vec![
+            Event::Start(Tag::Paragraph(None)),
+            Event::Start(Tag::Emphasis),
+            Event::Text("a".into()),
+        ];
+        assert_eq!(Err(UnexpectedEvent(3)), Document::try_from_events(events));
+        let events = vec![Event::Start(Tag::List(
+            None,
+            ListNumbering::Decimal,
+            ListMarker::Dash,
+            true,
+        ))];
+        assert_eq!(Err(UnexpectedEvent(1)), Document::try_from_events(events));
+        let events = vec![
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Indented)),
+            Event::Text("a\n".into()),
+        ];
+        assert_eq!(Err(UnexpectedEvent(2)), Document::try_from_events(events));
+    }
+}
@@ -0,0 +1,311 @@
+//! Lossless concrete syntax tree.
+//!
+//! The events of a [`Parser`] leave out much of the source: list markers, block
+//! quote prefixes, code fences, escaping backslashes and the like. A
+//! [`SyntaxTree`], returned by [`Parser::into_syntax_tree`], keeps these as
+//! [`SyntaxKind::Trivia`] nodes, so that every byte of the source belongs to
+//! exactly one leaf of the tree. This makes it suitable for formatters and
+//! editing tools, which need to change parts of a document and reproduce the
+//! rest exactly.
+//!
+//! The children of a node cover its range without gaps or overlap, in source
+//! order. Leaves are either events that have no children, like
+//! [`Event::Text`], or trivia. The range of a text leaf is its source spelling,
+//! so an entity like `&amp;` keeps its original form. Trivia is split into runs
+//! of whitespace, line endings and markers, see [`TriviaKind`].
+//!
+//! [`Parser`]: crate::Parser
+//! [`Parser::into_syntax_tree`]: crate::Parser::into_syntax_tree
+//!
+//! # Example
+//! ```rust
+//! use pulldown_cmark::cst::{SyntaxKind, TriviaKind};
+//! use pulldown_cmark::{Parser, Tag};
+//!
+//! let markdown_input = "> *a* &amp; b\n";
+//! let tree = Parser::new(markdown_input).into_syntax_tree();
+//!
+//! assert_eq!(markdown_input, tree.to_string());
+//! let quote = &tree.root().children[0];
+//! assert_eq!(SyntaxKind::Element(Tag::BlockQuote), quote.kind);
+//! assert_eq!(SyntaxKind::Trivia(TriviaKind::Marker), quote.children[0].kind);
+//! assert_eq!(">", tree.text(&quote.children[0]));
+//! assert_eq!(SyntaxKind::Trivia(TriviaKind::Whitespace), quote.children[1].kind);
+//! ```
+
+use core::fmt;
+use core::ops::Range;
+
+use crate::prelude::*;
+use crate::{Event, OffsetIter, Tag};
+
+/// What a node of a [`SyntaxTree`] stands for.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SyntaxKind<'a> {
+    /// The whole document.
+    Document,
+    /// An element with children, given by a matching pair of
+    /// [`Event::Start`] and [`Event::End`].
+    Element(Tag<'a>),
+    /// Any other event, like [`Event::Text`] or [`Event::SoftBreak`].
+    /// Never has children.
+    Leaf(Event<'a>),
+    /// Source text that is only syntax and isn't part of any event, like list
+    /// markers, `>` prefixes, code fences, backslashes before escaped characters
+    /// and whitespace. Never has children.
+    Trivia(TriviaKind),
+}
+
+/// What kind of source text a [`SyntaxKind::Trivia`] node is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriviaKind {
+    /// Spaces and tabs.
+    Whitespace,
+    /// One or more line endings.
+    LineEnding,
+    /// Any other syntax, like list markers, `>` prefixes, code fences and
+    /// backslashes before escaped characters.
+    Marker,
+}
+
+impl TriviaKind {
+    fn of(byte: u8) -> Self {
+        match byte {
+            b' ' | b'\t' => TriviaKind::Whitespace,
+            b'\n' | b'\r' => TriviaKind::LineEnding,
+            _ => TriviaKind::Marker,
+        }
+    }
+}
+
+/// A node of a [`SyntaxTree`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyntaxNode<'a> {
+    /// What the node stands for.
+    pub kind: SyntaxKind<'a>,
+    /// The byte range of the node in the source.
+    pub range: Range<usize>,
+    /// The child nodes, which together cover exactly the range of this node.
+    pub children: Vec<SyntaxNode<'a>>,
+}
+
+impl<'a> SyntaxNode<'a> {
+    fn new(kind: SyntaxKind<'a>, range: Range<usize>) -> Self {
+        SyntaxNode {
+            kind,
+            range,
+            children: Vec::new(),
+        }
+    }
+
+    /// Returns an iterator over the leaves below this node in source order,
+    /// including trivia. The node itself is returned if it is a leaf.
+    pub fn leaves(&self) -> impl Iterator<Item = &SyntaxNode<'a>> {
+        let mut stack = vec![self];
+        core::iter::from_fn(move || loop {
+            let node = stack.pop()?;
+            if node.children.is_empty() {
+                return Some(node);
+            }
+            stack.extend(node.children.iter().rev());
+        })
+    }
+}
+
+/// A concrete syntax tree that reproduces its source exactly.
+///
+/// Displaying the tree writes the source it was parsed from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SyntaxTree<'a> {
+    source: &'a str,
+    root: SyntaxNode<'a>,
+}
+
+impl<'a> SyntaxTree<'a> {
+    /// Returns the root node, which is a [`SyntaxKind::Document`] covering the
+    /// whole source.
+    pub fn root(&self) -> &SyntaxNode<'a> {
+        &self.root
+    }
+
+    /// Returns the source the tree was parsed from.
+    pub fn source(&self) -> &'a str {
+        self.source
+    }
+
+    /// Returns the source text of a node of this tree.
+    pub fn text(&self, node: &SyntaxNode<'a>) -> &'a str {
+        &self.source[node.range.clone()]
+    }
+}
+
+impl<'a> fmt::Display for SyntaxTree<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for leaf in self.root.leaves() {
+            f.write_str(self.text(leaf))?;
+        }
+        Ok(())
+    }
+}
+
+/// A node that is being built, together with how far its children reach.
+struct OpenNode<'a> {
+    node: SyntaxNode<'a>,
+    cursor: usize,
+}
+
+impl<'a> OpenNode<'a> {
+    /// Adds a child, filling the gap before it with trivia. The child's range
+    /// is clamped to the unfilled part of this node, so that the children never
+    /// overlap or stick out.
+    fn push(&mut self, source: &str, mut child: SyntaxNode<'a>) {
+        let end = self.node.range.end;
+        debug_assert!(
+            self.cursor <= child.range.start
+                && child.range.start <= child.range.end
+                && child.range.end <= end,
+            "{:?} doesn't fit in {:?} after {}",
+            child.range,
+            self.node.range,
+            self.cursor
+        );
+        let start = child.range.start.clamp(self.cursor, end);
+        child.range = start..child.range.end.clamp(start, end);
+        self.fill(source, start);
+        self.cursor = child.range.end;
+        self.node.children.push(child);
+    }
+
+    /// Fills the gap up to `end` with trivia, one node per run of the same
+    /// kind.
+    fn fill(&mut self, source: &str, end: usize) {
+        let bytes = source.as_bytes();
+        while self.cursor < end {
+            let kind = TriviaKind::of(bytes[self.cursor]);
+            let len = bytes[self.cursor..end]
+                .iter()
+                .position(|&byte| TriviaKind::of(byte) != kind)
+                .unwrap_or(end - self.cursor);
+            let range = self.cursor..self.cursor + len;
+            (self.node.children).push(SyntaxNode::new(SyntaxKind::Trivia(kind), range));
+            self.cursor += len;
+        }
+    }
+
+    fn close(mut self, source: &str) -> SyntaxNode<'a> {
+        self.fill(source, self.node.range.end);
+        self.node
+    }
+}
+
+/// Builds the syntax tree of `source` from the events of a parser over it.
+/// Events from included documents are left out, so the directives that
+/// include them become trivia.
+pub(crate) fn build<'a>(source: &'a str, mut events: OffsetIter<'a, '_>) -> SyntaxTree<'a> {
+    let root = SyntaxNode::new(SyntaxKind::Document, 0..source.len());
+    let mut stack = vec![OpenNode {
+        node: root,
+        cursor: 0,
+    }];
+    while let Some((event, range)) = events.next() {
+        if events.origin().is_some() {
+            continue;
+        }
+        match event {
+            Event::Start(tag) => {
+                // Clamp the range now, so that the children are clamped to
+                // what the element ends up covering.
+                let parent = stack.last().unwrap();
+                let end = parent.node.range.end;
+                let start = range.start.clamp(parent.cursor, end);
+                let range = start..range.end.clamp(start, end);
+                stack.push(OpenNode {
+                    node: SyntaxNode::new(SyntaxKind::Element(tag), range),
+                    cursor: start,
+                });
+            }
+            Event::End(_) => {
+                let node = stack.pop().unwrap().close(source);
+                stack.last_mut().unwrap().push(source, node);
+            }
+            event => {
+                let leaf = SyntaxNode::new(SyntaxKind::Leaf(event), range);
+                stack.last_mut().unwrap().push(source, leaf);
+            }
+        }
+    }
+    let root = stack.pop().unwrap().close(source);
+    SyntaxTree { source, root }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Options, Parser};
+
+    fn assert_lossless(text: &str, options: Options) {
+        let tree = Parser::new_ext(text, options).into_syntax_tree();
+        assert_eq!(text, tree.to_string());
+        let mut nodes = vec![tree.root()];
+        while let Some(node) = nodes.pop() {
+            if let Some(first) = node.children.first() {
+                assert_eq!(node.range.start, first.range.start);
+                assert_eq!(node.range.end, node.children.last().unwrap().range.end);
+                for pair in node.children.windows(2) {
+                    assert_eq!(pair[0].range.end, pair[1].range.start);
+                }
+            }
+            nodes.extend(&node.children);
+        }
+    }
+
+    #[test]
+    fn reproduces_source() {
+        let texts = [
+            "",
+            "plain",
+            "# Title #\n\nSetext\n===\n\n* a\n* b\n\n   1. c\n\n      d\n",
+            "> > nested\n> lazy\ncontinuation\n\n- [ ] task\n",
+            "````rust\ncode\n````\n\n    indented\n\n~~~\nunclosed",
+            "\\*not emphasis\\* &amp; &#35; `code` <b>html</b>\n",
+            "[link](/url \"title\") ![img][ref] <https://x.com>\n\n[ref]: /i\n",
+            "| a | b |\n|---|:-:|\n| 1 | 2 |\n\ntext[^1]\n\n[^1]: note\n",
+            "trailing  \nspaces   \n\n\n\n<div>\nblock\n</div>\n  \n",
+            "- a\n\n  - b\n\n    > c\n- \n\r\nline\r\n",
+        ];
+        for text in texts.iter() {
+            assert_lossless(text, Options::all() - Options::ENABLE_SMART_PUNCTUATION);
+            assert_lossless(text, Options::empty());
+        }
+    }
+
+    #[test]
+    fn trivia() {
+        let text = "- *a* \\*\n\n```\nb\n```\n";
+        let tree = Parser::new(text).into_syntax_tree();
+        let trivia = tree
+            .root()
+            .leaves()
+            .filter_map(|leaf| match leaf.kind {
+                SyntaxKind::Trivia(kind) => Some((kind, tree.text(leaf))),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        use TriviaKind::*;
+        assert_eq!(
+            vec![
+                (Marker, "-"),
+                (Whitespace, " "),
+                (Marker, "*"),
+                (Marker, "*"),
+                (Marker, "\\"),
+                (LineEnding, "\n\n"),
+                (Marker, "```"),
+                (LineEnding, "\n"),
+                (Marker, "```"),
+                (LineEnding, "\n"),
+            ],
+            trivia
+        );
+    }
+}
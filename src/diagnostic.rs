@@ -0,0 +1,151 @@
+//! Diagnostics about markup that didn't parse as probably intended.
+
+use core::fmt;
+use core::ops::Range;
+
+/// Something suspicious the parser found in a document, reported with
+/// [`Options::ENABLE_DIAGNOSTICS`](crate::Options::ENABLE_DIAGNOSTICS).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Diagnostic {
+    /// What was found.
+    pub code: DiagnosticCode,
+    /// The byte range of the source it was found in.
+    pub range: Range<usize>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}..{}: {} [{}]",
+            self.range.start,
+            self.range.end,
+            self.code.message(),
+            self.code.as_str()
+        )
+    }
+}
+
+/// The kinds of [`Diagnostic`]s.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DiagnosticCode {
+    /// Emphasis delimiters that can only open emphasis, which are left as
+    /// text because nothing closes them.
+    UnclosedEmphasis,
+    /// A reference link or image whose label has no reference definition, and
+    /// which is left as text. This includes shortcut references, so text in
+    /// brackets like `[sic]` is reported as well.
+    UnresolvedLink,
+    /// A reference definition that no link or image refers to.
+    UnusedDefinition,
+    /// A reference definition with the same label as an earlier one, which is
+    /// ignored.
+    DuplicateDefinition,
+    /// A footnote reference to a label that has no footnote definition.
+    UndefinedFootnote,
+    /// A table row with more or fewer cells than the header row. Missing cells
+    /// are added as empty cells, and excess cells are dropped.
+    TableCellCount,
+    /// An HTML block of a type that ends with a closing pattern like `-->` or
+    /// `</pre>`, which ends with its container or the document instead.
+    UnclosedHtmlBlock,
+}
+
+impl DiagnosticCode {
+    /// Returns the stable name of the code, like `unclosed-emphasis`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DiagnosticCode::UnclosedEmphasis => "unclosed-emphasis",
+            DiagnosticCode::UnresolvedLink => "unresolved-link",
+            DiagnosticCode::UnusedDefinition => "unused-definition",
+            DiagnosticCode::DuplicateDefinition => "duplicate-definition",
+            DiagnosticCode::UndefinedFootnote => "undefined-footnote",
+            DiagnosticCode::TableCellCount => "table-cell-count",
+            DiagnosticCode::UnclosedHtmlBlock => "unclosed-html-block",
+        }
+    }
+
+    /// Returns a short description of what was found.
+    pub fn message(self) -> &'static str {
+        match self {
+            DiagnosticCode::UnclosedEmphasis => "emphasis is never closed",
+            DiagnosticCode::UnresolvedLink => "link reference has no definition",
+            DiagnosticCode::UnusedDefinition => "reference definition is never used",
+            DiagnosticCode::DuplicateDefinition => "reference definition is already defined",
+            DiagnosticCode::UndefinedFootnote => "footnote has no definition",
+            DiagnosticCode::TableCellCount => "row has a different number of cells than the header",
+            DiagnosticCode::UnclosedHtmlBlock => "HTML block is never closed",
+        }
+    }
+}
+
+impl fmt::Display for DiagnosticCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::*;
+    use crate::{Options, Parser};
+
+    fn diagnostics(text: &str) -> Vec<(DiagnosticCode, &str)> {
+        let mut parser = Parser::new_ext(
+            text,
+            Options::ENABLE_DIAGNOSTICS | Options::ENABLE_TABLES | Options::ENABLE_FOOTNOTES,
+        );
+        parser.by_ref().for_each(drop);
+        (parser.diagnostics().iter())
+            .map(|diagnostic| (diagnostic.code, &text[diagnostic.range.clone()]))
+            .collect()
+    }
+
+    #[test]
+    fn inline_diagnostics() {
+        assert_eq!(
+            vec![
+                (DiagnosticCode::UnclosedEmphasis, "*"),
+                (DiagnosticCode::UnresolvedLink, "[b][c]"),
+                (DiagnosticCode::UndefinedFootnote, "[^2]"),
+                (DiagnosticCode::UnclosedEmphasis, "_"),
+            ],
+            diagnostics("**a* [b][c] *d* [^1] [^2] _e\n\n[^1]: f\n")
+        );
+    }
+
+    #[test]
+    fn definitions() {
+        assert_eq!(
+            vec![
+                (DiagnosticCode::UnusedDefinition, "[b]: /b"),
+                (DiagnosticCode::DuplicateDefinition, "[A]: /c"),
+            ],
+            diagnostics("[a]\n\n[a]: /a\n[b]: /b\n[A]: /c\n")
+        );
+    }
+
+    #[test]
+    fn block_diagnostics() {
+        assert_eq!(
+            vec![
+                (DiagnosticCode::TableCellCount, "| 1 |"),
+                (DiagnosticCode::TableCellCount, "| 1 | 2 | 3 |"),
+                (DiagnosticCode::UnclosedHtmlBlock, "<!-- a\n\nb\n"),
+            ],
+            diagnostics("| a | b |\n|---|---|\n| 1 |\n| 1 | 2 | 3 |\n| 1 | 2 |\n\n<!-- a\n\nb\n")
+        );
+        assert_eq!(
+            vec![(DiagnosticCode::UnclosedHtmlBlock, "<pre>\n> a\n")],
+            diagnostics("> <pre>\n> a\n\nb\n")
+        );
+    }
+
+    #[test]
+    fn disabled() {
+        let mut parser = Parser::new("*a [b] [^c]\n\n[d]: /d\n[d]: /e\n");
+        parser.by_ref().for_each(drop);
+        assert!(parser.diagnostics().is_empty());
+    }
+}
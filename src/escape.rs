@@ -21,9 +21,11 @@
 //! Utility functions for HTML escaping. Only useful when building your own
 //! HTML renderer.
 
-use std::fmt::{Arguments, Write as FmtWrite};
-use std::io::{self, ErrorKind, Write};
-use std::str::from_utf8;
+use core::convert::Infallible;
+use core::fmt::{self, Arguments, Write as FmtWrite};
+use core::str::from_utf8;
+
+use crate::prelude::*;
 
 #[rustfmt::skip]
 static HREF_SAFE: [u8; 128] = [
@@ -45,42 +47,81 @@ static SLASH_ESCAPE: &str = "&#x27;";
 /// for all types implementing `Write` and types of the for `&mut W` where
 /// `W: StrWrite`. Since we need the latter a lot, we choose to wrap
 /// `Write` types.
+#[cfg(feature = "std")]
 pub struct WriteWrapper<W>(pub W);
 
+/// Wraps a `core::fmt::Write` sink, like a `fmt::Formatter`, for the same
+/// reason as `WriteWrapper` and without requiring `std`.
+pub struct FmtWriter<W>(pub W);
+
 /// Trait that allows writing string slices. This is basically an extension
-/// of `std::io::Write` in order to include `String`.
+/// of `std::io::Write` in order to include `String`, with the error type of
+/// the underlying sink.
+///
+/// Before the `std` feature was added, the error type was always
+/// `std::io::Error`. Renderers that are generic over the writer and return
+/// `std::io::Result` can require `W: StrWrite<Error = std::io::Error>`, which
+/// `WriteWrapper` satisfies. Writing to a `String` can't fail, so its error
+/// type is `Infallible`.
 pub trait StrWrite {
-    fn write_str(&mut self, s: &str) -> io::Result<()>;
+    type Error;
+
+    fn write_str(&mut self, s: &str) -> Result<(), Self::Error>;
 
-    fn write_fmt(&mut self, args: Arguments) -> io::Result<()>;
+    fn write_fmt(&mut self, args: Arguments) -> Result<(), Self::Error>;
 }
 
+#[cfg(feature = "std")]
 impl<W> StrWrite for WriteWrapper<W>
 where
-    W: Write,
+    W: std::io::Write,
 {
+    type Error = std::io::Error;
+
     #[inline]
-    fn write_str(&mut self, s: &str) -> io::Result<()> {
+    fn write_str(&mut self, s: &str) -> std::io::Result<()> {
         self.0.write_all(s.as_bytes())
     }
 
     #[inline]
-    fn write_fmt(&mut self, args: Arguments) -> io::Result<()> {
+    fn write_fmt(&mut self, args: Arguments) -> std::io::Result<()> {
         self.0.write_fmt(args)
     }
 }
 
-impl<'w> StrWrite for String {
+impl<W> StrWrite for FmtWriter<W>
+where
+    W: FmtWrite,
+{
+    type Error = fmt::Error;
+
+    #[inline]
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write_str(s)
+    }
+
     #[inline]
-    fn write_str(&mut self, s: &str) -> io::Result<()> {
+    fn write_fmt(&mut self, args: Arguments) -> fmt::Result {
+        self.0.write_fmt(args)
+    }
+}
+
+impl StrWrite for String {
+    type Error = Infallible;
+
+    #[inline]
+    fn write_str(&mut self, s: &str) -> Result<(), Infallible> {
         self.push_str(s);
         Ok(())
     }
 
     #[inline]
-    fn write_fmt(&mut self, args: Arguments) -> io::Result<()> {
-        // FIXME: translate fmt error to io error?
-        FmtWrite::write_fmt(self, args).map_err(|_| ErrorKind::Other.into())
+    fn write_fmt(&mut self, args: Arguments) -> Result<(), Infallible> {
+        // Formatting into a `String` only fails if a `Display` implementation
+        // returns an error, which the standard library treats as a bug.
+        FmtWrite::write_fmt(self, args)
+            .expect("a formatting trait implementation returned an error");
+        Ok(())
     }
 }
 
@@ -88,19 +129,21 @@ impl<W> StrWrite for &'_ mut W
 where
     W: StrWrite,
 {
+    type Error = W::Error;
+
     #[inline]
-    fn write_str(&mut self, s: &str) -> io::Result<()> {
+    fn write_str(&mut self, s: &str) -> Result<(), W::Error> {
         (**self).write_str(s)
     }
 
     #[inline]
-    fn write_fmt(&mut self, args: Arguments) -> io::Result<()> {
+    fn write_fmt(&mut self, args: Arguments) -> Result<(), W::Error> {
         (**self).write_fmt(args)
     }
 }
 
 /// Writes an href to the buffer, escaping href unsafe bytes.
-pub fn escape_href<W>(mut w: W, s: &str) -> io::Result<()>
+pub fn escape_href<W>(mut w: W, s: &str) -> Result<(), W::Error>
 where
     W: StrWrite,
 {
@@ -152,7 +195,7 @@ static HTML_ESCAPES: [&str; 5] = ["", "&quot;", "&amp;", "&lt;", "&gt;"];
 
 /// Writes the given string to the Write sink, replacing special HTML bytes
 /// (<, >, &, ") by escape sequences.
-pub fn escape_html<W: StrWrite>(w: W, s: &str) -> io::Result<()> {
+pub fn escape_html<W: StrWrite>(w: W, s: &str) -> Result<(), W::Error> {
     #[cfg(all(target_arch = "x86_64", feature = "simd"))]
     {
         simd::escape_html(w, s)
@@ -163,7 +206,7 @@ pub fn escape_html<W: StrWrite>(w: W, s: &str) -> io::Result<()> {
     }
 }
 
-fn escape_html_scalar<W: StrWrite>(mut w: W, s: &str) -> io::Result<()> {
+fn escape_html_scalar<W: StrWrite>(mut w: W, s: &str) -> Result<(), W::Error> {
     let bytes = s.as_bytes();
     let mut mark = 0;
     let mut i = 0;
@@ -191,36 +234,36 @@ fn escape_html_scalar<W: StrWrite>(mut w: W, s: &str) -> io::Result<()> {
 #[cfg(all(target_arch = "x86_64", feature = "simd"))]
 mod simd {
     use super::StrWrite;
-    use std::arch::x86_64::*;
-    use std::io;
-    use std::mem::size_of;
+    use core::arch::x86_64::*;
+    use core::mem::size_of;
 
     const VECTOR_SIZE: usize = size_of::<__m128i>();
+    const AVX2_VECTOR_SIZE: usize = size_of::<__m256i>();
 
-    pub(super) fn escape_html<W: StrWrite>(mut w: W, s: &str) -> io::Result<()> {
+    pub(super) fn escape_html<W: StrWrite>(mut w: W, s: &str) -> Result<(), W::Error> {
         // The SIMD accelerated code uses the PSHUFB instruction, which is part
-        // of the SSSE3 instruction set. Further, we can only use this code if
-        // the buffer is at least one VECTOR_SIZE in length to prevent reading
-        // out of bounds. If either of these conditions is not met, we fall back
-        // to scalar code.
-        if is_x86_feature_detected!("ssse3") && s.len() >= VECTOR_SIZE {
-            let bytes = s.as_bytes();
-            let mut mark = 0;
-
-            unsafe {
-                foreach_special_simd(bytes, 0, |i| {
-                    let escape_ix = *bytes.get_unchecked(i) as usize;
-                    let replacement =
-                        super::HTML_ESCAPES[super::HTML_ESCAPE_TABLE[escape_ix] as usize];
-                    w.write_str(&s.get_unchecked(mark..i))?;
-                    mark = i + 1; // all escaped characters are ASCII
-                    w.write_str(replacement)
-                })?;
-                w.write_str(&s.get_unchecked(mark..))
-            }
+        // of the SSSE3 instruction set, or its 256-bit AVX2 version. Further, we
+        // can only use this code if the buffer is at least one vector in length
+        // to prevent reading out of bounds. If neither instruction set can be
+        // used, we fall back to scalar code.
+        let width = crate::simd::width();
+        let bytes = s.as_bytes();
+        let mut mark = 0;
+        let callback = |i| unsafe {
+            let escape_ix = *bytes.get_unchecked(i) as usize;
+            let replacement = super::HTML_ESCAPES[super::HTML_ESCAPE_TABLE[escape_ix] as usize];
+            w.write_str(s.get_unchecked(mark..i))?;
+            mark = i + 1; // all escaped characters are ASCII
+            w.write_str(replacement)
+        };
+        if width >= 256 && s.len() >= AVX2_VECTOR_SIZE {
+            unsafe { foreach_special_avx2(bytes, 0, callback)? }
+        } else if width >= 128 && s.len() >= VECTOR_SIZE {
+            unsafe { foreach_special_simd(bytes, 0, callback)? }
         } else {
-            super::escape_html_scalar(w, s)
+            return super::escape_html_scalar(w, s);
         }
+        unsafe { w.write_str(s.get_unchecked(mark..)) }
     }
 
     /// Creates the lookup table for use in `compute_mask`.
@@ -240,12 +283,12 @@ mod simd {
     /// `bytes[offset..]`. For example, the mask `(1 << 3)` states that there is an HTML byte
     /// at `offset + 3`. It is only safe to call this function when
     /// `bytes.len() >= offset + VECTOR_SIZE`.
-    unsafe fn compute_mask(bytes: &[u8], offset: usize) -> i32 {
+    unsafe fn compute_mask(bytes: &[u8], offset: usize) -> u32 {
         debug_assert!(bytes.len() >= offset + VECTOR_SIZE);
 
         let table = create_lookup();
         let lookup = _mm_loadu_si128(table.as_ptr() as *const __m128i);
-        let raw_ptr = bytes.as_ptr().offset(offset as isize) as *const __m128i;
+        let raw_ptr = bytes.as_ptr().add(offset) as *const __m128i;
 
         // Load the vector from memory.
         let vector = _mm_loadu_si128(raw_ptr);
@@ -265,7 +308,26 @@ mod simd {
 
         // Translate matches to a bitmask, where every 1 corresponds to a HTML special character
         // and a 0 is a non-HTML byte.
-        _mm_movemask_epi8(matches)
+        _mm_movemask_epi8(matches) as u32
+    }
+
+    #[target_feature(enable = "avx2")]
+    /// Computes a byte mask like `compute_mask`, for the 32 bytes `bytes[offset..]`.
+    /// The lookup table is repeated for the upper half of the vector, since AVX2
+    /// shuffles bytes within each half separately. It is only safe to call this
+    /// function when `bytes.len() >= offset + AVX2_VECTOR_SIZE`.
+    unsafe fn compute_mask_avx2(bytes: &[u8], offset: usize) -> u32 {
+        debug_assert!(bytes.len() >= offset + AVX2_VECTOR_SIZE);
+
+        let table = create_lookup();
+        let lookup = _mm256_broadcastsi128_si256(_mm_loadu_si128(table.as_ptr() as *const __m128i));
+        let raw_ptr = bytes.as_ptr().add(offset) as *const __m256i;
+
+        let vector = _mm256_loadu_si256(raw_ptr);
+        let expected = _mm256_shuffle_epi8(lookup, vector);
+        let matches = _mm256_cmpeq_epi8(expected, vector);
+
+        _mm256_movemask_epi8(matches) as u32
     }
 
     /// Calls the given function with the index of every byte in the given byteslice
@@ -273,15 +335,38 @@ mod simd {
     /// Make sure to only call this when `bytes.len() >= 16`, undefined behaviour may
     /// occur otherwise.
     #[target_feature(enable = "ssse3")]
-    unsafe fn foreach_special_simd<F>(
+    unsafe fn foreach_special_simd<E, F>(bytes: &[u8], offset: usize, callback: F) -> Result<(), E>
+    where
+        F: FnMut(usize) -> Result<(), E>,
+    {
+        foreach_special_vector(bytes, offset, callback, VECTOR_SIZE, compute_mask)
+    }
+
+    /// Like `foreach_special_simd`, for AVX2. Make sure to only call this when
+    /// `bytes.len() >= 32`, undefined behaviour may occur otherwise.
+    #[target_feature(enable = "avx2")]
+    unsafe fn foreach_special_avx2<E, F>(bytes: &[u8], offset: usize, callback: F) -> Result<(), E>
+    where
+        F: FnMut(usize) -> Result<(), E>,
+    {
+        foreach_special_vector(bytes, offset, callback, AVX2_VECTOR_SIZE, compute_mask_avx2)
+    }
+
+    /// Walks the bytes in vectors of `vector_size` bytes, using `compute_mask` to
+    /// find the special bytes in each. Always inlined, so that the mask computation
+    /// is inlined into the callers with the matching target feature.
+    #[inline(always)]
+    unsafe fn foreach_special_vector<E, F>(
         bytes: &[u8],
         mut offset: usize,
         mut callback: F,
-    ) -> io::Result<()>
+        vector_size: usize,
+        compute_mask: unsafe fn(&[u8], usize) -> u32,
+    ) -> Result<(), E>
     where
-        F: FnMut(usize) -> io::Result<()>,
+        F: FnMut(usize) -> Result<(), E>,
     {
-        // The strategy here is to walk the byte buffer in chunks of VECTOR_SIZE (16)
+        // The strategy here is to walk the byte buffer in chunks of `vector_size`
         // bytes at a time starting at the given offset. For each chunk, we compute a
         // a bitmask indicating whether the corresponding byte is a HTML special byte.
         // We then iterate over all the 1 bits in this mask and call the callback function
@@ -290,16 +375,16 @@ mod simd {
         // allows us to quickly go through the buffer without a lookup and for every
         // single byte.
 
-        debug_assert!(bytes.len() >= VECTOR_SIZE);
-        let upperbound = bytes.len() - VECTOR_SIZE;
+        debug_assert!(bytes.len() >= vector_size);
+        let upperbound = bytes.len() - vector_size;
         while offset < upperbound {
             let mut mask = compute_mask(bytes, offset);
             while mask != 0 {
                 let ix = mask.trailing_zeros();
                 callback(offset + ix as usize)?;
-                mask ^= mask & -mask;
+                mask &= mask - 1;
             }
-            offset += VECTOR_SIZE;
+            offset += vector_size;
         }
 
         // Final iteration. We align the read with the end of the slice and
@@ -309,47 +394,75 @@ mod simd {
         while mask != 0 {
             let ix = mask.trailing_zeros();
             callback(offset + ix as usize)?;
-            mask ^= mask & -mask;
+            mask &= mask - 1;
         }
         Ok(())
     }
 
     #[cfg(test)]
     mod html_scan_tests {
+        use super::{foreach_special_avx2, foreach_special_simd, AVX2_VECTOR_SIZE, VECTOR_SIZE};
+
+        /// Returns the indices of the special bytes that each implementation
+        /// that can run on this processor finds.
+        fn special_indices(bytes: &[u8]) -> Vec<Vec<usize>> {
+            let mut results = vec![];
+            if is_x86_feature_detected!("ssse3") && bytes.len() >= VECTOR_SIZE {
+                let mut vec = Vec::new();
+                unsafe {
+                    foreach_special_simd(bytes, 0, |ix| {
+                        vec.push(ix);
+                        Ok::<_, ()>(())
+                    })
+                    .unwrap()
+                };
+                results.push(vec);
+            }
+            if is_x86_feature_detected!("avx2") && bytes.len() >= AVX2_VECTOR_SIZE {
+                let mut vec = Vec::new();
+                unsafe {
+                    foreach_special_avx2(bytes, 0, |ix| {
+                        vec.push(ix);
+                        Ok::<_, ()>(())
+                    })
+                    .unwrap()
+                };
+                results.push(vec);
+            }
+            results
+        }
+
         #[test]
         fn multichunk() {
-            let mut vec = Vec::new();
-            unsafe {
-                super::foreach_special_simd("&aXaaaa.a'aa9a<>aab&".as_bytes(), 0, |ix| {
-                    Ok(vec.push(ix))
-                })
-                .unwrap();
+            for vec in special_indices("&aXaaaa.a'aa9a<>aab&".as_bytes()) {
+                assert_eq!(vec, vec![0, 14, 15, 19]);
+            }
+            let text = "&aXaaaa.a'aa9a<>aab&aaaaaaaaaaa\"aaaaaaaaaaaaaaaaaa<a>";
+            for vec in special_indices(text.as_bytes()) {
+                assert_eq!(vec, vec![0, 14, 15, 19, 31, 50, 52]);
             }
-            assert_eq!(vec, vec![0, 14, 15, 19]);
         }
 
-        // only match these bytes, and when we match them, match them VECTOR_SIZE times
+        // only match these bytes, and when we match them, match them for every
+        // byte of the vector
         #[test]
         fn only_right_bytes_matched() {
             for b in 0..255u8 {
                 let right_byte = b == b'&' || b == b'<' || b == b'>' || b == b'"';
-                let vek = vec![b; super::VECTOR_SIZE];
-                let mut match_count = 0;
-                unsafe {
-                    super::foreach_special_simd(&vek, 0, |_| {
-                        match_count += 1;
-                        Ok(())
-                    })
-                    .unwrap();
+                for &len in [VECTOR_SIZE, AVX2_VECTOR_SIZE].iter() {
+                    let vek = vec![b; len];
+                    for vec in special_indices(&vek) {
+                        let match_count = vec.len();
+                        assert!((match_count > 0) == (match_count == len));
+                        assert_eq!(
+                            (match_count == len),
+                            right_byte,
+                            "match_count: {}, byte: {:?}",
+                            match_count,
+                            b as char
+                        );
+                    }
                 }
-                assert!((match_count > 0) == (match_count == super::VECTOR_SIZE));
-                assert_eq!(
-                    (match_count == super::VECTOR_SIZE),
-                    right_byte,
-                    "match_count: {}, byte: {:?}",
-                    match_count,
-                    b as char
-                );
             }
         }
     }
@@ -0,0 +1,330 @@
+//! Syntax extensions defined outside of this crate.
+
+use crate::strings::CowStr;
+
+/// A custom block, recognized by its first line.
+///
+/// Block parsers are given to [`Parser::new_with_block_parsers`], and are asked
+/// about every line that could start a block, before the built-in block syntax
+/// except for indented code. A block that is started continues line by line,
+/// like a fenced code block, until the parser ends it or a container block it
+/// is in ends. Its lines after the first become the text of the block, and
+/// are returned as [`Event::Text`] between the [`Tag::Custom`] start and end
+/// events. Custom blocks can't interrupt a paragraph.
+///
+/// # Example
+/// ```rust
+/// use pulldown_cmark::{BlockContext, BlockLine, BlockParser, BlockStart, Event, Options, Parser, Tag};
+///
+/// /// Parses `:::name` ... `:::` callouts.
+/// struct Callouts;
+///
+/// impl BlockParser for Callouts {
+///     fn start<'a>(&mut self, line: &'a str, _context: &BlockContext) -> Option<BlockStart<'a>> {
+///         let kind = line.strip_prefix(":::")?.trim();
+///         if kind.is_empty() {
+///             return None;
+///         }
+///         Some(BlockStart::new("callout", kind))
+///     }
+///
+///     fn next_line(&mut self, line: &str) -> BlockLine {
+///         if line.trim_end() == ":::" {
+///             BlockLine::Close
+///         } else {
+///             BlockLine::Content
+///         }
+///     }
+/// }
+///
+/// let events: Vec<_> =
+///     Parser::new_with_block_parsers(":::warning\nHot!\n:::\n", Options::empty(), &mut [&mut Callouts])
+///         .collect();
+/// let tag = Tag::Custom("callout".into(), "warning".into());
+/// assert_eq!(
+///     vec![Event::Start(tag.clone()), Event::Text("Hot!\n".into()), Event::End(tag)],
+///     events
+/// );
+/// ```
+///
+/// [`Parser::new_with_block_parsers`]: crate::Parser::new_with_block_parsers
+/// [`Event::Text`]: crate::Event::Text
+/// [`Tag::Custom`]: crate::Tag::Custom
+pub trait BlockParser {
+    /// Returns how the block starting with `line` is reported when this parser
+    /// recognizes it, and `None` otherwise. The line is given without its
+    /// containers' markers, indentation and line ending.
+    fn start<'a>(&mut self, line: &'a str, context: &BlockContext) -> Option<BlockStart<'a>>;
+
+    /// Returns whether `line`, which follows the lines of a block started by
+    /// this parser, belongs to the block. The line is given without its
+    /// containers' markers, the indentation of the first line, and its line
+    /// ending.
+    fn next_line(&mut self, line: &str) -> BlockLine;
+}
+
+/// Where a line that could start a custom block is in the document.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockContext {
+    /// The number of spaces the line is indented by, less than four.
+    pub indent: usize,
+    /// The number of block quotes the line is in.
+    pub block_quote_depth: usize,
+    /// The number of list items the line is in.
+    pub list_depth: usize,
+}
+
+/// A custom block recognized by a [`BlockParser`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockStart<'a> {
+    /// The name of the block, which identifies the kind of block to renderers.
+    pub name: CowStr<'a>,
+    /// What else the first line says about the block.
+    pub data: CowStr<'a>,
+}
+
+impl<'a> BlockStart<'a> {
+    /// Creates a block start with the given name and data.
+    pub fn new(name: impl Into<CowStr<'a>>, data: impl Into<CowStr<'a>>) -> Self {
+        BlockStart {
+            name: name.into(),
+            data: data.into(),
+        }
+    }
+}
+
+/// How a line relates to the custom block before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockLine {
+    /// The line is part of the text of the block.
+    Content,
+    /// The line closes the block, like a closing code fence, and isn't part of
+    /// its text.
+    Close,
+    /// The block ended with the line before, and this line is parsed as usual.
+    After,
+}
+
+/// A custom inline element, recognized by the byte it starts with.
+///
+/// Inline parsers are given to [`Parser::new_with_extensions`]. Their trigger
+/// bytes are scanned for together with the ones of the built-in inline syntax,
+/// and the parsers are asked about every occurrence, before the built-in
+/// syntax. An element found by a parser is returned as an [`Event::Custom`]
+/// and can't contain other inline elements.
+///
+/// # Example
+/// ```rust
+/// use pulldown_cmark::{html, InlineNode, InlineParser, Options, Parser};
+///
+/// /// Parses `{{name}}` variables.
+/// struct Variables;
+///
+/// impl InlineParser for Variables {
+///     fn triggers(&self) -> &[u8] {
+///         b"{"
+///     }
+///
+///     fn parse<'a>(&mut self, text: &'a str, ix: usize) -> Option<(usize, InlineNode<'a>)> {
+///         let rest = text[ix..].strip_prefix("{{")?;
+///         let name = &rest[..rest.find("}}")?];
+///         Some((name.len() + 4, InlineNode::new("variable", name)))
+///     }
+/// }
+///
+/// let parser = Parser::new_with_extensions(
+///     "Hello *{{user}}*!",
+///     Options::empty(),
+///     &mut [],
+///     &mut [&mut Variables],
+/// );
+/// let mut html_output = String::new();
+/// html::push_html(&mut html_output, parser);
+/// assert_eq!(
+///     "<p>Hello <em><span data-inline=\"variable\">user</span></em>!</p>\n",
+///     html_output
+/// );
+/// ```
+///
+/// [`Parser::new_with_extensions`]: crate::Parser::new_with_extensions
+/// [`Event::Custom`]: crate::Event::Custom
+pub trait InlineParser {
+    /// Returns the bytes that elements of this parser can start with. Only ASCII
+    /// bytes can be triggers, others are ignored.
+    fn triggers(&self) -> &[u8];
+
+    /// Returns the length of the element that starts at `ix` in `text` with one
+    /// of the trigger bytes, and how it is reported, or `None` if there isn't
+    /// one. The text is the inline content of the current line of a block,
+    /// without its line ending, and the element can't be longer than the rest of
+    /// it. Results that are empty or too long are ignored.
+    fn parse<'a>(&mut self, text: &'a str, ix: usize) -> Option<(usize, InlineNode<'a>)>;
+}
+
+/// A custom inline element recognized by an [`InlineParser`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct InlineNode<'a> {
+    /// The name of the element, which identifies the kind of element to
+    /// renderers.
+    pub name: CowStr<'a>,
+    /// The content of the element.
+    pub data: CowStr<'a>,
+}
+
+impl<'a> InlineNode<'a> {
+    /// Creates an inline element with the given name and content.
+    pub fn new(name: impl Into<CowStr<'a>>, data: impl Into<CowStr<'a>>) -> Self {
+        InlineNode {
+            name: name.into(),
+            data: data.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::*;
+    use crate::{html, Event, Options, Parser, Tag};
+
+    /// Blocks of lines starting with `!`, which record their context.
+    #[derive(Default)]
+    struct Bangs(Vec<BlockContext>);
+
+    impl BlockParser for Bangs {
+        fn start<'a>(&mut self, line: &'a str, context: &BlockContext) -> Option<BlockStart<'a>> {
+            let data = line.strip_prefix('!')?;
+            self.0.push(context.clone());
+            Some(BlockStart::new("bang", data))
+        }
+
+        fn next_line(&mut self, line: &str) -> BlockLine {
+            if let Some(_rest) = line.strip_prefix('!') {
+                BlockLine::Content
+            } else {
+                BlockLine::After
+            }
+        }
+    }
+
+    fn render(text: &str, bangs: &mut Bangs) -> String {
+        let mut s = String::new();
+        html::push_html(
+            &mut s,
+            Parser::new_with_block_parsers(text, Options::empty(), &mut [bangs]),
+        );
+        s
+    }
+
+    #[test]
+    fn custom_block() {
+        let mut bangs = Bangs::default();
+        let events: Vec<_> =
+            Parser::new_with_block_parsers("!a\n!b\nc\n", Options::empty(), &mut [&mut bangs])
+                .collect();
+        let tag = Tag::Custom("bang".into(), "a".into());
+        assert_eq!(
+            vec![
+                Event::Start(tag.clone()),
+                Event::Text("!b\n".into()),
+                Event::End(tag),
+                Event::Start(Tag::Paragraph(None)),
+                Event::Text("c".into()),
+                Event::End(Tag::Paragraph(None)),
+            ],
+            events
+        );
+    }
+
+    #[test]
+    fn containers() {
+        let mut bangs = Bangs::default();
+        assert_eq!(
+            "<ul>\n<li>\n<blockquote>\n<div data-block=\"bang\">\n!&lt;b&gt;\n</div>\n</blockquote>\n</li>\n</ul>\n<p>c</p>\n",
+            render("- > !\n  > !<b>\nc\n", &mut bangs)
+        );
+        let context = BlockContext {
+            indent: 0,
+            block_quote_depth: 1,
+            list_depth: 1,
+        };
+        assert_eq!(vec![context], bangs.0);
+    }
+
+    #[test]
+    fn no_paragraph_interruption() {
+        let mut bangs = Bangs::default();
+        assert_eq!(
+            "<p>a\n!b</p>\n<div data-block=\"bang\" data-info=\"c\">\n</div>\n",
+            render("a\n!b\n\n  !c", &mut bangs)
+        );
+        assert_eq!(2, bangs.0[0].indent);
+    }
+
+    /// Keyboard keys like `[[Ctrl]]`, and `$name` variables.
+    struct Keys;
+
+    impl InlineParser for Keys {
+        fn triggers(&self) -> &[u8] {
+            b"[$\xff"
+        }
+
+        fn parse<'a>(&mut self, text: &'a str, ix: usize) -> Option<(usize, InlineNode<'a>)> {
+            let rest = &text[ix..];
+            if let Some(var) = rest.strip_prefix('$') {
+                let len = var.find(' ').unwrap_or(var.len());
+                return Some((len + 1, InlineNode::new("var", &var[..len])));
+            }
+            let key = rest.strip_prefix("[[")?;
+            let len = key.find("]]")?;
+            Some((len + 4, InlineNode::new("key", &key[..len])))
+        }
+    }
+
+    #[test]
+    fn custom_inline() {
+        let text =
+            "Press [[Ctrl]] and [[C]] to copy $x, not `[[A]]` or \\$y or [[B]\nand [link](/u) $";
+        let mut s = String::new();
+        let parser = Parser::new_with_extensions(text, Options::empty(), &mut [], &mut [&mut Keys]);
+        html::push_html(&mut s, parser);
+        assert_eq!(
+            "<p>Press <span data-inline=\"key\">Ctrl</span> and <span data-inline=\"key\">C</span> \
+             to copy <span data-inline=\"var\">x,</span> not <code>[[A]]</code> or $y or [[B]\n\
+             and <a href=\"/u\">link</a> <span data-inline=\"var\"></span></p>\n",
+            s
+        );
+    }
+
+    /// `$name` variables, counting the calls to `parse`.
+    #[derive(Default)]
+    struct Vars(usize);
+
+    impl InlineParser for Vars {
+        fn triggers(&self) -> &[u8] {
+            b"$"
+        }
+
+        fn parse<'a>(&mut self, text: &'a str, ix: usize) -> Option<(usize, InlineNode<'a>)> {
+            self.0 += 1;
+            let var = &text[ix + 1..];
+            let len = var.find(' ').unwrap_or(var.len());
+            Some((len + 1, InlineNode::new("var", &var[..len])))
+        }
+    }
+
+    #[test]
+    fn long_line_with_other_special_bytes() {
+        // Special bytes that trigger no inline parser must not make the
+        // first pass quadratic in the length of the line.
+        let text = format!("{}$x", "*a ".repeat(80_000));
+        let mut vars = Vars::default();
+        let parser =
+            Parser::new_with_extensions(&text, Options::empty(), &mut [], &mut [&mut vars]);
+        let customs = parser
+            .filter(|event| matches!(event, Event::Custom(..)))
+            .count();
+        assert_eq!(1, customs);
+        assert_eq!(1, vars.0);
+    }
+}
@@ -1,42 +1,184 @@
 //! The first pass resolves all block structure, generating an AST. Within a block, items
 //! are in a linear chain with potential inline markup identified.
 
-use std::cmp::max;
-use std::ops::Range;
-
-use crate::parse::{scan_containers, Allocations, Item, ItemBody, LinkDef};
+use alloc::collections::btree_map::Entry;
+use core::cmp::max;
+use core::ops::Range;
+
+use crate::diagnostic::DiagnosticCode;
+use crate::extension::{BlockContext, BlockLine, BlockParser, InlineParser};
+use crate::limits::ParseLimits;
+use crate::parse::{scan_containers, Allocations, CustomIndex, Item, ItemBody, LinkDef};
+use crate::prelude::*;
 use crate::scanners::*;
 use crate::strings::CowStr;
 use crate::tree::{Tree, TreeIndex};
-use crate::Options;
 use crate::{
     linklabel::{scan_link_label_rest, LinkLabel},
     HeadingLevel,
 };
+use crate::{ListNumbering, Options};
 
 use unicase::UniCase;
 
 /// Runs the first pass, which resolves the block structure of the document,
 /// and returns the resulting tree.
-pub(crate) fn run_first_pass<'a>(text: &'a str, options: Options) -> (Tree<Item>, Allocations<'a>) {
+pub(crate) fn run_first_pass<'a>(
+    text: &'a str,
+    options: Options,
+    block_parsers: &mut [&mut dyn BlockParser],
+    inline_parsers: &mut [&mut dyn InlineParser],
+) -> (Tree<Item>, Allocations<'a>) {
     // This is a very naive heuristic for the number of nodes
     // we'll need.
     let start_capacity = max(128, text.len() / 32);
-    let lookup_table = &create_lut(&options);
+    run_first_pass_with(
+        text,
+        options,
+        Tree::with_capacity(start_capacity),
+        Allocations::new(),
+        block_parsers,
+        inline_parsers,
+    )
+}
+
+/// Runs the first pass like `run_first_pass`, building the tree in empty
+/// buffers that are reused from an earlier parse.
+pub(crate) fn run_first_pass_with<'a>(
+    text: &'a str,
+    options: Options,
+    tree: Tree<Item>,
+    allocs: Allocations<'a>,
+    block_parsers: &mut [&mut dyn BlockParser],
+    inline_parsers: &mut [&mut dyn InlineParser],
+) -> (Tree<Item>, Allocations<'a>) {
+    let triggers: Vec<u8> = (inline_parsers.iter())
+        .flat_map(|parser| parser.triggers().iter().copied())
+        .collect();
+    let lookup_table = &create_lut(&options, &triggers);
+    let mut inline_triggers = [false; 256];
+    for &byte in &triggers {
+        inline_triggers[byte as usize] = true;
+    }
     let first_pass = FirstPass {
         text,
-        tree: Tree::with_capacity(start_capacity),
+        tree,
+        begin_list_item: false,
+        last_line_blank: false,
+        allocs,
+        options,
+        lookup_table,
+        refdef_count: 0,
+        block_parsers,
+        inline_parsers,
+        inline_triggers,
+        limits: ParseLimits::default(),
+        limit_exceeded: false,
+    };
+    first_pass.run()
+}
+
+/// Runs the first pass like `run_first_pass`, but stops after the block at
+/// which a limit on the block structure is exceeded, and doesn't start if the
+/// text is longer than allowed. Which limit is exceeded is left to
+/// `limits::check_blocks`.
+pub(crate) fn run_first_pass_with_limits<'a>(
+    text: &'a str,
+    options: Options,
+    limits: ParseLimits,
+) -> (Tree<Item>, Allocations<'a>) {
+    if text.len() > limits.max_input_bytes {
+        return (Tree::with_capacity(1), Allocations::new());
+    }
+    let lookup_table = &create_lut(&options, &[]);
+    let first_pass = FirstPass {
+        text,
+        tree: Tree::with_capacity(max(128, text.len() / 32)),
         begin_list_item: false,
         last_line_blank: false,
         allocs: Allocations::new(),
         options,
         lookup_table,
+        refdef_count: 0,
+        block_parsers: &mut [],
+        inline_parsers: &mut [],
+        inline_triggers: [false; 256],
+        limits,
+        limit_exceeded: false,
     };
     first_pass.run()
 }
 
+/// What `run_first_pass_from` found while parsing part of a document.
+pub(crate) struct Reparse {
+    /// The starts of the lines at which no container block was open, in order.
+    pub boundaries: Vec<usize>,
+    /// The offset at which parsing stopped.
+    pub end: usize,
+    /// The number of link reference definitions that were parsed.
+    pub refdefs: usize,
+}
+
+/// Runs the first pass over a part of a document, appending the blocks found
+/// after the current node of `tree`. Parsing starts at `start`, which has to be
+/// the start of a line at which no container block is open, and stops at the
+/// first such line for which `resync` returns true, or at the end of the text.
+pub(crate) fn run_first_pass_from<'a>(
+    text: &'a str,
+    options: Options,
+    tree: Tree<Item>,
+    allocs: Allocations<'a>,
+    start: usize,
+    mut resync: impl FnMut(usize) -> bool,
+) -> (Tree<Item>, Allocations<'a>, Reparse) {
+    let lookup_table = &create_lut(&options, &[]);
+    let mut first_pass = FirstPass {
+        text,
+        tree,
+        begin_list_item: false,
+        last_line_blank: false,
+        allocs,
+        options,
+        lookup_table,
+        refdef_count: 0,
+        block_parsers: &mut [],
+        inline_parsers: &mut [],
+        inline_triggers: [false; 256],
+        limits: ParseLimits::default(),
+        limit_exceeded: false,
+    };
+    let mut boundaries = Vec::new();
+    let mut ix = start;
+    while ix < text.len() {
+        if first_pass.tree.spine_len() == 0
+            && !first_pass.last_line_blank
+            && !first_pass.begin_list_item
+        {
+            if ix > start && resync(ix) {
+                break;
+            }
+            boundaries.push(ix);
+        }
+        ix = first_pass.parse_block(ix);
+    }
+    for _ in 0..first_pass.tree.spine_len() {
+        first_pass.pop(ix);
+    }
+    let reparse = Reparse {
+        boundaries,
+        end: ix,
+        refdefs: first_pass.refdef_count,
+    };
+    (first_pass.tree, first_pass.allocs, reparse)
+}
+
+/// Returns a line without its line ending.
+fn strip_line_ending(line: &str) -> &str {
+    line.trim_end_matches(&['\n', '\r'][..])
+}
+
 /// State for the first parsing pass.
-struct FirstPass<'a, 'b> {
+struct FirstPass<'a, 'b, 'c, 'd> {
     text: &'a str,
     tree: Tree<Item>,
     begin_list_item: bool,
@@ -44,18 +186,53 @@ struct FirstPass<'a, 'b> {
     allocs: Allocations<'a>,
     options: Options,
     lookup_table: &'b LookupTable,
+    // number of link reference definitions parsed
+    refdef_count: usize,
+    block_parsers: &'b mut [&'c mut dyn BlockParser],
+    inline_parsers: &'b mut [&'d mut dyn InlineParser],
+    // whether each byte triggers any of the inline parsers
+    inline_triggers: [bool; 256],
+    limits: ParseLimits,
+    // whether a limit was exceeded, which stops parsing
+    limit_exceeded: bool,
 }
 
-impl<'a, 'b> FirstPass<'a, 'b> {
+impl<'a, 'b, 'c, 'd> FirstPass<'a, 'b, 'c, 'd> {
     fn run(mut self) -> (Tree<Item>, Allocations<'a>) {
+        self.parse_document();
+        (self.tree, self.allocs)
+    }
+
+    fn parse_document(&mut self) {
         let mut ix = 0;
-        while ix < self.text.len() {
+        while ix < self.text.len() && !self.limit_exceeded {
             ix = self.parse_block(ix);
+            if self.tree.len() - 1 > self.limits.max_nodes
+                || self.allocs.refdefs.0.len() > self.limits.max_reference_definitions
+            {
+                self.limit_exceeded = true;
+            }
         }
         for _ in 0..self.tree.spine_len() {
             self.pop(ix);
         }
-        (self.tree, self.allocs)
+    }
+
+    /// Records whether opening a container block exceeds the limit on block
+    /// nesting, which stops parsing after the current block.
+    fn check_nesting(&mut self) {
+        if self.limit_exceeded || self.tree.spine_len() < self.limits.max_block_depth {
+            return;
+        }
+        let depth = (self.tree.walk_spine())
+            .filter(|&&node_ix| {
+                matches!(
+                    self.tree[node_ix].item.body,
+                    ItemBody::BlockQuote | ItemBody::ListItem(..)
+                )
+            })
+            .count();
+        self.limit_exceeded = depth >= self.limits.max_block_depth;
     }
 
     /// Returns offset after block.
@@ -92,13 +269,15 @@ impl<'a, 'b> FirstPass<'a, 'b> {
         // Process new containers
         loop {
             let container_start = start_ix + line_start.bytes_scanned();
-            if let Some((ch, index, indent)) = line_start.scan_list_marker() {
+            let fancy_lists = self.options.contains(Options::ENABLE_FANCY_LISTS);
+            if let Some((ch, index, numbering, indent)) = line_start.scan_list_marker(fancy_lists) {
+                self.check_nesting();
                 let after_marker_index = start_ix + line_start.bytes_scanned();
-                self.continue_list(container_start, ch, index);
+                self.continue_list(container_start, ch, numbering, index);
                 self.tree.append(Item {
                     start: container_start,
                     end: after_marker_index, // will get updated later if item not empty
-                    body: ItemBody::ListItem(indent),
+                    body: ItemBody::ListItem(indent, None),
                 });
                 self.tree.push();
                 if let Some(n) = scan_blank_line(&bytes[after_marker_index..]) {
@@ -115,6 +294,7 @@ impl<'a, 'b> FirstPass<'a, 'b> {
                     }
                 }
             } else if line_start.scan_blockquote_marker() {
+                self.check_nesting();
                 self.finish_list(start_ix);
                 self.tree.append(Item {
                     start: container_start,
@@ -160,11 +340,22 @@ impl<'a, 'b> FirstPass<'a, 'b> {
 
         let ix = start_ix + line_start.bytes_scanned();
 
+        if !self.block_parsers.is_empty() {
+            if let Some(next_ix) = self.parse_custom_block(ix, indent) {
+                return next_ix;
+            }
+        }
+
         // HTML Blocks
         if bytes[ix] == b'<' {
             // Types 1-5 are all detected by one function and all end with the same
             // pattern
             if let Some(html_end_tag) = get_html_end_tag(&bytes[(ix + 1)..]) {
+                if html_end_tag == "-->"
+                    && self.options.contains(Options::ENABLE_STRIP_HTML_COMMENTS)
+                {
+                    return self.parse_html_comment_block(ix);
+                }
                 return self.parse_html_block_type_1_to_5(ix, html_end_tag, remaining_space);
             }
 
@@ -190,7 +381,30 @@ impl<'a, 'b> FirstPass<'a, 'b> {
 
         // parse refdef
         if let Some((bytecount, label, link_def)) = self.parse_refdef_total(ix) {
-            self.allocs.refdefs.0.entry(label).or_insert(link_def);
+            if self
+                .options
+                .contains(Options::ENABLE_LINK_DEFINITION_EVENTS)
+            {
+                let definition_ix = (self.allocs).allocate_definition(
+                    (*label).clone(),
+                    link_def.dest.clone(),
+                    link_def.title.clone(),
+                );
+                self.tree.append(Item {
+                    start: link_def.span.start,
+                    end: link_def.span.end,
+                    body: ItemBody::LinkDefinition(definition_ix),
+                });
+            }
+            match self.allocs.refdefs.0.entry(label) {
+                Entry::Occupied(_) => {
+                    self.report(DiagnosticCode::DuplicateDefinition, link_def.span)
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(link_def);
+                }
+            }
+            self.refdef_count += 1;
             let ix = ix + bytecount;
             // try to read trailing whitespace or it will register as a completely blank line
             // TODO: shouldn't we do this for all block level items?
@@ -200,9 +414,190 @@ impl<'a, 'b> FirstPass<'a, 'b> {
         if let Some((n, fence_ch)) = scan_code_fence(&bytes[ix..]) {
             return self.parse_fenced_code_block(ix, indent, fence_ch, n);
         }
+
+        if self.options.contains(Options::ENABLE_COMMENTS) && bytes[ix..].starts_with(b"%%") {
+            if let Some(next_ix) = self.parse_comment_block(ix) {
+                return next_ix;
+            }
+        }
+
+        if self.options.contains(Options::ENABLE_LINE_BLOCKS)
+            && scan_line_block_marker(&bytes[ix..])
+            && !self.precedes_table_head(ix)
+        {
+            return self.parse_line_block(ix);
+        }
+
         self.parse_paragraph(ix)
     }
 
+    /// Parses a block recognized by one of the block parsers, starting at the
+    /// first line at `start_ix`. Returns the offset of the first line after it,
+    /// or `None` if no parser recognizes the line.
+    fn parse_custom_block(&mut self, start_ix: usize, indent: usize) -> Option<usize> {
+        let text = self.text;
+        let bytes = text.as_bytes();
+        let mut ix = start_ix + scan_nextline(&bytes[start_ix..]);
+        let mut context = BlockContext {
+            indent,
+            ..BlockContext::default()
+        };
+        for &node_ix in self.tree.walk_spine() {
+            match self.tree[node_ix].item.body {
+                ItemBody::BlockQuote => context.block_quote_depth += 1,
+                ItemBody::ListItem(..) => context.list_depth += 1,
+                _ => (),
+            }
+        }
+        let line = strip_line_ending(&text[start_ix..ix]);
+        let (parser_ix, start) = (self.block_parsers.iter_mut().enumerate())
+            .find_map(|(i, parser)| Some((i, parser.start(line, &context)?)))?;
+
+        let custom_ix = self.allocs.allocate_custom(start.name, start.data);
+        self.tree.append(Item {
+            start: start_ix,
+            end: 0, // will get set later
+            body: ItemBody::Custom(custom_ix),
+        });
+        self.tree.push();
+        while ix < bytes.len() {
+            let mut line_start = LineStart::new(&bytes[ix..]);
+            let n_containers = scan_containers(&self.tree, &mut line_start);
+            if n_containers < self.tree.spine_len() {
+                break;
+            }
+            line_start.scan_space(indent);
+            let remaining_space = line_start.remaining_space();
+            let line_ix = ix + line_start.bytes_scanned();
+            let next_ix = line_ix + scan_nextline(&bytes[line_ix..]);
+            let line = strip_line_ending(&text[line_ix..next_ix]);
+            match self.block_parsers[parser_ix].next_line(line) {
+                BlockLine::Content => self.append_code_text(remaining_space, line_ix, next_ix),
+                BlockLine::Close => {
+                    ix = next_ix;
+                    break;
+                }
+                BlockLine::After => break,
+            }
+            ix = next_ix;
+        }
+        self.pop(ix);
+        Some(ix)
+    }
+
+    /// Adds a diagnostic when they are enabled.
+    fn report(&mut self, code: DiagnosticCode, range: Range<usize>) {
+        if self.options.contains(Options::ENABLE_DIAGNOSTICS) {
+            self.allocs.report(code, range);
+        }
+    }
+
+    /// Returns whether the line after the one at `ix` is a table delimiter row,
+    /// when tables are enabled.
+    fn precedes_table_head(&self, ix: usize) -> bool {
+        if !self.options.contains(Options::ENABLE_TABLES) {
+            return false;
+        }
+        let bytes = self.text.as_bytes();
+        let next_line_ix = ix + scan_nextline(&bytes[ix..]);
+        let mut line_start = LineStart::new(&bytes[next_line_ix..]);
+        scan_containers(&self.tree, &mut line_start) == self.tree.spine_len()
+            && !scan_table_head(&bytes[next_line_ix + line_start.bytes_scanned()..])
+                .1
+                .is_empty()
+    }
+
+    /// Parses a line block, starting at the `|` of its first line.
+    /// Returns the offset of the first line after it.
+    fn parse_line_block(&mut self, start_ix: usize) -> usize {
+        let bytes = self.text.as_bytes();
+        self.tree.append(Item {
+            start: start_ix,
+            end: 0, // will get set later
+            body: ItemBody::LineBlock,
+        });
+        self.tree.push();
+
+        let mut ix = start_ix;
+        loop {
+            self.tree.append(Item {
+                start: ix,
+                end: 0, // will get set later
+                body: ItemBody::Line,
+            });
+            self.tree.push();
+            let mut content_ix = ix + 1 + scan_ch(&bytes[ix + 1..], b' ');
+            let mut next_ix = content_ix + scan_nextline(&bytes[content_ix..]);
+            if scan_blank_line(&bytes[content_ix..]).is_none() {
+                // leading spaces are significant, so they are kept as non-breaking spaces
+                let spaces = scan_ch_repeat(&bytes[content_ix..], b' ');
+                if spaces > 0 {
+                    let cow_ix = self.allocs.allocate_cow("\u{a0}".repeat(spaces).into());
+                    self.tree.append(Item {
+                        start: content_ix,
+                        end: content_ix + spaces,
+                        body: ItemBody::SynthesizeText(cow_ix),
+                    });
+                    content_ix += spaces;
+                }
+                loop {
+                    let (line_end, brk) =
+                        self.parse_line(content_ix, None, TableParseMode::Disabled);
+                    next_ix = line_end;
+                    match self.scan_line_block_next(next_ix) {
+                        Some((continuation_ix, false)) => {
+                            if let Some(item) = brk {
+                                self.tree.append(item);
+                            }
+                            content_ix = continuation_ix;
+                        }
+                        _ => break,
+                    }
+                }
+            }
+            self.pop(next_ix);
+
+            match self.scan_line_block_next(next_ix) {
+                Some((bar_ix, true)) => ix = bar_ix,
+                _ => {
+                    ix = next_ix;
+                    break;
+                }
+            }
+        }
+
+        self.pop(ix);
+        ix
+    }
+
+    /// Scans the line at `ix` for the continuation of a line block. Returns the
+    /// offset of the `|` and `true` if it starts a new line of the block, or the
+    /// offset of its content and `false` if it continues the last line.
+    fn scan_line_block_next(&self, ix: usize) -> Option<(usize, bool)> {
+        let bytes = self.text.as_bytes();
+        if ix >= bytes.len() {
+            return None;
+        }
+        let mut line_start = LineStart::new(&bytes[ix..]);
+        if scan_containers(&self.tree, &mut line_start) < self.tree.spine_len() {
+            return None;
+        }
+        let line_ix = ix + line_start.bytes_scanned();
+        if scan_blank_line(&bytes[line_ix..]).is_some() {
+            return None;
+        }
+        let indent = line_start.scan_space_upto(4);
+        let content_ix = ix + line_start.bytes_scanned();
+        if indent < 4 && scan_line_block_marker(&bytes[content_ix..]) {
+            Some((content_ix, true))
+        } else if indent > 0 {
+            line_start.scan_all_space();
+            Some((ix + line_start.bytes_scanned(), false))
+        } else {
+            None
+        }
+    }
+
     /// Returns the offset of the first line after the table.
     /// Assumptions: current focus is a table element and the table header
     /// matches the separator line (same number of columns).
@@ -225,6 +620,8 @@ impl<'a, 'b> FirstPass<'a, 'b> {
     /// Returns bytes scanned, row_ix
     fn parse_table_row_inner(&mut self, mut ix: usize, row_cells: usize) -> (usize, TreeIndex) {
         let bytes = self.text.as_bytes();
+        let row_start = ix;
+        let mut row_end = ix;
         let mut cells = 0;
         let mut final_cell_ix = None;
 
@@ -236,7 +633,11 @@ impl<'a, 'b> FirstPass<'a, 'b> {
         self.tree.push();
 
         loop {
-            ix += scan_ch(&bytes[ix..], b'|');
+            let pipe_bytes = scan_ch(&bytes[ix..], b'|');
+            if pipe_bytes > 0 {
+                ix += pipe_bytes;
+                row_end = ix;
+            }
             let start_ix = ix;
             ix += scan_whitespace_no_nl(&bytes[ix..]);
 
@@ -262,6 +663,7 @@ impl<'a, 'b> FirstPass<'a, 'b> {
             self.tree.pop();
 
             ix = next_ix;
+            row_end = ix;
             cells += 1;
 
             if cells == row_cells {
@@ -269,6 +671,10 @@ impl<'a, 'b> FirstPass<'a, 'b> {
             }
         }
 
+        if cells != row_cells {
+            self.report(DiagnosticCode::TableCellCount, row_start..row_end);
+        }
+
         // fill empty cells if needed
         // note: this is where GFM and commonmark-extra diverge. we follow
         // GFM here
@@ -313,7 +719,7 @@ impl<'a, 'b> FirstPass<'a, 'b> {
         let node_ix = self.tree.append(Item {
             start: start_ix,
             end: 0, // will get set later
-            body: ItemBody::Paragraph,
+            body: ItemBody::Paragraph(None),
         });
         self.tree.push();
         let bytes = self.text.as_bytes();
@@ -386,10 +792,57 @@ impl<'a, 'b> FirstPass<'a, 'b> {
             }
         }
 
+        if self.options.contains(Options::ENABLE_BLOCK_IDS) {
+            self.parse_block_id(node_ix);
+        }
         self.pop(ix);
         ix
     }
 
+    /// Removes a block id marker like ` ^abc` from the end of the paragraph that
+    /// is being parsed, and records the id on the paragraph or on the list item
+    /// the paragraph starts.
+    fn parse_block_id(&mut self, paragraph_ix: TreeIndex) {
+        let last_ix = match self.tree.cur() {
+            Some(ix) if self.tree[ix].item.body == ItemBody::Text => ix,
+            _ => return,
+        };
+        // the paragraph may have turned into a setext heading
+        if self.tree[paragraph_ix].item.body != ItemBody::Paragraph(None) {
+            return;
+        }
+        let Item { start, end, .. } = self.tree[last_ix].item;
+        let marker_start = match scan_block_id(&self.text[start..end]) {
+            Some(offset) => start + offset,
+            None => return,
+        };
+        let caret = marker_start + self.text[marker_start..end].find('^').unwrap();
+        let block_id = Some(self.allocs.allocate_block_id(&self.text[caret + 1..end]));
+        self.tree
+            .truncate_siblings(self.text.as_bytes(), marker_start);
+
+        let list_item_ix = self
+            .tree
+            .peek_grandparent()
+            .filter(|&ix| matches!(self.tree[ix].item.body, ItemBody::ListItem(..)));
+        if let Some(list_item_ix) = list_item_ix {
+            let mut first_block = self.tree[list_item_ix].child;
+            while let Some(ix) = first_block {
+                if !matches!(self.tree[ix].item.body, ItemBody::TaskListMarker(_)) {
+                    break;
+                }
+                first_block = self.tree[ix].next;
+            }
+            if first_block == Some(paragraph_ix) {
+                if let ItemBody::ListItem(indent, _) = self.tree[list_item_ix].item.body {
+                    self.tree[list_item_ix].item.body = ItemBody::ListItem(indent, block_id);
+                }
+                return;
+            }
+        }
+        self.tree[paragraph_ix].item.body = ItemBody::Paragraph(block_id);
+    }
+
     /// Returns end ix of setext_heading on success.
     fn parse_setext_heading(
         &mut self,
@@ -456,9 +909,26 @@ impl<'a, 'b> FirstPass<'a, 'b> {
         let mut pipes = 0;
         let mut last_pipe_ix = start;
         let mut begin_text = start;
+        let line_end = if self.inline_parsers.is_empty() {
+            bytes_len
+        } else {
+            memchr::memchr2(b'\n', b'\r', &bytes[start..]).map_or(bytes_len, |n| start + n)
+        };
 
         let (final_ix, brk) =
             iterate_special_bytes(&self.lookup_table, bytes, start, |ix, byte| {
+                if self.inline_triggers[byte as usize] {
+                    if let Some((len, custom_ix)) = self.parse_custom_inline(start, line_end, ix) {
+                        self.tree.append_text(begin_text, ix);
+                        self.tree.append(Item {
+                            start: ix,
+                            end: ix + len,
+                            body: ItemBody::CustomInline(custom_ix),
+                        });
+                        begin_text = ix + len;
+                        return LoopInstruction::ContinueAndSkip(len - 1);
+                    }
+                }
                 match byte {
                     b'\n' | b'\r' => {
                         if let TableParseMode::Active = mode {
@@ -602,6 +1072,40 @@ impl<'a, 'b> FirstPass<'a, 'b> {
                         begin_text = ix + 1;
                         LoopInstruction::ContinueAndSkip(0)
                     }
+                    b'%' => {
+                        if ix + 1 < bytes_len && bytes[ix + 1] == b'%' {
+                            self.tree.append_text(begin_text, ix);
+                            self.tree.append(Item {
+                                start: ix,
+                                end: ix + 2,
+                                body: ItemBody::MaybeComment,
+                            });
+                            begin_text = ix + 2;
+                            LoopInstruction::ContinueAndSkip(1)
+                        } else {
+                            LoopInstruction::ContinueAndSkip(0)
+                        }
+                    }
+                    b'#' => {
+                        let starts_word = ix == 0 || is_hashtag_boundary(bytes[ix - 1]);
+                        let tag_len = if starts_word {
+                            scan_hashtag(&self.text[ix + 1..bytes_len])
+                        } else {
+                            0
+                        };
+                        if tag_len > 0 {
+                            self.tree.append_text(begin_text, ix);
+                            self.tree.append(Item {
+                                start: ix,
+                                end: ix + 1 + tag_len,
+                                body: ItemBody::Hashtag,
+                            });
+                            begin_text = ix + 1 + tag_len;
+                            LoopInstruction::ContinueAndSkip(tag_len)
+                        } else {
+                            LoopInstruction::ContinueAndSkip(0)
+                        }
+                    }
                     b'!' => {
                         if ix + 1 < bytes_len && bytes[ix + 1] == b'[' {
                             self.tree.append_text(begin_text, ix);
@@ -735,10 +1239,31 @@ impl<'a, 'b> FirstPass<'a, 'b> {
         (final_ix, brk)
     }
 
+    /// Asks the inline parsers triggered by the byte at `ix` for an element
+    /// there, on the line whose inline content spans `start..line_end`. Returns
+    /// the length of the element found, and its name and data.
+    fn parse_custom_inline(
+        &mut self,
+        start: usize,
+        line_end: usize,
+        ix: usize,
+    ) -> Option<(usize, CustomIndex)> {
+        let byte = self.text.as_bytes()[ix];
+        let line = &self.text[start..line_end];
+        let (len, node) = (self.inline_parsers.iter_mut())
+            .filter(|parser| parser.triggers().contains(&byte))
+            .filter_map(|parser| parser.parse(line, ix - start))
+            .find(|&(len, _)| {
+                len > 0 && ix + len <= line_end && line.is_char_boundary(ix - start + len)
+            })?;
+        Some((len, self.allocs.allocate_custom(node.name, node.data)))
+    }
+
     /// Check whether we should allow a paragraph interrupt by lists. Only non-empty
     /// lists are allowed.
     fn interrupt_paragraph_by_list(&self, current_container: bool, suffix: &[u8]) -> bool {
-        scan_listitem(suffix).map_or(false, |(ix, delim, index, _)| {
+        let fancy_lists = self.options.contains(Options::ENABLE_FANCY_LISTS);
+        scan_listitem(suffix, fancy_lists).map_or(false, |(ix, delim, index, _)| {
             ! current_container ||
             // we don't allow interruption by either empty lists or
             // numbered lists starting at an index other than 1
@@ -764,18 +1289,14 @@ impl<'a, 'b> FirstPass<'a, 'b> {
             ix += scan_nextline(&bytes[ix..]);
             self.append_html_line(remaining_space, line_start_ix, ix);
 
+            let closed = self.text[line_start_ix..ix].contains(html_end_tag);
             let mut line_start = LineStart::new(&bytes[ix..]);
             let n_containers = scan_containers(&self.tree, &mut line_start);
-            if n_containers < self.tree.spine_len() {
-                break;
-            }
-
-            if (&self.text[line_start_ix..ix]).contains(html_end_tag) {
-                break;
-            }
-
             let next_line_ix = ix + line_start.bytes_scanned();
-            if next_line_ix == self.text.len() {
+            if closed || n_containers < self.tree.spine_len() || next_line_ix == self.text.len() {
+                if !closed {
+                    self.report(DiagnosticCode::UnclosedHtmlBlock, start_ix..ix);
+                }
                 break;
             }
             ix = next_line_ix;
@@ -784,6 +1305,79 @@ impl<'a, 'b> FirstPass<'a, 'b> {
         ix
     }
 
+    /// Parses an HTML block that starts with a comment like
+    /// `parse_html_block_type_1_to_5`, but appends the comment as a comment item.
+    /// Anything after the end of the comment on its last line is kept as HTML.
+    fn parse_html_comment_block(&mut self, start_ix: usize) -> usize {
+        let bytes = self.text.as_bytes();
+        let mut content = String::new();
+        // `<!-->` and `<!--->` are complete comments
+        let mut search_ix = start_ix + 2;
+        let mut content_ix = start_ix + 4;
+        loop {
+            let line_end = search_ix + scan_nextline(&bytes[search_ix..]);
+            let comment_end = self.text[search_ix..line_end]
+                .find("-->")
+                .map(|end| search_ix + end);
+            let content_end = comment_end.unwrap_or(line_end);
+            if content_end > content_ix {
+                content.push_str(&self.text[content_ix..content_end]);
+            }
+
+            let mut line_start = LineStart::new(&bytes[line_end..]);
+            let end_of_block = line_end == bytes.len()
+                || scan_containers(&self.tree, &mut line_start) < self.tree.spine_len();
+            if comment_end.is_some() || end_of_block {
+                if comment_end.is_none() {
+                    self.report(DiagnosticCode::UnclosedHtmlBlock, start_ix..line_end);
+                }
+                let comment_end = comment_end.map_or(line_end, |end| end + 3);
+                self.tree.append(Item {
+                    start: start_ix,
+                    end: comment_end,
+                    body: ItemBody::Comment(self.allocs.allocate_cow(content.into())),
+                });
+                if scan_blank_line(&bytes[comment_end..]).is_none() {
+                    self.append_html_line(0, comment_end, line_end);
+                }
+                return line_end;
+            }
+            search_ix = line_end + line_start.bytes_scanned();
+            content_ix = search_ix;
+        }
+    }
+
+    /// Parses a comment block, which starts with `%%` and ends with `%%` at
+    /// the end of a line. Returns `None` if there is no such end.
+    fn parse_comment_block(&mut self, start_ix: usize) -> Option<usize> {
+        let bytes = self.text.as_bytes();
+        let mut content = String::new();
+        let mut content_ix = start_ix + 2;
+        loop {
+            let line_end = content_ix + scan_nextline(&bytes[content_ix..]);
+            if let Some(end) = self.text[content_ix..line_end].find("%%") {
+                let comment_end = content_ix + end + 2;
+                scan_blank_line(&bytes[comment_end..])?;
+                content.push_str(&self.text[content_ix..comment_end - 2]);
+                self.tree.append(Item {
+                    start: start_ix,
+                    end: comment_end,
+                    body: ItemBody::Comment(self.allocs.allocate_cow(content.into())),
+                });
+                return Some(line_end);
+            }
+            content.push_str(&self.text[content_ix..line_end]);
+
+            let mut line_start = LineStart::new(&bytes[line_end..]);
+            if line_end == bytes.len()
+                || scan_containers(&self.tree, &mut line_start) < self.tree.spine_len()
+            {
+                return None;
+            }
+            content_ix = line_end + line_start.bytes_scanned();
+        }
+    }
+
     /// When start_ix is at the beginning of an HTML block of type 6 or 7,
     /// this will consume lines until there is a blank line and keep track of
     /// the HTML within the block.
@@ -881,8 +1475,15 @@ impl<'a, 'b> FirstPass<'a, 'b> {
         // to just do a forward scan here?
         let mut ix = info_start + scan_nextline(&bytes[info_start..]);
         let info_end = ix - scan_rev_while(&bytes[info_start..ix], is_ascii_whitespace);
+        let raw_format = if self.options.contains(Options::ENABLE_RAW_ATTRIBUTES) {
+            scan_raw_attribute(&self.text[info_start..info_end])
+                .filter(|&(len, _)| info_start + len == info_end)
+                .map(|(_, format)| format)
+        } else {
+            None
+        };
         let info_string = unescape(&self.text[info_start..info_end]);
-        self.tree.append(Item {
+        let node_ix = self.tree.append(Item {
             start: start_ix,
             end: 0, // will get set later
             body: ItemBody::FencedCodeBlock(self.allocs.allocate_cow(info_string)),
@@ -912,11 +1513,39 @@ impl<'a, 'b> FirstPass<'a, 'b> {
         }
 
         self.pop(ix);
+        if let Some(format) = raw_format {
+            self.make_raw_block(node_ix, format);
+        }
 
         // try to read trailing whitespace or it will register as a completely blank line
         ix + scan_blank_line(&bytes[ix..]).unwrap_or(0)
     }
 
+    /// Turns a fenced code block into a raw block with the given format, which
+    /// has the content of the code block and no children.
+    fn make_raw_block(&mut self, node_ix: TreeIndex, format: &'a str) {
+        let mut content = CowStr::Borrowed("");
+        let mut child = self.tree[node_ix].child;
+        while let Some(child_ix) = child {
+            let item = self.tree[child_ix].item;
+            let text = match item.body {
+                ItemBody::SynthesizeText(cow_ix) => self.allocs[cow_ix].clone(),
+                _ => self.text[item.start..item.end].into(),
+            };
+            content = if content.is_empty() {
+                text
+            } else {
+                let mut buf = content.into_string();
+                buf.push_str(&text);
+                buf.into()
+            };
+            child = self.tree[child_ix].next;
+        }
+        self.tree[node_ix].item.body =
+            ItemBody::Raw(self.allocs.allocate_raw(format.into(), content));
+        self.tree[node_ix].child = None;
+    }
+
     fn append_code_text(&mut self, remaining_space: usize, start: usize, end: usize) {
         if remaining_space > 0 {
             let cow_ix = self.allocs.allocate_cow("   "[..remaining_space].into());
@@ -971,21 +1600,23 @@ impl<'a, 'b> FirstPass<'a, 'b> {
     fn pop(&mut self, ix: usize) {
         let cur_ix = self.tree.pop().unwrap();
         self.tree[cur_ix].item.end = ix;
-        if let ItemBody::List(true, _, _) = self.tree[cur_ix].item.body {
-            surgerize_tight_list(&mut self.tree, cur_ix);
+        if let ItemBody::List(true, _, _, _) = self.tree[cur_ix].item.body {
+            if !self.options.contains(Options::ENABLE_TIGHT_LIST_PARAGRAPHS) {
+                surgerize_tight_list(&mut self.tree, cur_ix);
+            }
         }
     }
 
     /// Close a list if it's open. Also set loose if last line was blank
     fn finish_list(&mut self, ix: usize) {
         if let Some(node_ix) = self.tree.peek_up() {
-            if let ItemBody::List(_, _, _) = self.tree[node_ix].item.body {
+            if let ItemBody::List(..) = self.tree[node_ix].item.body {
                 self.pop(ix);
             }
         }
         if self.last_line_blank {
             if let Some(node_ix) = self.tree.peek_grandparent() {
-                if let ItemBody::List(ref mut is_tight, _, _) = self.tree[node_ix].item.body {
+                if let ItemBody::List(ref mut is_tight, _, _, _) = self.tree[node_ix].item.body {
                     *is_tight = false;
                 }
             }
@@ -995,10 +1626,12 @@ impl<'a, 'b> FirstPass<'a, 'b> {
 
     /// Continue an existing list or start a new one if there's not an open
     /// list that matches.
-    fn continue_list(&mut self, start: usize, ch: u8, index: u64) {
+    fn continue_list(&mut self, start: usize, ch: u8, numbering: ListNumbering, index: u64) {
         if let Some(node_ix) = self.tree.peek_up() {
-            if let ItemBody::List(ref mut is_tight, existing_ch, _) = self.tree[node_ix].item.body {
-                if existing_ch == ch {
+            if let ItemBody::List(ref mut is_tight, existing_ch, existing_numbering, _) =
+                self.tree[node_ix].item.body
+            {
+                if existing_ch == ch && continues_numbering(existing_numbering, numbering, index) {
                     if self.last_line_blank {
                         *is_tight = false;
                         self.last_line_blank = false;
@@ -1012,7 +1645,7 @@ impl<'a, 'b> FirstPass<'a, 'b> {
         self.tree.append(Item {
             start,
             end: 0, // will get set later
-            body: ItemBody::List(true, ch, index),
+            body: ItemBody::List(true, ch, numbering, index),
         });
         self.tree.push();
         self.last_line_blank = false;
@@ -1233,19 +1866,18 @@ impl<'a, 'b> FirstPass<'a, 'b> {
 
         // scan title
         // if this fails but newline == 1, return also a refdef without title
-        if let Some((title_length, title)) = scan_refdef_title(&self.text[i..]) {
-            i += title_length;
-            backup.1.span = span_start..i;
-            backup.1.title = Some(unescape(title));
-        } else if newlines > 0 {
-            return Some(backup);
-        } else {
-            return None;
+        let (title_length, title) = match scan_refdef_title(&self.text[i..]) {
+            Some(title) => title,
+            None if newlines > 0 => return Some(backup),
+            None => return None,
         };
+        i += title_length;
 
         // scan EOL
         if let Some(bytes) = scan_blank_line(&bytes[i..]) {
             backup.0 = i + bytes - start;
+            backup.1.span = span_start..i;
+            backup.1.title = Some(unescape(title));
             Some(backup)
         } else if newlines > 0 {
             Some(backup)
@@ -1394,6 +2026,25 @@ fn get_html_end_tag(text_bytes: &[u8]) -> Option<&'static str> {
     }
 }
 
+/// Checks whether a list item numbered in the given style can continue a list
+/// with the existing numbering style.
+///
+/// Single letter markers are ambiguous between alphabetic and roman numbering.
+/// They are read as letters, except for `i`, but within an existing list the
+/// style of that list wins, so that both `h. i.` and `iv. v.` work.
+fn continues_numbering(existing: ListNumbering, numbering: ListNumbering, index: u64) -> bool {
+    match (existing, numbering) {
+        (ListNumbering::LowerAlpha, ListNumbering::LowerRoman)
+        | (ListNumbering::UpperAlpha, ListNumbering::UpperRoman) => index == 1,
+        (ListNumbering::LowerRoman, ListNumbering::LowerAlpha)
+        | (ListNumbering::UpperRoman, ListNumbering::UpperAlpha) => {
+            // c, d, l, m, v and x
+            matches!(index, 3 | 4 | 12 | 13 | 22 | 24)
+        }
+        _ => existing == numbering,
+    }
+}
+
 // https://english.stackexchange.com/a/285573
 fn surgerize_tight_list(tree: &mut Tree<Item>, list_ix: TreeIndex) {
     let mut list_item = tree[list_ix].child;
@@ -1403,15 +2054,15 @@ fn surgerize_tight_list(tree: &mut Tree<Item>, list_ix: TreeIndex) {
 
         // Check that list item has children - this is not necessarily the case!
         if let Some(firstborn_ix) = list_item_firstborn {
-            if let ItemBody::Paragraph = tree[firstborn_ix].item.body {
+            if let ItemBody::Paragraph(None) = tree[firstborn_ix].item.body {
                 tree[listitem_ix].child = tree[firstborn_ix].child;
             }
 
             let mut list_item_child = Some(firstborn_ix);
             let mut node_to_repoint = None;
             while let Some(child_ix) = list_item_child {
-                // surgerize paragraphs
-                let repoint_ix = if let ItemBody::Paragraph = tree[child_ix].item.body {
+                // surgerize paragraphs, except those that keep a block id
+                let repoint_ix = if let ItemBody::Paragraph(None) = tree[child_ix].item.body {
                     if let Some(child_firstborn) = tree[child_ix].child {
                         if let Some(repoint_ix) = node_to_repoint {
                             tree[repoint_ix].next = Some(child_firstborn);
@@ -1490,21 +2141,23 @@ fn delim_run_can_close(s: &str, suffix: &str, run_len: usize, ix: usize) -> bool
     next_char.is_whitespace() || is_punctuation(next_char)
 }
 
-fn create_lut(options: &Options) -> LookupTable {
+/// Creates the table of the bytes that can start inline markup, with the
+/// ASCII bytes of `triggers` in addition to the built-in ones.
+fn create_lut(options: &Options, triggers: &[u8]) -> LookupTable {
     #[cfg(all(target_arch = "x86_64", feature = "simd"))]
     {
         LookupTable {
-            simd: simd::compute_lookup(options),
-            scalar: special_bytes(options),
+            simd: simd::compute_lookup(options, triggers),
+            scalar: special_bytes(options, triggers),
         }
     }
     #[cfg(not(all(target_arch = "x86_64", feature = "simd")))]
     {
-        special_bytes(options)
+        special_bytes(options, triggers)
     }
 }
 
-fn special_bytes(options: &Options) -> [bool; 256] {
+fn special_bytes(options: &Options, triggers: &[u8]) -> [bool; 256] {
     let mut bytes = [false; 256];
     let standard_bytes = [
         b'\n', b'\r', b'*', b'_', b'&', b'\\', b'[', b']', b'<', b'!', b'`',
@@ -1524,6 +2177,15 @@ fn special_bytes(options: &Options) -> [bool; 256] {
             bytes[byte as usize] = true;
         }
     }
+    if options.contains(Options::ENABLE_COMMENTS) {
+        bytes[b'%' as usize] = true;
+    }
+    if options.contains(Options::ENABLE_HASHTAGS) {
+        bytes[b'#' as usize] = true;
+    }
+    for &byte in triggers.iter().filter(|byte| byte.is_ascii()) {
+        bytes[byte as usize] = true;
+    }
 
     bytes
 }
@@ -1729,13 +2391,14 @@ mod simd {
     use crate::Options;
     use core::arch::x86_64::*;
 
-    const VECTOR_SIZE: usize = std::mem::size_of::<__m128i>();
+    const VECTOR_SIZE: usize = core::mem::size_of::<__m128i>();
+    const AVX2_VECTOR_SIZE: usize = core::mem::size_of::<__m256i>();
 
     /// Generates a lookup table containing the bitmaps for our
     /// special marker bytes. This is effectively a 128 element 2d bitvector,
     /// that can be indexed by a four bit row index (the lower nibble)
     /// and a three bit column index (upper nibble).
-    pub(super) fn compute_lookup(options: &Options) -> [u8; 16] {
+    pub(super) fn compute_lookup(options: &Options, triggers: &[u8]) -> [u8; 16] {
         let mut lookup = [0u8; 16];
         let standard_bytes = [
             b'\n', b'\r', b'*', b'_', b'&', b'\\', b'[', b']', b'<', b'!', b'`',
@@ -1751,10 +2414,19 @@ mod simd {
             add_lookup_byte(&mut lookup, b'~');
         }
         if options.contains(Options::ENABLE_SMART_PUNCTUATION) {
-            for &byte in &[b'.', b'-', b'"', b'\''] {
+            for &byte in b".-\"'" {
                 add_lookup_byte(&mut lookup, byte);
             }
         }
+        if options.contains(Options::ENABLE_COMMENTS) {
+            add_lookup_byte(&mut lookup, b'%');
+        }
+        if options.contains(Options::ENABLE_HASHTAGS) {
+            add_lookup_byte(&mut lookup, b'#');
+        }
+        for &byte in triggers.iter().filter(|byte| byte.is_ascii()) {
+            add_lookup_byte(&mut lookup, byte);
+        }
 
         lookup
     }
@@ -1771,7 +2443,7 @@ mod simd {
     /// It is only safe to call this function when `bytes.len() >= ix + VECTOR_SIZE`.
     #[target_feature(enable = "ssse3")]
     #[inline]
-    unsafe fn compute_mask(lut: &[u8; 16], bytes: &[u8], ix: usize) -> i32 {
+    unsafe fn compute_mask(lut: &[u8; 16], bytes: &[u8], ix: usize) -> u32 {
         debug_assert!(bytes.len() >= ix + VECTOR_SIZE);
 
         let bitmap = _mm_loadu_si128(lut.as_ptr() as *const __m128i);
@@ -1803,7 +2475,33 @@ mod simd {
         let result = _mm_cmpeq_epi8(tmp, bitmask);
 
         // Return the resulting bitmask.
-        _mm_movemask_epi8(result)
+        _mm_movemask_epi8(result) as u32
+    }
+
+    /// Computes a bit mask like `compute_mask`, for the 32 bytes starting at the
+    /// given index. The 16 byte lookup tables are repeated for the upper half of
+    /// the vector, since AVX2 shuffles bytes within each half separately.
+    /// It is only safe to call this function when `bytes.len() >= ix + AVX2_VECTOR_SIZE`.
+    #[target_feature(enable = "avx2")]
+    #[inline]
+    unsafe fn compute_mask_avx2(lut: &[u8; 16], bytes: &[u8], ix: usize) -> u32 {
+        debug_assert!(bytes.len() >= ix + AVX2_VECTOR_SIZE);
+
+        let bitmap = _mm256_broadcastsi128_si256(_mm_loadu_si128(lut.as_ptr() as *const __m128i));
+        let bitmask_lookup = _mm256_setr_epi8(
+            1, 2, 4, 8, 16, 32, 64, -128, -1, -1, -1, -1, -1, -1, -1, -1, //
+            1, 2, 4, 8, 16, 32, 64, -128, -1, -1, -1, -1, -1, -1, -1, -1,
+        );
+
+        let raw_ptr = bytes.as_ptr().add(ix) as *const __m256i;
+        let input = _mm256_loadu_si256(raw_ptr);
+        let bitset = _mm256_shuffle_epi8(bitmap, input);
+        let higher_nibbles = _mm256_and_si256(_mm256_srli_epi16(input, 4), _mm256_set1_epi8(0x0f));
+        let bitmask = _mm256_shuffle_epi8(bitmask_lookup, higher_nibbles);
+        let tmp = _mm256_and_si256(bitset, bitmask);
+        let result = _mm256_cmpeq_epi8(tmp, bitmask);
+
+        _mm256_movemask_epi8(result) as u32
     }
 
     /// Calls callback on byte indices and their value.
@@ -1819,7 +2517,10 @@ mod simd {
     where
         F: FnMut(usize, u8) -> LoopInstruction<Option<T>>,
     {
-        if is_x86_feature_detected!("ssse3") && bytes.len() >= VECTOR_SIZE {
+        let width = crate::simd::width();
+        if width >= 256 && bytes.len() >= AVX2_VECTOR_SIZE {
+            unsafe { avx2_iterate_special_bytes(&lut.simd, bytes, ix, callback) }
+        } else if width >= 128 && bytes.len() >= VECTOR_SIZE {
             unsafe { simd_iterate_special_bytes(&lut.simd, bytes, ix, callback) }
         } else {
             super::scalar_iterate_special_bytes(&lut.scalar, bytes, ix, callback)
@@ -1831,7 +2532,7 @@ mod simd {
     /// Returns `Ok(ix)` to continue from index `ix`, `Err((end_ix, opt_val)` to break with
     /// final index `end_ix` and optional value `opt_val`.
     unsafe fn process_mask<F, T>(
-        mut mask: i32,
+        mut mask: u32,
         bytes: &[u8],
         mut offset: usize,
         callback: &mut F,
@@ -1845,7 +2546,7 @@ mod simd {
             match callback(offset, *bytes.get_unchecked(offset)) {
                 LoopInstruction::ContinueAndSkip(skip) => {
                     offset += skip + 1;
-                    mask >>= skip + 1 + mask_ix;
+                    mask = mask.checked_shr((skip + 1 + mask_ix) as u32).unwrap_or(0);
                 }
                 LoopInstruction::BreakAtWith(ix, val) => return Err((ix, val)),
             }
@@ -1857,29 +2558,69 @@ mod simd {
     /// Important: only call this function when `bytes.len() >= 16`. Doing
     /// so otherwise may exhibit undefined behaviour.
     unsafe fn simd_iterate_special_bytes<F, T>(
+        lut: &[u8; 16],
+        bytes: &[u8],
+        ix: usize,
+        callback: F,
+    ) -> (usize, Option<T>)
+    where
+        F: FnMut(usize, u8) -> LoopInstruction<Option<T>>,
+    {
+        iterate_vectors(lut, bytes, ix, callback, VECTOR_SIZE, compute_mask)
+    }
+
+    #[target_feature(enable = "avx2")]
+    /// Important: only call this function when `bytes.len() >= 32`. Doing
+    /// so otherwise may exhibit undefined behaviour.
+    unsafe fn avx2_iterate_special_bytes<F, T>(
+        lut: &[u8; 16],
+        bytes: &[u8],
+        ix: usize,
+        callback: F,
+    ) -> (usize, Option<T>)
+    where
+        F: FnMut(usize, u8) -> LoopInstruction<Option<T>>,
+    {
+        iterate_vectors(
+            lut,
+            bytes,
+            ix,
+            callback,
+            AVX2_VECTOR_SIZE,
+            compute_mask_avx2,
+        )
+    }
+
+    /// Walks the bytes in vectors of `vector_size` bytes, using `compute_mask`
+    /// to find the special bytes in each. Always inlined, so that the mask
+    /// computation is inlined into the callers with the matching target feature.
+    #[inline(always)]
+    unsafe fn iterate_vectors<F, T>(
         lut: &[u8; 16],
         bytes: &[u8],
         mut ix: usize,
         mut callback: F,
+        vector_size: usize,
+        compute_mask: unsafe fn(&[u8; 16], &[u8], usize) -> u32,
     ) -> (usize, Option<T>)
     where
         F: FnMut(usize, u8) -> LoopInstruction<Option<T>>,
     {
-        debug_assert!(bytes.len() >= VECTOR_SIZE);
-        let upperbound = bytes.len() - VECTOR_SIZE;
+        debug_assert!(bytes.len() >= vector_size);
+        let upperbound = bytes.len() - vector_size;
 
         while ix < upperbound {
             let mask = compute_mask(lut, bytes, ix);
             let block_start = ix;
             ix = match process_mask(mask, bytes, ix, &mut callback) {
-                Ok(ix) => std::cmp::max(ix, VECTOR_SIZE + block_start),
+                Ok(ix) => core::cmp::max(ix, vector_size + block_start),
                 Err((end_ix, val)) => return (end_ix, val),
             };
         }
 
         if bytes.len() > ix {
             // shift off the bytes at start we have already scanned
-            let mask = compute_mask(lut, bytes, upperbound) >> ix - upperbound;
+            let mask = compute_mask(lut, bytes, upperbound) >> (ix - upperbound);
             if let Err((end_ix, val)) = process_mask(mask, bytes, ix, &mut callback) {
                 return (end_ix, val);
             }
@@ -1890,10 +2631,46 @@ mod simd {
 
     #[cfg(test)]
     mod simd_test {
-        use super::super::create_lut;
-        use super::{iterate_special_bytes, LoopInstruction};
+        use super::super::{create_lut, scalar_iterate_special_bytes};
+        use super::{
+            avx2_iterate_special_bytes, simd_iterate_special_bytes, LoopInstruction,
+            AVX2_VECTOR_SIZE, VECTOR_SIZE,
+        };
         use crate::Options;
 
+        /// Returns the indices that each implementation that can run on this
+        /// processor finds in `bytes` with the given options and triggers.
+        fn special_indices(
+            opts: Options,
+            triggers: &[u8],
+            bytes: &[u8],
+            skip: usize,
+        ) -> Vec<Vec<usize>> {
+            let lut = create_lut(&opts, triggers);
+            let mut results = vec![];
+            for width in [0, 128, 256].iter() {
+                let mut indices = vec![];
+                let callback = |ix, _byte_ty| {
+                    indices.push(ix);
+                    LoopInstruction::ContinueAndSkip::<Option<i32>>(skip)
+                };
+                match width {
+                    0 => {
+                        scalar_iterate_special_bytes(&lut.scalar, bytes, 0, callback);
+                    }
+                    128 if is_x86_feature_detected!("ssse3") && bytes.len() >= VECTOR_SIZE => unsafe {
+                        simd_iterate_special_bytes(&lut.simd, bytes, 0, callback);
+                    },
+                    256 if is_x86_feature_detected!("avx2") && bytes.len() >= AVX2_VECTOR_SIZE => unsafe {
+                        avx2_iterate_special_bytes(&lut.simd, bytes, 0, callback);
+                    },
+                    _ => continue,
+                }
+                results.push(indices);
+            }
+            results
+        }
+
         fn check_expected_indices(bytes: &[u8], expected: &[usize], skip: usize) {
             let mut opts = Options::empty();
             opts.insert(Options::ENABLE_TABLES);
@@ -1901,15 +2678,9 @@ mod simd {
             opts.insert(Options::ENABLE_STRIKETHROUGH);
             opts.insert(Options::ENABLE_TASKLISTS);
 
-            let lut = create_lut(&opts);
-            let mut indices = vec![];
-
-            iterate_special_bytes::<_, i32>(&lut, bytes, 0, |ix, _byte_ty| {
-                indices.push(ix);
-                LoopInstruction::ContinueAndSkip(skip)
-            });
-
-            assert_eq!(&indices[..], expected);
+            for indices in special_indices(opts, &[], bytes, skip) {
+                assert_eq!(&indices[..], expected);
+            }
         }
 
         #[test]
@@ -1935,6 +2706,24 @@ mod simd {
         #[test]
         fn border_skip() {
             check_expected_indices("0123456789abcde~~~~d&f0".as_bytes(), &[15, 20], 3);
+            check_expected_indices(
+                "0123456789abcdef0123456789abcde~~~~d&f0123456789".as_bytes(),
+                &[31, 36],
+                3,
+            );
+            check_expected_indices(
+                "~0123456789abcdef0123456789abcdef0123456789~".as_bytes(),
+                &[0],
+                43,
+            );
+        }
+
+        #[test]
+        fn custom_triggers() {
+            let bytes = "0123456789 {x} é $ 0123456789".as_bytes();
+            for indices in special_indices(Options::empty(), b"{$\xc3", bytes, 0) {
+                assert_eq!(&indices[..], &[11, 18]);
+            }
         }
 
         #[test]
@@ -1952,6 +2741,16 @@ mod simd {
                         buf[6] = c;
 
                         check_expected_indices(&buf[..], &[3, 6], 0);
+
+                        // matches in both halves of an AVX2 vector and in the
+                        // overlapping last one
+                        let mut buf = [i; 40];
+                        buf[3] = c;
+                        buf[17] = c;
+                        buf[31] = c;
+                        buf[38] = c;
+
+                        check_expected_indices(&buf[..], &[3, 17, 31, 38], 0);
                     }
                 }
             }
@@ -20,13 +20,16 @@
 
 //! HTML renderer that takes an iterator of events as input.
 
-use std::collections::HashMap;
-use std::io::{self, Write};
+use alloc::collections::BTreeMap;
+use core::fmt;
 
-use crate::escape::{escape_href, escape_html, StrWrite, WriteWrapper};
+#[cfg(feature = "std")]
+use crate::escape::WriteWrapper;
+use crate::escape::{escape_href, escape_html, FmtWriter, StrWrite};
+use crate::prelude::*;
 use crate::strings::CowStr;
 use crate::Event::*;
-use crate::{Alignment, CodeBlockKind, Event, LinkType, Tag};
+use crate::{Alignment, CodeBlockKind, Event, LinkType, ListNumbering, Tag};
 
 enum TableState {
     Head,
@@ -46,7 +49,15 @@ struct HtmlWriter<'a, I, W> {
     table_state: TableState,
     table_alignments: Vec<Alignment>,
     table_cell_index: usize,
-    numbers: HashMap<CowStr<'a>, usize>,
+    numbers: BTreeMap<CowStr<'a>, usize>,
+
+    /// Tightness of the lists that are currently open.
+    tight_lists: Vec<bool>,
+    /// For each open list item, block quote and footnote definition, whether
+    /// paragraphs directly inside of it are rendered without `<p>` tags.
+    hidden_paragraphs: Vec<bool>,
+    /// Whether the next line of a line block is its first one.
+    first_line: bool,
 }
 
 impl<'a, I, W> HtmlWriter<'a, I, W>
@@ -62,19 +73,22 @@ where
             table_state: TableState::Head,
             table_alignments: vec![],
             table_cell_index: 0,
-            numbers: HashMap::new(),
+            numbers: BTreeMap::new(),
+            tight_lists: vec![],
+            hidden_paragraphs: vec![],
+            first_line: false,
         }
     }
 
     /// Writes a new line.
-    fn write_newline(&mut self) -> io::Result<()> {
+    fn write_newline(&mut self) -> Result<(), W::Error> {
         self.end_newline = true;
         self.writer.write_str("\n")
     }
 
     /// Writes a buffer, and tracks whether or not a newline was written.
     #[inline]
-    fn write(&mut self, s: &str) -> io::Result<()> {
+    fn write(&mut self, s: &str) -> Result<(), W::Error> {
         self.writer.write_str(s)?;
 
         if !s.is_empty() {
@@ -83,7 +97,7 @@ where
         Ok(())
     }
 
-    fn run(mut self) -> io::Result<()> {
+    fn run(mut self) -> Result<(), W::Error> {
         while let Some(event) = self.iter.next() {
             match event {
                 Start(tag) => {
@@ -104,6 +118,24 @@ where
                 Html(html) => {
                     self.write(&html)?;
                 }
+                Raw { format, content } => {
+                    if &*format == "html" {
+                        self.write(&content)?;
+                    }
+                }
+                Comment(_) | LinkDefinition { .. } => {}
+                Hashtag(tag) => {
+                    self.write("<span class=\"hashtag\">#")?;
+                    escape_html(&mut self.writer, &tag)?;
+                    self.write("</span>")?;
+                }
+                Custom(name, data) => {
+                    self.write("<span data-inline=\"")?;
+                    escape_html(&mut self.writer, &name)?;
+                    self.write("\">")?;
+                    escape_html(&mut self.writer, &data)?;
+                    self.write("</span>")?;
+                }
                 SoftBreak => {
                     self.write_newline()?;
                 }
@@ -137,15 +169,36 @@ where
         Ok(())
     }
 
+    /// Returns true when paragraphs in the current container belong to a
+    /// tight list item, and should therefore not be wrapped in `<p>` tags.
+    fn is_paragraph_hidden(&self) -> bool {
+        self.hidden_paragraphs.last().copied().unwrap_or(false)
+    }
+
+    /// Writes the `id` attribute for the block id of a paragraph or list item.
+    fn write_block_id(&mut self, block_id: Option<CowStr>) -> Result<(), W::Error> {
+        if let Some(id) = block_id {
+            self.write(" id=\"")?;
+            escape_html(&mut self.writer, &id)?;
+            self.write("\"")?;
+        }
+        Ok(())
+    }
+
     /// Writes the start of an HTML tag.
-    fn start_tag(&mut self, tag: Tag<'a>) -> io::Result<()> {
+    fn start_tag(&mut self, tag: Tag<'a>) -> Result<(), W::Error> {
         match tag {
-            Tag::Paragraph => {
+            Tag::Paragraph(block_id) => {
+                if self.is_paragraph_hidden() {
+                    return Ok(());
+                }
                 if self.end_newline {
-                    self.write("<p>")
+                    self.write("<p")?;
                 } else {
-                    self.write("\n<p>")
+                    self.write("\n<p")?;
                 }
+                self.write_block_id(block_id)?;
+                self.write(">")
             }
             Tag::Heading(level, id, classes) => {
                 if self.end_newline {
@@ -202,12 +255,41 @@ where
                 }
             }
             Tag::BlockQuote => {
+                self.hidden_paragraphs.push(false);
                 if self.end_newline {
                     self.write("<blockquote>\n")
                 } else {
                     self.write("\n<blockquote>\n")
                 }
             }
+            Tag::LineBlock => {
+                self.first_line = true;
+                if self.end_newline {
+                    self.write("<div class=\"line-block\">")
+                } else {
+                    self.write("\n<div class=\"line-block\">")
+                }
+            }
+            Tag::Custom(name, data) => {
+                if !self.end_newline {
+                    self.write_newline()?;
+                }
+                self.write("<div data-block=\"")?;
+                escape_html(&mut self.writer, &name)?;
+                if !data.is_empty() {
+                    self.write("\" data-info=\"")?;
+                    escape_html(&mut self.writer, &data)?;
+                }
+                self.write("\">\n")
+            }
+            Tag::Line => {
+                if self.first_line {
+                    self.first_line = false;
+                    Ok(())
+                } else {
+                    self.write("<br />\n")
+                }
+            }
             Tag::CodeBlock(info) => {
                 if !self.end_newline {
                     self.write_newline()?;
@@ -226,35 +308,45 @@ where
                     CodeBlockKind::Indented => self.write("<pre><code>"),
                 }
             }
-            Tag::List(Some(1)) => {
+            Tag::List(Some(start), numbering, _, is_tight) => {
+                self.tight_lists.push(is_tight);
                 if self.end_newline {
-                    self.write("<ol>\n")
+                    self.write("<ol")?;
                 } else {
-                    self.write("\n<ol>\n")
-                }
-            }
-            Tag::List(Some(start)) => {
-                if self.end_newline {
-                    self.write("<ol start=\"")?;
-                } else {
-                    self.write("\n<ol start=\"")?;
+                    self.write("\n<ol")?;
+                }
+                match numbering {
+                    ListNumbering::Decimal => {}
+                    ListNumbering::LowerAlpha => self.write(" type=\"a\"")?,
+                    ListNumbering::UpperAlpha => self.write(" type=\"A\"")?,
+                    ListNumbering::LowerRoman => self.write(" type=\"i\"")?,
+                    ListNumbering::UpperRoman => self.write(" type=\"I\"")?,
+                }
+                if start != 1 {
+                    self.write(" start=\"")?;
+                    write!(&mut self.writer, "{}", start)?;
+                    self.write("\"")?;
                 }
-                write!(&mut self.writer, "{}", start)?;
-                self.write("\">\n")
+                self.write(">\n")
             }
-            Tag::List(None) => {
+            Tag::List(None, _, _, is_tight) => {
+                self.tight_lists.push(is_tight);
                 if self.end_newline {
                     self.write("<ul>\n")
                 } else {
                     self.write("\n<ul>\n")
                 }
             }
-            Tag::Item => {
+            Tag::Item(block_id) => {
+                let is_tight = self.tight_lists.last().copied().unwrap_or(false);
+                self.hidden_paragraphs.push(is_tight);
                 if self.end_newline {
-                    self.write("<li>")
+                    self.write("<li")?;
                 } else {
-                    self.write("\n<li>")
+                    self.write("\n<li")?;
                 }
+                self.write_block_id(block_id)?;
+                self.write(">")
             }
             Tag::Emphasis => self.write("<em>"),
             Tag::Strong => self.write("<strong>"),
@@ -289,6 +381,7 @@ where
                 self.write("\" />")
             }
             Tag::FootnoteDefinition(name) => {
+                self.hidden_paragraphs.push(false);
                 if self.end_newline {
                     self.write("<div class=\"footnote-definition\" id=\"")?;
                 } else {
@@ -304,10 +397,12 @@ where
         }
     }
 
-    fn end_tag(&mut self, tag: Tag) -> io::Result<()> {
+    fn end_tag(&mut self, tag: Tag) -> Result<(), W::Error> {
         match tag {
-            Tag::Paragraph => {
-                self.write("</p>\n")?;
+            Tag::Paragraph(_) => {
+                if !self.is_paragraph_hidden() {
+                    self.write("</p>\n")?;
+                }
             }
             Tag::Heading(level, _id, _classes) => {
                 self.write("</")?;
@@ -336,18 +431,29 @@ where
                 self.table_cell_index += 1;
             }
             Tag::BlockQuote => {
+                self.hidden_paragraphs.pop();
                 self.write("</blockquote>\n")?;
             }
             Tag::CodeBlock(_) => {
                 self.write("</code></pre>\n")?;
             }
-            Tag::List(Some(_)) => {
+            Tag::LineBlock => {
+                self.write("</div>\n")?;
+            }
+            Tag::Line => {}
+            Tag::Custom(..) => {
+                self.write("</div>\n")?;
+            }
+            Tag::List(Some(_), ..) => {
+                self.tight_lists.pop();
                 self.write("</ol>\n")?;
             }
-            Tag::List(None) => {
+            Tag::List(None, ..) => {
+                self.tight_lists.pop();
                 self.write("</ul>\n")?;
             }
-            Tag::Item => {
+            Tag::Item(_) => {
+                self.hidden_paragraphs.pop();
                 self.write("</li>\n")?;
             }
             Tag::Emphasis => {
@@ -364,6 +470,7 @@ where
             }
             Tag::Image(_, _, _) => (), // shouldn't happen, handled in start
             Tag::FootnoteDefinition(_) => {
+                self.hidden_paragraphs.pop();
                 self.write("</div>\n")?;
             }
         }
@@ -371,7 +478,7 @@ where
     }
 
     // run raw text, consuming end tag
-    fn raw_text(&mut self) -> io::Result<()> {
+    fn raw_text(&mut self) -> Result<(), W::Error> {
         let mut nest = 0;
         while let Some(event) = self.iter.next() {
             match event {
@@ -396,6 +503,14 @@ where
                 }
                 TaskListMarker(true) => self.write("[x]")?,
                 TaskListMarker(false) => self.write("[ ]")?,
+                Hashtag(tag) => {
+                    self.write("#")?;
+                    escape_html(&mut self.writer, &tag)?;
+                }
+                Custom(_, data) => {
+                    escape_html(&mut self.writer, &data)?;
+                }
+                Raw { .. } | Comment(_) | LinkDefinition { .. } => (),
             }
         }
         Ok(())
@@ -469,10 +584,39 @@ where
 /// </ul>
 /// "#);
 /// ```
-pub fn write_html<'a, I, W>(writer: W, iter: I) -> io::Result<()>
+#[cfg(feature = "std")]
+pub fn write_html<'a, I, W>(writer: W, iter: I) -> std::io::Result<()>
 where
     I: Iterator<Item = Event<'a>>,
-    W: Write,
+    W: std::io::Write,
 {
     HtmlWriter::new(iter, WriteWrapper(writer)).run()
 }
+
+/// Iterate over an `Iterator` of `Event`s, generate HTML for each `Event`, and
+/// write it out to a `core::fmt::Write` sink, like a `fmt::Formatter`. Unlike
+/// [`write_html`], this is available without the `std` feature.
+///
+/// # Examples
+///
+/// ```
+/// use pulldown_cmark::{html, Parser};
+/// use std::fmt;
+///
+/// struct Rendered<'a>(&'a str);
+///
+/// impl fmt::Display for Rendered<'_> {
+///     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+///         html::write_html_fmt(f, Parser::new(self.0))
+///     }
+/// }
+///
+/// assert_eq!(Rendered("*hello*").to_string(), "<p><em>hello</em></p>\n");
+/// ```
+pub fn write_html_fmt<'a, I, W>(writer: W, iter: I) -> fmt::Result
+where
+    I: Iterator<Item = Event<'a>>,
+    W: fmt::Write,
+{
+    HtmlWriter::new(iter, FmtWriter(writer)).run()
+}
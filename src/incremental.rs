@@ -0,0 +1,582 @@
+//! Incremental parsing.
+//!
+//! Editors parse a document again after every keystroke, although most edits
+//! only change a block or two. A [`Document`] keeps the block structure found
+//! by the first pass of the parser, and [`Document::edit`] only parses the
+//! blocks around an edit again. The blocks after it are reused and moved to
+//! their new offsets. The document keeps the events of each top-level block,
+//! so only the inline markup of the new blocks is parsed, and reading the
+//! events with [`Document::events`] or [`Document::block_events`] doesn't
+//! parse anything.
+//!
+//! Reference definitions apply to the whole document, so an edit that adds,
+//! changes or removes one makes the document be parsed again from the start.
+//! Heading identifiers depend on the headings before them, so with
+//! [`Options::ENABLE_HEADING_IDS`] the headings after an edit are parsed again
+//! if the identifiers of the edited headings change, and all blocks are parsed
+//! again if a heading with an explicit identifier is edited. A table of
+//! contents shows all headings, so with [`Options::ENABLE_TOC`] all blocks are
+//! parsed again after each edit.
+//!
+//! # Example
+//! ```rust
+//! use pulldown_cmark::incremental::Document;
+//! use pulldown_cmark::{html, Options};
+//!
+//! let mut document = Document::new("# Title\n\nSome *text*.\n\n> quote\n", Options::empty());
+//! let change = document.edit(15..19, "more");
+//!
+//! assert_eq!("# Title\n\nSome *more*.\n\n> quote\n", document.text());
+//! assert_eq!(1..2, change.blocks);
+//! assert_eq!(1, change.replaced);
+//!
+//! let mut html_output = String::new();
+//! html::push_html(&mut html_output, document.events());
+//! assert_eq!(
+//!     "<h1>Title</h1>\n<p>Some <em>more</em>.</p>\n<blockquote>\n<p>quote</p>\n</blockquote>\n",
+//!     html_output
+//! );
+//! ```
+
+use core::cmp::max;
+use core::mem;
+use core::ops::Range;
+
+use crate::firstpass::run_first_pass_from;
+use crate::parse::{Allocations, Item, ItemBody};
+use crate::prelude::*;
+use crate::slug::HeadingIds;
+use crate::tree::{Tree, TreeIndex};
+use crate::{CowStr, Event, Options, Parser, Tag};
+
+/// A Markdown document that can be edited and parsed again incrementally.
+pub struct Document {
+    text: String,
+    options: Options,
+    tree: Tree<Item>,
+    allocs: Allocations<'static>,
+    // the top-level blocks, in order
+    blocks: Vec<TreeIndex>,
+    // the events of each top-level block, with their ranges
+    events: Vec<Vec<(Event<'static>, Range<usize>)>>,
+    // the starts of the lines at which no container block is open
+    boundaries: Vec<usize>,
+    // the number of nodes after the last full parse, to limit the garbage
+    // that replaced blocks leave in the tree
+    parsed_len: usize,
+}
+
+/// The headings of the top-level blocks that an edit replaces.
+struct ReplacedHeadings {
+    /// The identifiers of the headings, in order.
+    ids: Vec<CowStr<'static>>,
+    /// Whether any of the identifiers was given explicitly, which changes the
+    /// identifiers generated for the headings before it too.
+    explicit: bool,
+}
+
+/// The top-level blocks that were parsed again after an edit, see
+/// [`Document::edit`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    /// The indices of the new blocks, among the top-level blocks after the
+    /// edit. Blocks before and after these are unchanged, apart from their
+    /// offsets.
+    pub blocks: Range<usize>,
+    /// The number of top-level blocks before the edit that the new blocks
+    /// replace, starting at index `blocks.start`.
+    pub replaced: usize,
+    /// Whether the whole document was parsed again, because the edit affected
+    /// reference definitions.
+    pub full: bool,
+}
+
+impl Document {
+    /// Parses a document with the given options. Diagnostics aren't kept up
+    /// to date with edits, so [`Options::ENABLE_DIAGNOSTICS`] is ignored.
+    pub fn new(text: impl Into<String>, mut options: Options) -> Self {
+        options.remove(Options::ENABLE_DIAGNOSTICS);
+        let mut document = Document {
+            text: text.into(),
+            options,
+            tree: Tree::with_capacity(0),
+            allocs: Allocations::new(),
+            blocks: Vec::new(),
+            events: Vec::new(),
+            boundaries: Vec::new(),
+            parsed_len: 0,
+        };
+        document.parse();
+        document
+    }
+
+    /// Returns the text of the document.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Returns the options the document is parsed with.
+    pub fn options(&self) -> Options {
+        self.options
+    }
+
+    /// Returns the byte ranges of the top-level blocks of the document.
+    pub fn blocks(&self) -> impl ExactSizeIterator<Item = Range<usize>> + '_ {
+        (self.blocks.iter()).map(move |&ix| self.tree[ix].item.start..self.tree[ix].item.end)
+    }
+
+    /// Returns the events of the document, like a [`Parser`] with the same
+    /// options.
+    pub fn events(&self) -> impl Iterator<Item = Event<'static>> + '_ {
+        self.offset_events().map(|(event, _)| event)
+    }
+
+    /// Returns the events of the document with their byte ranges, like
+    /// [`Parser::into_offset_iter`].
+    pub fn offset_events(&self) -> impl Iterator<Item = (Event<'static>, Range<usize>)> + '_ {
+        self.events.iter().flatten().cloned()
+    }
+
+    /// Returns the events of the top-level block at the given index, with
+    /// their byte ranges.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index is out of bounds.
+    pub fn block_events(&self, block: usize) -> &[(Event<'static>, Range<usize>)] {
+        &self.events[block]
+    }
+
+    /// Replaces a byte range of the text by `new_text`, parses the blocks
+    /// around it again and returns which top-level blocks changed.
+    ///
+    /// Parsing starts at the block before the one the edit starts in, since
+    /// an edit can decide where that block ends, and stops at the first block
+    /// after the edit that starts in the same state as before.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of bounds, or if its start or end doesn't lie
+    /// on a `char` boundary.
+    pub fn edit(&mut self, range: Range<usize>, new_text: &str) -> Change {
+        self.text.replace_range(range.clone(), new_text);
+        let shift = |offset: usize| offset - range.end + range.start + new_text.len();
+
+        let preceding = self.boundaries.partition_point(|&b| b < range.start);
+        let start = match preceding.checked_sub(2) {
+            Some(ix) => self.boundaries[ix],
+            None => 0,
+        };
+        let kept = self
+            .blocks
+            .partition_point(|&ix| self.tree[ix].item.start < start);
+        let prev = kept.checked_sub(1).map(|ix| self.blocks[ix]);
+        if let Some(prev) = prev {
+            self.tree[prev].next = None;
+        }
+        self.tree.set_position((Vec::new(), prev));
+        let first_ix = self.tree.len();
+
+        let tree = core::mem::replace(&mut self.tree, Tree::with_capacity(0));
+        let allocs = core::mem::replace(&mut self.allocs, Allocations::new());
+        let old_boundaries = &self.boundaries;
+        let mut next = old_boundaries.partition_point(|&b| b < range.end);
+        let resync = |ix: usize| {
+            while next < old_boundaries.len() && shift(old_boundaries[next]) < ix {
+                next += 1;
+            }
+            next < old_boundaries.len() && shift(old_boundaries[next]) == ix
+        };
+        let (tree, allocs, reparse) =
+            run_first_pass_from(&self.text, self.options, tree, allocs, start, resync);
+        self.tree = tree;
+        self.allocs = allocs.into_owned();
+
+        // the end of the parsed part, before the edit
+        let old_end = if reparse.end < self.text.len() {
+            reparse.end + range.end - range.start - new_text.len()
+        } else {
+            usize::MAX
+        };
+        let reused = self
+            .blocks
+            .partition_point(|&ix| self.tree[ix].item.start < old_end);
+        let affects_refdefs = reparse.refdefs > 0
+            || (self.allocs.refdefs.0.values())
+                .any(|def| def.span.end > start && def.span.start < old_end);
+        if affects_refdefs || self.tree.len() > 2 * self.parsed_len {
+            let replaced = self.blocks.len();
+            self.parse();
+            return Change {
+                blocks: 0..self.blocks.len(),
+                replaced,
+                full: affects_refdefs,
+            };
+        }
+
+        let first = match prev {
+            Some(prev) => self.tree[prev].next,
+            None if self.tree.len() > first_ix => Some(TreeIndex::new(first_ix)),
+            None => None,
+        };
+        let mut new_blocks = Vec::new();
+        let mut ix = first;
+        while let Some(block) = ix {
+            new_blocks.push(block);
+            ix = self.tree[block].next;
+        }
+        if let Some(&last) = new_blocks.last().or(prev.as_ref()) {
+            self.tree[last].next = self.blocks.get(reused).copied();
+        }
+
+        let allocs = &mut self.allocs;
+        let mut explicit = false;
+        for &block in &self.blocks[kept..reused] {
+            for_each_node(&mut self.tree, block, |item| {
+                if let ItemBody::Heading(_, Some(heading_ix)) = item.body {
+                    explicit |= allocs[heading_ix].0.is_some();
+                    allocs.forget_heading_id(heading_ix);
+                }
+            });
+        }
+        let replaced_headings = ReplacedHeadings {
+            ids: heading_ids(&self.events[kept..reused]),
+            explicit,
+        };
+        for &block in &self.blocks[reused..] {
+            for_each_node(&mut self.tree, block, |item| {
+                item.start = shift(item.start);
+                item.end = shift(item.end);
+            });
+        }
+        for (_, range) in self.events[reused..].iter_mut().flatten() {
+            *range = shift(range.start)..shift(range.end);
+        }
+        for def in self.allocs.refdefs.0.values_mut() {
+            if def.span.start >= old_end {
+                def.span = shift(def.span.start)..shift(def.span.end);
+            }
+        }
+        let later_boundaries = old_boundaries.partition_point(|&b| b < old_end);
+        let later_boundaries = self.boundaries.split_off(later_boundaries);
+        self.boundaries.truncate(preceding.saturating_sub(2));
+        self.boundaries.extend(reparse.boundaries);
+        self.boundaries
+            .extend(later_boundaries.into_iter().map(shift));
+
+        let count = new_blocks.len();
+        let replaced = reused - kept;
+        self.blocks.splice(kept..reused, new_blocks);
+        self.events
+            .splice(kept..reused, (0..count).map(|_| Vec::new()));
+        if self.blocks.is_empty() {
+            self.parse();
+        } else {
+            self.move_first_block();
+            self.parse_inlines(kept..kept + count, Some(replaced_headings));
+        }
+        Change {
+            blocks: kept..kept + count,
+            replaced,
+            full: false,
+        }
+    }
+
+    /// Parses the whole document.
+    fn parse(&mut self) {
+        let capacity = max(128, self.text.len() / 32);
+        let (tree, allocs, reparse) = run_first_pass_from(
+            &self.text,
+            self.options,
+            Tree::with_capacity(capacity),
+            Allocations::new(),
+            0,
+            |_| false,
+        );
+        self.blocks.clear();
+        if tree.len() > 1 {
+            let mut ix = Some(TreeIndex::new(1));
+            while let Some(block) = ix {
+                self.blocks.push(block);
+                ix = tree[block].next;
+            }
+        }
+        self.tree = tree;
+        self.allocs = allocs.into_owned();
+        self.boundaries = reparse.boundaries;
+        self.events = vec![Vec::new(); self.blocks.len()];
+        self.parse_inlines(0..self.blocks.len(), None);
+        self.parsed_len = max(128, self.tree.len());
+    }
+
+    /// Parses the inline markup of the new top-level blocks in `range`, and
+    /// keeps their events. `replaced` holds the headings of the blocks they
+    /// replace, or is `None` if all blocks are new. With heading identifiers,
+    /// the events of other blocks are parsed again if they can change.
+    fn parse_inlines(&mut self, range: Range<usize>, replaced: Option<ReplacedHeadings>) {
+        if self.options.contains(Options::ENABLE_TOC) {
+            self.parse_inlines_with_ids(0..self.blocks.len(), None);
+        } else if self.options.contains(Options::ENABLE_HEADING_IDS) {
+            self.parse_inlines_with_ids(range, replaced);
+        } else {
+            self.parse_inlines_in_place(range);
+        }
+    }
+
+    /// Parses the inline markup of the top-level blocks in `range`, resolving
+    /// it in the tree itself, which the first pass doesn't look at again.
+    fn parse_inlines_in_place(&mut self, range: Range<usize>) {
+        let tree = mem::replace(&mut self.tree, Tree::with_capacity(0));
+        let allocs = mem::replace(&mut self.allocs, Allocations::new());
+        let parser = Parser::from_first_pass(&self.text, self.options, tree, allocs, None);
+        let mut iter = parser.into_offset_iter();
+        for ix in range {
+            self.events[ix] = into_static(iter.block_events(self.blocks[ix]));
+        }
+        let (tree, allocs) = iter.into_parts();
+        self.tree = tree;
+        self.allocs = allocs.into_owned();
+    }
+
+    /// Parses the inline markup of the top-level blocks in `range` with heading
+    /// identifiers, and of the blocks after them whose identifiers can change.
+    /// The tree is never resolved in place in this case, so that blocks can be
+    /// parsed again after later edits.
+    fn parse_inlines_with_ids(&mut self, range: Range<usize>, replaced: Option<ReplacedHeadings>) {
+        let allocs = &self.allocs;
+        let mut explicit = match &replaced {
+            Some(replaced) => replaced.explicit,
+            None => true,
+        };
+        for &block in &self.blocks[range.clone()] {
+            for_each_node(&mut self.tree, block, |item| {
+                if let ItemBody::Heading(_, Some(heading_ix)) = item.body {
+                    explicit |= allocs[heading_ix].0.is_some();
+                }
+            });
+        }
+        // Explicit identifiers are reserved before any are generated, so an
+        // edit to one affects the headings before it too.
+        let start = if explicit { 0 } else { range.start };
+
+        let allocs = self.allocs.borrowed();
+        let mut parser =
+            Parser::from_first_pass(&self.text, self.options, self.tree.clone(), allocs, None);
+        if self.options.contains(Options::ENABLE_TOC) {
+            // the outline needs the whole tree, which `block_events` cuts off
+            parser.outline();
+        }
+        let mut preceding = HeadingIds::default();
+        for id in heading_ids(&self.events[..start]) {
+            preceding.reserve(&id);
+        }
+        parser.continue_heading_ids(&mut preceding);
+        let mut iter = parser.into_offset_iter();
+        for ix in start..range.end {
+            self.events[ix] = into_static(iter.block_events(self.blocks[ix]));
+        }
+
+        if let Some(replaced) = replaced {
+            if !explicit && replaced.ids == heading_ids(&self.events[range.clone()]) {
+                return;
+            }
+        }
+        for ix in range.end..self.blocks.len() {
+            // blocks without headings don't depend on the identifiers
+            if explicit || !heading_ids(&self.events[ix..ix + 1]).is_empty() {
+                self.events[ix] = into_static(iter.block_events(self.blocks[ix]));
+            }
+        }
+    }
+
+    /// Moves the first top-level block to index one, where parsers expect it.
+    fn move_first_block(&mut self) {
+        let one = TreeIndex::new(1);
+        if self.blocks[0] == one {
+            return;
+        }
+        if let Some(pos) = self.blocks.iter().position(|&ix| ix == one) {
+            // the block at index one is still in use, so it moves too
+            let node = self.tree[one];
+            let copy = self.tree.create_node(node.item);
+            self.tree[copy].child = node.child;
+            self.tree[copy].next = node.next;
+            self.tree[self.blocks[pos - 1]].next = Some(copy);
+            self.blocks[pos] = copy;
+        }
+        self.tree[one] = self.tree[self.blocks[0]];
+        self.blocks[0] = one;
+    }
+}
+
+/// Converts events into ones that don't borrow from the text.
+fn into_static(events: Vec<(Event<'_>, Range<usize>)>) -> Vec<(Event<'static>, Range<usize>)> {
+    (events.into_iter())
+        .map(|(event, range)| (event.into_static(), range))
+        .collect()
+}
+
+/// Returns the identifiers of the headings in the events of some top-level
+/// blocks, in order.
+fn heading_ids(events: &[Vec<(Event<'static>, Range<usize>)>]) -> Vec<CowStr<'static>> {
+    let mut ids = Vec::new();
+    for (event, _) in events.iter().flatten() {
+        if let Event::Start(Tag::Heading(_, Some(id), _)) = event {
+            ids.push(id.clone());
+        }
+    }
+    ids
+}
+
+/// Calls `f` on the item of a node and of all nodes below it.
+fn for_each_node(tree: &mut Tree<Item>, root: TreeIndex, mut f: impl FnMut(&mut Item)) {
+    let mut stack = vec![root];
+    while let Some(ix) = stack.pop() {
+        f(&mut tree[ix].item);
+        if let Some(child) = tree[ix].child {
+            stack.push(child);
+        }
+        if ix != root {
+            if let Some(next) = tree[ix].next {
+                stack.push(next);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_same_as_full_parse(document: &Document) {
+        let expected = Parser::new_ext(document.text(), document.options())
+            .into_offset_iter()
+            .collect::<Vec<_>>();
+        let events = document.offset_events().collect::<Vec<_>>();
+        assert_eq!(expected, events, "{:?}", document.text());
+    }
+
+    #[test]
+    fn change() {
+        let mut document = Document::new("a\n\nb\n\n- c\n- d\n\ne\n", Options::empty());
+        assert_eq!(
+            vec![0..2, 3..5, 6..15, 15..17],
+            document.blocks().collect::<Vec<_>>()
+        );
+
+        let change = document.edit(12..13, "x\n\n# y");
+        // the paragraph after the list is parsed again, since it starts on a
+        // line that closes the list
+        assert_eq!(2..5, change.blocks);
+        assert_eq!(2, change.replaced);
+        assert!(!change.full);
+        assert_eq!(
+            vec![0..2, 3..5, 6..15, 15..19, 20..22],
+            document.blocks().collect::<Vec<_>>()
+        );
+        assert_same_as_full_parse(&document);
+
+        // the first block changes
+        let change = document.edit(0..0, "> ");
+        assert_eq!(0..1, change.blocks);
+        assert_eq!(1, change.replaced);
+        assert_same_as_full_parse(&document);
+
+        // a new reference definition changes other blocks
+        let change = document.edit(0..0, "[b]: /url\n\n[b]\n\n");
+        assert!(change.full);
+        assert_same_as_full_parse(&document);
+        document.edit(0..11, "");
+        assert_same_as_full_parse(&document);
+    }
+
+    #[test]
+    fn headings() {
+        let mut document = Document::new("[TOC]\n\n# One\n\n# Two\n", Options::ENABLE_TOC);
+        assert_same_as_full_parse(&document);
+        document.edit(14..14, "\n\n# One");
+        assert_same_as_full_parse(&document);
+
+        let mut document = Document::new("# a\n\ntext\n\n# a\n", Options::ENABLE_HEADING_IDS);
+        // the identifier of the last heading depends on the first one
+        document.edit(2..3, "b");
+        assert_same_as_full_parse(&document);
+        // and on explicit identifiers after it
+        document.edit(9..9, "\n\n# c {#a-1}");
+        assert_same_as_full_parse(&document);
+        document.edit(5..9, "more");
+        assert_same_as_full_parse(&document);
+    }
+
+    #[test]
+    fn random_edits() {
+        let snippets = [
+            "\n",
+            "\n\n",
+            "a",
+            "text ",
+            "# ",
+            "- ",
+            "1. ",
+            "> ",
+            "    ",
+            "```\n",
+            "~~~",
+            "---\n",
+            "===\n",
+            "*",
+            "_",
+            "`",
+            "[x]",
+            "[x]: /u\n",
+            "|a|b|\n|-|-|\n",
+            "<div>\n",
+            "<!--",
+            "-->",
+            "[^n]: x\n",
+            "[^n]",
+            " {#id .c}",
+            " ^id",
+            "[TOC]\n",
+            "\r\n",
+            "é",
+        ];
+        let mut seed = 0x2545_f491_u32;
+        let mut random = |n: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as usize % n
+        };
+        // without heading ids, the inline content is resolved in the tree
+        let options = Options::all();
+        let without_toc = options - Options::ENABLE_TOC;
+        for &options in [
+            options,
+            without_toc,
+            without_toc - Options::ENABLE_HEADING_IDS,
+        ]
+        .iter()
+        {
+            let mut document = Document::new("", options);
+            for _ in 0..2000 {
+                let text = document.text();
+                let mut start = random(text.len() + 1);
+                while !text.is_char_boundary(start) {
+                    start -= 1;
+                }
+                let mut end = start + random(8);
+                while end > text.len() || !text.is_char_boundary(end) {
+                    end -= 1;
+                }
+                let new_text = if random(3) == 0 {
+                    ""
+                } else {
+                    snippets[random(snippets.len())]
+                };
+                document.edit(start..end, new_text);
+                assert_same_as_full_parse(&document);
+            }
+        }
+    }
+}
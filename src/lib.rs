@@ -46,6 +46,14 @@
 //! let expected_html = "<p>Hello world, this is a <del>complicated</del> <em>very simple</em> example.</p>\n";
 //! assert_eq!(expected_html, &html_output);
 //! ```
+//!
+//! # `no_std` support
+//!
+//! The `std` feature is enabled by default. Without it, the crate only depends
+//! on `core` and `alloc`. The parser and the HTML renderer are still available,
+//! the latter writing to a [`core::fmt::Write`] sink through
+//! [`html::write_html_fmt`]. Everything that reads from or writes to
+//! `std::io`, like the [`stream`] module, requires `std`.
 
 // When compiled for the rustc compiler itself we want to make sure that this is
 // an unstable crate.
@@ -53,25 +61,58 @@
 #![cfg_attr(rustbuild, unstable(feature = "rustc_private", issue = "27812"))]
 // Forbid unsafe code unless the SIMD feature is enabled.
 #![cfg_attr(not(feature = "simd"), forbid(unsafe_code))]
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
+pub mod ast;
+pub mod cst;
 pub mod html;
+pub mod incremental;
+pub mod line_index;
+pub mod markdown;
 
+mod diagnostic;
 mod entities;
 pub mod escape;
+mod extension;
 mod firstpass;
+mod limits;
 mod linklabel;
+#[cfg(feature = "parallel")]
+pub mod parallel;
 mod parse;
+mod prelude;
 mod puncttable;
 mod scanners;
+#[cfg(all(target_arch = "x86_64", feature = "simd"))]
+mod simd;
+mod slug;
+#[cfg(feature = "std")]
+pub mod stream;
 mod strings;
+pub mod toc;
 mod tree;
 
-use std::{convert::TryFrom, fmt::Display};
+use core::{convert::TryFrom, fmt::Display};
 
-pub use crate::parse::{BrokenLink, BrokenLinkCallback, LinkDef, OffsetIter, Parser, RefDefs};
+use crate::prelude::*;
+
+pub use crate::diagnostic::{Diagnostic, DiagnosticCode};
+pub use crate::extension::{
+    BlockContext, BlockLine, BlockParser, BlockStart, InlineNode, InlineParser,
+};
+pub use crate::limits::{ParseError, ParseLimits};
+pub use crate::parse::{
+    BrokenLink, BrokenLinkCallback, LinkDef, OffsetIter, Parser, ParserPool, RefDefs,
+};
+#[cfg(all(target_arch = "x86_64", feature = "bench-simd-width"))]
+#[doc(hidden)]
+pub use crate::simd::set_max_simd_width;
+pub use crate::slug::{GitHubSlugifier, Slugifier};
 pub use crate::strings::{CowStr, InlineStr};
 
 /// Codeblock kind.
@@ -92,28 +133,92 @@ impl<'a> CodeBlockKind<'a> {
     pub fn is_fenced(&self) -> bool {
         matches!(*self, CodeBlockKind::Fenced(_))
     }
+
+    /// Converts the kind into one that doesn't borrow from the input.
+    pub fn into_static(self) -> CodeBlockKind<'static> {
+        match self {
+            CodeBlockKind::Indented => CodeBlockKind::Indented,
+            CodeBlockKind::Fenced(info) => CodeBlockKind::Fenced(info.into_static()),
+        }
+    }
+}
+
+/// The marker character of a list, as written in the source.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ListMarker {
+    /// Bullet list item like `- foo`
+    Dash,
+    /// Bullet list item like `* foo`
+    Star,
+    /// Bullet list item like `+ foo`
+    Plus,
+    /// Ordered list item like `1. foo`
+    Period,
+    /// Ordered list item like `1) foo`
+    OneParen,
+    /// Ordered list item like `(1) foo`. Only recognized with
+    /// [`Options::ENABLE_FANCY_LISTS`].
+    TwoParens,
+}
+
+impl ListMarker {
+    /// Returns whether the marker is one of an ordered list, like `1.`.
+    pub fn is_ordered(&self) -> bool {
+        matches!(
+            *self,
+            ListMarker::Period | ListMarker::OneParen | ListMarker::TwoParens
+        )
+    }
+}
+
+/// The numbering style of an ordered list. Anything other than `Decimal` is
+/// only recognized with [`Options::ENABLE_FANCY_LISTS`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ListNumbering {
+    /// `1.`, `2.`, `3.`. This is also the numbering style of bullet lists.
+    Decimal,
+    /// `a.`, `b.`, `c.`
+    LowerAlpha,
+    /// `A.`, `B.`, `C.`
+    UpperAlpha,
+    /// `i.`, `ii.`, `iii.`
+    LowerRoman,
+    /// `I.`, `II.`, `III.`
+    UpperRoman,
 }
 
 /// Tags for elements that can contain other elements.
 #[derive(Clone, Debug, PartialEq)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Tag<'a> {
-    /// A paragraph of text and other inline elements.
-    Paragraph,
+    /// A paragraph of text and other inline elements. Contains the block
+    /// identifier given by a trailing `^id`, see [`Options::ENABLE_BLOCK_IDS`].
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    Paragraph(Option<CowStr<'a>>),
 
     /// A heading. The first field indicates the level of the heading,
     /// the second the fragment identifier, and the third the classes.
-    Heading(HeadingLevel, Option<&'a str>, Vec<&'a str>),
+    Heading(
+        HeadingLevel,
+        #[cfg_attr(feature = "serde", serde(borrow))] Option<CowStr<'a>>,
+        #[cfg_attr(feature = "serde", serde(borrow))] Vec<CowStr<'a>>,
+    ),
 
     BlockQuote,
     /// A code block.
     CodeBlock(CodeBlockKind<'a>),
 
-    /// A list. If the list is ordered the field indicates the number of the first item.
+    /// A list. If the list is ordered the first field indicates the number of the first item,
+    /// and the second how the items are numbered. The third field is the marker used by the
+    /// items of the list, and the fourth is true when the list is tight.
     /// Contains only list items.
-    List(Option<u64>), // TODO: add delim and tight for ast (not needed for html)
-    /// A list item.
-    Item,
+    List(Option<u64>, ListNumbering, ListMarker, bool),
+    /// A list item. Contains the block identifier given by a trailing `^id` at
+    /// the end of its first paragraph, see [`Options::ENABLE_BLOCK_IDS`].
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    Item(Option<CowStr<'a>>),
     /// A footnote definition. The value contained is the footnote's label by which it can
     /// be referred to.
     #[cfg_attr(feature = "serde", serde(borrow))]
@@ -128,6 +233,19 @@ pub enum Tag<'a> {
     TableRow,
     TableCell,
 
+    /// A line block, in which line breaks are significant. Contains only `Line`s.
+    LineBlock,
+    /// A line of a line block. Leading spaces are kept as non-breaking spaces.
+    Line,
+
+    /// A block recognized by a [`BlockParser`]. The first field is the name of
+    /// the block and the second its data, see [`BlockStart`]. Contains the
+    /// text of the block.
+    Custom(
+        #[cfg_attr(feature = "serde", serde(borrow))] CowStr<'a>,
+        #[cfg_attr(feature = "serde", serde(borrow))] CowStr<'a>,
+    ),
+
     // span-level tags
     Emphasis,
     Strong,
@@ -140,6 +258,39 @@ pub enum Tag<'a> {
     Image(LinkType, CowStr<'a>, CowStr<'a>),
 }
 
+impl<'a> Tag<'a> {
+    /// Converts the tag into one that doesn't borrow from the input.
+    pub fn into_static(self) -> Tag<'static> {
+        match self {
+            Tag::Paragraph(id) => Tag::Paragraph(id.map(CowStr::into_static)),
+            Tag::Heading(level, id, classes) => Tag::Heading(
+                level,
+                id.map(CowStr::into_static),
+                classes.into_iter().map(CowStr::into_static).collect(),
+            ),
+            Tag::BlockQuote => Tag::BlockQuote,
+            Tag::CodeBlock(kind) => Tag::CodeBlock(kind.into_static()),
+            Tag::List(start, numbering, marker, tight) => {
+                Tag::List(start, numbering, marker, tight)
+            }
+            Tag::Item(id) => Tag::Item(id.map(CowStr::into_static)),
+            Tag::FootnoteDefinition(label) => Tag::FootnoteDefinition(label.into_static()),
+            Tag::Table(alignments) => Tag::Table(alignments),
+            Tag::TableHead => Tag::TableHead,
+            Tag::TableRow => Tag::TableRow,
+            Tag::TableCell => Tag::TableCell,
+            Tag::LineBlock => Tag::LineBlock,
+            Tag::Line => Tag::Line,
+            Tag::Custom(name, data) => Tag::Custom(name.into_static(), data.into_static()),
+            Tag::Emphasis => Tag::Emphasis,
+            Tag::Strong => Tag::Strong,
+            Tag::Strikethrough => Tag::Strikethrough,
+            Tag::Link(ty, url, title) => Tag::Link(ty, url.into_static(), title.into_static()),
+            Tag::Image(ty, url, title) => Tag::Image(ty, url.into_static(), title.into_static()),
+        }
+    }
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash, Debug)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum HeadingLevel {
@@ -152,7 +303,7 @@ pub enum HeadingLevel {
 }
 
 impl Display for HeadingLevel {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Self::H1 => write!(f, "h1"),
             Self::H2 => write!(f, "h2"),
@@ -256,6 +407,73 @@ pub enum Event<'a> {
     Rule,
     /// A task list marker, rendered as a checkbox in HTML. Contains a true when it is checked.
     TaskListMarker(bool),
+    /// Content that is only meant for one output format, given by a code block or
+    /// inline code marked with `{=format}`.
+    Raw {
+        /// The output format, like `html`.
+        #[cfg_attr(feature = "serde", serde(borrow))]
+        format: CowStr<'a>,
+        /// The content, which is passed through as is to that format.
+        #[cfg_attr(feature = "serde", serde(borrow))]
+        content: CowStr<'a>,
+    },
+    /// The text of a comment. Only emitted with [`Options::ENABLE_COMMENT_EVENTS`].
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    Comment(CowStr<'a>),
+    /// A `#tag` in inline text. Contains the tag without the leading `#`, which
+    /// may be nested like `project/alpha`. Only emitted with [`Options::ENABLE_HASHTAGS`].
+    #[cfg_attr(feature = "serde", serde(borrow))]
+    Hashtag(CowStr<'a>),
+    /// An inline element recognized by an [`InlineParser`]. Contains its name
+    /// and data, see [`InlineNode`].
+    Custom(
+        #[cfg_attr(feature = "serde", serde(borrow))] CowStr<'a>,
+        #[cfg_attr(feature = "serde", serde(borrow))] CowStr<'a>,
+    ),
+    /// A link reference definition. Definitions with a label that is already
+    /// defined are included too. Only emitted with
+    /// [`Options::ENABLE_LINK_DEFINITION_EVENTS`].
+    LinkDefinition {
+        /// The label, with its whitespace collapsed.
+        #[cfg_attr(feature = "serde", serde(borrow))]
+        label: CowStr<'a>,
+        /// The destination.
+        #[cfg_attr(feature = "serde", serde(borrow))]
+        dest: CowStr<'a>,
+        /// The title, if the definition has one, which can be empty.
+        #[cfg_attr(feature = "serde", serde(borrow))]
+        title: Option<CowStr<'a>>,
+    },
+}
+
+impl<'a> Event<'a> {
+    /// Converts the event into one that doesn't borrow from the input.
+    pub fn into_static(self) -> Event<'static> {
+        match self {
+            Event::Start(tag) => Event::Start(tag.into_static()),
+            Event::End(tag) => Event::End(tag.into_static()),
+            Event::Text(text) => Event::Text(text.into_static()),
+            Event::Code(text) => Event::Code(text.into_static()),
+            Event::Html(html) => Event::Html(html.into_static()),
+            Event::FootnoteReference(label) => Event::FootnoteReference(label.into_static()),
+            Event::SoftBreak => Event::SoftBreak,
+            Event::HardBreak => Event::HardBreak,
+            Event::Rule => Event::Rule,
+            Event::TaskListMarker(checked) => Event::TaskListMarker(checked),
+            Event::Raw { format, content } => Event::Raw {
+                format: format.into_static(),
+                content: content.into_static(),
+            },
+            Event::Comment(text) => Event::Comment(text.into_static()),
+            Event::Hashtag(tag) => Event::Hashtag(tag.into_static()),
+            Event::Custom(name, data) => Event::Custom(name.into_static(), data.into_static()),
+            Event::LinkDefinition { label, dest, title } => Event::LinkDefinition {
+                label: label.into_static(),
+                dest: dest.into_static(),
+                title: title.map(CowStr::into_static),
+            },
+        }
+    }
 }
 
 /// Table column text alignment.
@@ -285,5 +503,83 @@ bitflags::bitflags! {
         /// with the content `text`, ID `id`, and classes `class1` and `class2`.
         /// Note that attributes (ID and classes) should be space-separeted.
         const ENABLE_HEADING_ATTRIBUTES = 1 << 6;
+        /// Keep the paragraphs inside the items of tight lists.
+        ///
+        /// By default, paragraphs that are direct children of a tight list item
+        /// are removed, so that only their inline content is emitted. With this
+        /// option they are emitted as `Tag::Paragraph` like everywhere else. The
+        /// HTML renderer still omits the `<p>` tags for them.
+        const ENABLE_TIGHT_LIST_PARAGRAPHS = 1 << 7;
+        /// Pandoc-style "fancy lists".
+        ///
+        /// Besides decimal numbers, ordered list items can be numbered with single
+        /// letters (`a.`, `B)`) and roman numerals (`iv.`), and the number can be
+        /// enclosed in parentheses (`(1)`). A change of numbering style or marker
+        /// starts a new list. Capital letters followed by a period must be followed
+        /// by at least two spaces, so that initials like `B. Russell` don't start a
+        /// list.
+        const ENABLE_FANCY_LISTS = 1 << 8;
+        /// Generate a fragment identifier for every heading that doesn't have one
+        /// given with [`ENABLE_HEADING_ATTRIBUTES`](Self::ENABLE_HEADING_ATTRIBUTES).
+        ///
+        /// Identifiers are made from the plain text of the heading by a [`Slugifier`],
+        /// which defaults to [`GitHubSlugifier`]. Identifiers that are already in use
+        /// get a `-1`, `-2`, etc. suffix.
+        const ENABLE_HEADING_IDS = 1 << 9;
+        /// Replace a paragraph consisting of just `[TOC]` by a table of contents: a
+        /// nested bullet list of links to the headings of the document, as returned
+        /// by [`Parser::table_of_contents`]. Only the first eight placeholders are
+        /// replaced, since each one copies all headings. Implies
+        /// [`ENABLE_HEADING_IDS`](Self::ENABLE_HEADING_IDS).
+        const ENABLE_TOC = 1 << 10;
+        /// Pandoc-style raw attributes.
+        ///
+        /// A fenced code block whose info string is `{=format}`, or inline code
+        /// directly followed by `{=format}` (`` `<br>`{=html} ``), is emitted as
+        /// [`Event::Raw`] instead of a code block or code span. The HTML renderer
+        /// writes the content of the `html` format verbatim and drops all others.
+        const ENABLE_RAW_ATTRIBUTES = 1 << 11;
+        /// Pandoc-style line blocks, for verse and addresses.
+        ///
+        /// Consecutive lines starting with `| ` form a [`Tag::LineBlock`], in which
+        /// each line is a [`Tag::Line`]. A line starting with a space instead
+        /// continues the previous one. With [`ENABLE_TABLES`](Self::ENABLE_TABLES),
+        /// a line followed by a table delimiter row starts a table instead.
+        const ENABLE_LINE_BLOCKS = 1 << 12;
+        /// Obsidian-style comments, which are left out of the output.
+        ///
+        /// Text between a pair of `%%` in a paragraph is a comment. A block
+        /// starting with `%%` and ending with `%%` at the end of a line, possibly
+        /// spanning several lines, is a comment block.
+        const ENABLE_COMMENTS = 1 << 13;
+        /// Treat HTML comments like the comments of
+        /// [`ENABLE_COMMENTS`](Self::ENABLE_COMMENTS), so they are left out of the
+        /// output instead of being passed through as [`Event::Html`]. Only comments
+        /// that are inline HTML or start an HTML block are recognized.
+        const ENABLE_STRIP_HTML_COMMENTS = 1 << 14;
+        /// Emit comments as [`Event::Comment`] instead of leaving them out, for
+        /// example to find notes left for authors. The HTML renderer ignores them.
+        const ENABLE_COMMENT_EVENTS = 1 << 15;
+        /// Recognize `#tag` and `#nested/tag` in inline text and emit them as
+        /// [`Event::Hashtag`]. A tag must start a word and may not consist of digits
+        /// only, so `#123` and the fragment in `page#intro` stay text. Tags are not
+        /// recognized in code, URLs or HTML.
+        const ENABLE_HASHTAGS = 1 << 16;
+        /// Recognize block identifiers like `^abc-123` at the end of a paragraph,
+        /// separated from its text by whitespace. The marker is removed from the
+        /// text and the identifier is given to the [`Tag::Paragraph`], or to the
+        /// [`Tag::Item`] when the paragraph is the first in a list item, so that
+        /// links like `Page#^abc-123` can point at the block. Other paragraphs of
+        /// tight list items keep their tags when they have an identifier.
+        const ENABLE_BLOCK_IDS = 1 << 17;
+        /// Collect [`Diagnostic`]s about markup that probably doesn't parse as
+        /// intended, like unclosed emphasis or links to undefined references.
+        /// They are available from [`Parser::diagnostics`] while parsing, and
+        /// complete once all events have been read.
+        const ENABLE_DIAGNOSTICS = 1 << 18;
+        /// Emit link reference definitions as [`Event::LinkDefinition`] where
+        /// they are defined, so that they can be kept in place or highlighted.
+        /// The HTML renderer ignores them.
+        const ENABLE_LINK_DEFINITION_EVENTS = 1 << 19;
     }
 }
@@ -0,0 +1,369 @@
+//! Limits on the resources a parser uses for a document.
+
+use core::fmt;
+
+use crate::parse::{Allocations, Item, ItemBody};
+use crate::prelude::*;
+use crate::tree::{Tree, TreeIndex};
+
+/// Limits on the size and structure of a document, for parsing untrusted
+/// input with [`Parser::try_new`](crate::Parser::try_new) or
+/// [`Parser::with_limits`](crate::Parser::with_limits).
+///
+/// The limits on the input and its block structure are checked when the parser
+/// is created, which fails with a [`ParseError`] if one is exceeded. Inline
+/// elements are only parsed while the events are read, and are kept within the
+/// limits as they are built: an element that would be nested too deeply, or an
+/// autolink that would add a node over `max_nodes`, is left as text. The work
+/// spent on the inline elements of a block grows with its length, which
+/// `max_input_bytes` bounds.
+///
+/// By default, nothing is limited.
+///
+/// # Example
+/// ```rust
+/// use pulldown_cmark::{Options, ParseError, ParseLimits, Parser};
+///
+/// let limits = ParseLimits {
+///     max_block_depth: 2,
+///     ..ParseLimits::default()
+/// };
+/// assert!(Parser::try_new("> > a", Options::empty(), limits).is_ok());
+/// assert_eq!(
+///     Some(ParseError::BlockNestingTooDeep(4)),
+///     Parser::try_new("> > > a", Options::empty(), limits).err()
+/// );
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ParseLimits {
+    /// The maximum length of the input in bytes.
+    pub max_input_bytes: usize,
+    /// The maximum number of block quotes and list items that can be nested
+    /// in each other.
+    pub max_block_depth: usize,
+    /// The maximum number of inline elements like emphasis and links that can
+    /// be nested in each other. The markup of elements that would be nested
+    /// deeper is left as text.
+    pub max_inline_depth: usize,
+    /// The maximum number of nodes the document is made of, including the text
+    /// and markup of inline content that is yet to be parsed. An error is
+    /// returned if the block structure exceeds it, and the inline elements that
+    /// would add nodes over it are left as text.
+    pub max_nodes: usize,
+    /// The maximum number of link reference definitions.
+    pub max_reference_definitions: usize,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits {
+            max_input_bytes: usize::MAX,
+            max_block_depth: usize::MAX,
+            max_inline_depth: usize::MAX,
+            max_nodes: usize::MAX,
+            max_reference_definitions: usize::MAX,
+        }
+    }
+}
+
+/// The limit of [`ParseLimits`] the block structure of a document exceeds.
+/// Contains the offset at which it is exceeded, where there is one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ParseError {
+    /// The input is longer than `max_input_bytes`.
+    InputTooLarge,
+    /// A block quote or list item is nested deeper than `max_block_depth`.
+    BlockNestingTooDeep(usize),
+    /// The document has more than `max_nodes` nodes.
+    TooManyNodes(usize),
+    /// The document has more than `max_reference_definitions` link reference
+    /// definitions.
+    TooManyDefinitions(usize),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::InputTooLarge => f.write_str("input is too large"),
+            ParseError::BlockNestingTooDeep(ix) => {
+                write!(f, "blocks are nested too deeply at offset {}", ix)
+            }
+            ParseError::TooManyNodes(ix) => write!(f, "too many nodes at offset {}", ix),
+            ParseError::TooManyDefinitions(ix) => {
+                write!(f, "too many reference definitions at offset {}", ix)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
+impl ParseError {
+    /// Returns the offset at which the limit is exceeded.
+    fn offset(&self) -> usize {
+        match *self {
+            ParseError::InputTooLarge => 0,
+            ParseError::BlockNestingTooDeep(ix)
+            | ParseError::TooManyNodes(ix)
+            | ParseError::TooManyDefinitions(ix) => ix,
+        }
+    }
+}
+
+/// Checks the block structure that the first pass found against the limits,
+/// and returns the error for the limit that is exceeded first in the document.
+/// The tree can end after that point, when the first pass stopped early.
+pub(crate) fn check_blocks(
+    text: &str,
+    tree: &Tree<Item>,
+    allocs: &Allocations<'_>,
+    limits: &ParseLimits,
+) -> Result<(), ParseError> {
+    if text.len() > limits.max_input_bytes {
+        return Err(ParseError::InputTooLarge);
+    }
+    let mut errors = Vec::new();
+    if let Some(ix) = first_nested_too_deeply(tree, limits.max_block_depth) {
+        errors.push(ParseError::BlockNestingTooDeep(tree[ix].item.start));
+    }
+    if tree.len() - 1 > limits.max_nodes {
+        let ix = TreeIndex::new(limits.max_nodes + 1);
+        errors.push(ParseError::TooManyNodes(tree[ix].item.start));
+    }
+    if allocs.refdefs.0.len() > limits.max_reference_definitions {
+        let mut starts = (allocs.refdefs.0.values())
+            .map(|def| def.span.start)
+            .collect::<Vec<_>>();
+        starts.sort_unstable();
+        let start = starts[limits.max_reference_definitions];
+        errors.push(ParseError::TooManyDefinitions(start));
+    }
+    match errors.into_iter().min_by_key(ParseError::offset) {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+/// Returns the first block quote or list item in the document that is nested in
+/// more than `max_depth` others.
+fn first_nested_too_deeply(tree: &Tree<Item>, max_depth: usize) -> Option<TreeIndex> {
+    if max_depth == usize::MAX || tree.is_empty() {
+        return None;
+    }
+    let mut stack = vec![(TreeIndex::new(1), 0)];
+    while let Some((ix, depth)) = stack.pop() {
+        let node = &tree[ix];
+        let inner = match node.item.body {
+            ItemBody::BlockQuote | ItemBody::ListItem(..) if depth == max_depth => return Some(ix),
+            ItemBody::BlockQuote | ItemBody::ListItem(..) => depth + 1,
+            _ => depth,
+        };
+        if let Some(next) = node.next {
+            stack.push((next, depth));
+        }
+        if let Some(child) = node.child {
+            stack.push((child, inner));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::*;
+    use crate::{html, Event, Options, Parser, Tag};
+
+    fn try_parse(text: &str, limits: ParseLimits) -> Result<String, ParseError> {
+        let mut s = String::new();
+        html::push_html(&mut s, Parser::try_new(text, Options::empty(), limits)?);
+        Ok(s)
+    }
+
+    #[test]
+    fn unlimited() {
+        let text = "> - a *b [c](/d)*\n>\n>   [e]: /f\n";
+        let mut expected = String::new();
+        html::push_html(&mut expected, Parser::new(text));
+        assert_eq!(Ok(expected), try_parse(text, ParseLimits::default()));
+    }
+
+    #[test]
+    fn block_limits() {
+        let limits = ParseLimits {
+            max_input_bytes: 12,
+            ..ParseLimits::default()
+        };
+        assert!(try_parse("twelve bytes", limits).is_ok());
+        assert_eq!(
+            Err(ParseError::InputTooLarge),
+            try_parse("thirteen byte", limits)
+        );
+
+        let limits = ParseLimits {
+            max_block_depth: 2,
+            ..ParseLimits::default()
+        };
+        assert!(try_parse("- > a\n\n> > b\n", limits).is_ok());
+        assert_eq!(
+            Err(ParseError::BlockNestingTooDeep(8)),
+            try_parse("> a\n> - > b\n", limits)
+        );
+
+        let limits = ParseLimits {
+            max_nodes: 4,
+            ..ParseLimits::default()
+        };
+        assert!(try_parse("a\n\nb\n", limits).is_ok());
+        assert_eq!(
+            Err(ParseError::TooManyNodes(6)),
+            try_parse("a\n\nb\n\n*c*\n", limits)
+        );
+
+        let limits = ParseLimits {
+            max_reference_definitions: 1,
+            ..ParseLimits::default()
+        };
+        assert!(try_parse("[a]: /a\n", limits).is_ok());
+        assert_eq!(
+            Err(ParseError::TooManyDefinitions(8)),
+            try_parse("[a]: /a\n[b]: /b\n", limits)
+        );
+    }
+
+    #[test]
+    fn inline_depth() {
+        let text = "*a [**b** c](/u) [](/v)* **d**e";
+        let limits = |max_inline_depth| ParseLimits {
+            max_inline_depth,
+            ..ParseLimits::default()
+        };
+        assert_eq!(
+            Ok(
+                "<p>*a <a href=\"/u\">**b** c</a> <a href=\"/v\"></a>* <strong>d</strong>e</p>\n"
+                    .to_owned()
+            ),
+            try_parse(text, limits(1))
+        );
+        assert_eq!(
+            Ok("<p><em>a <a href=\"/u\">**b** c</a> <a href=\"/v\"></a></em> <strong>d</strong>e</p>\n"
+                .to_owned()),
+            try_parse(text, limits(2))
+        );
+        assert_eq!(
+            Ok("<blockquote>\n<p>***a\nb***</p>\n</blockquote>\n".to_owned()),
+            try_parse("> ***a\n> b***", limits(0))
+        );
+    }
+
+    #[test]
+    fn any_parser() {
+        let text = "[a] *[b]*";
+        let mut callback = |_: crate::BrokenLink<'_>| Some(("/u".into(), "".into()));
+        let parser =
+            Parser::new_with_broken_link_callback(text, Options::empty(), Some(&mut callback));
+        let limits = ParseLimits {
+            max_inline_depth: 1,
+            ..ParseLimits::default()
+        };
+        let mut s = String::new();
+        html::push_html(&mut s, parser.with_limits(limits).unwrap());
+        assert_eq!("<p><a href=\"/u\">a</a> *<a href=\"/u\">b</a>*</p>\n", s);
+
+        let limits = ParseLimits {
+            max_block_depth: 0,
+            ..ParseLimits::default()
+        };
+        let parser = Parser::new_ext("> a", Options::empty());
+        assert_eq!(
+            Some(ParseError::BlockNestingTooDeep(0)),
+            parser.with_limits(limits).err()
+        );
+    }
+
+    #[test]
+    fn inline_limits() {
+        let depth = |text: &str, max_inline_depth| {
+            let limits = ParseLimits {
+                max_inline_depth,
+                ..ParseLimits::default()
+            };
+            let is_inline = |tag: &Tag<'_>| {
+                matches!(
+                    tag,
+                    Tag::Emphasis
+                        | Tag::Strong
+                        | Tag::Strikethrough
+                        | Tag::Link(..)
+                        | Tag::Image(..)
+                )
+            };
+            let mut depth = 0;
+            let mut max_depth = 0;
+            for event in Parser::try_new(text, Options::all(), limits).unwrap() {
+                match event {
+                    Event::Start(tag) if is_inline(&tag) => depth += 1,
+                    Event::End(tag) if is_inline(&tag) => depth -= 1,
+                    _ => (),
+                }
+                max_depth = core::cmp::max(max_depth, depth);
+            }
+            max_depth
+        };
+        let stars = "*".repeat(20);
+        let text = format!("{}a{}", stars, stars);
+        assert_eq!(10, depth(&text, usize::MAX));
+        assert_eq!(3, depth(&text, 3));
+        let text = format!("{}a{}", "![".repeat(10), "](/u)".repeat(10));
+        assert_eq!(10, depth(&text, usize::MAX));
+        assert_eq!(4, depth(&text, 4));
+        assert_eq!(2, depth("*[<http://a>](/u)*", 2));
+
+        let limits = |max_nodes| ParseLimits {
+            max_nodes,
+            ..ParseLimits::default()
+        };
+        assert_eq!(
+            Ok("<p><a href=\"http://a\">http://a</a></p>\n".to_owned()),
+            try_parse("<http://a>", limits(4))
+        );
+        assert_eq!(
+            Ok("<p>&lt;http://a&gt;</p>\n".to_owned()),
+            try_parse("<http://a>", limits(3))
+        );
+    }
+
+    #[test]
+    fn same_errors() {
+        let snippets = ["a", "\n", "\n\n", "> ", "- ", "*", "[x]: /u\n", "    "];
+        let mut seed = 0x2545_f491_u32;
+        let mut random = |n: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed as usize % n
+        };
+        for _ in 0..500 {
+            let mut text = String::new();
+            for _ in 0..random(30) {
+                text.push_str(snippets[random(snippets.len())]);
+            }
+            let limits = ParseLimits {
+                max_input_bytes: 20 + random(40),
+                max_block_depth: random(4),
+                max_inline_depth: random(3),
+                max_nodes: random(40),
+                max_reference_definitions: random(3),
+            };
+            assert_eq!(
+                Parser::new_ext(&text, Options::empty())
+                    .with_limits(limits)
+                    .err(),
+                Parser::try_new(&text, Options::empty(), limits).err(),
+                "{:?}",
+                text
+            );
+        }
+    }
+}
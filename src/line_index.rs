@@ -0,0 +1,390 @@
+//! Line and column positions.
+//!
+//! The ranges of [`OffsetIter`] are byte offsets. Editors and language servers
+//! use line and column positions instead, often counting columns in UTF-16
+//! code units. A [`LineIndex`] converts between the two: it finds the line of
+//! an offset in logarithmic time, and counts the column from the start of the
+//! line, which takes time linear in the length of the line.
+//! [`Parser::into_span_iter`] returns the events of a document with their
+//! positions, and counts the columns of each event from those of the previous
+//! one on the same line instead.
+//!
+//! Lines end at `\n`, `\r\n` or `\r`, like in CommonMark. Positions are counted
+//! from 0, and [`LineCol::one_based`] converts them for display.
+//!
+//! [`Parser::into_span_iter`]: crate::Parser::into_span_iter
+//!
+//! # Example
+//! ```rust
+//! use pulldown_cmark::line_index::{ColumnUnit, LineCol, LineIndex};
+//!
+//! let index = LineIndex::new("# Title\n\n*😀* text\n");
+//! let offset = "# Title\n\n*😀* ".len();
+//!
+//! let utf16 = index.line_col(offset, ColumnUnit::Utf16);
+//! assert_eq!(LineCol { line: 2, column: 5 }, utf16);
+//! assert_eq!(LineCol { line: 2, column: 4 }, index.line_col(offset, ColumnUnit::Char));
+//! assert_eq!(Some(offset), index.offset(utf16, ColumnUnit::Utf16));
+//! ```
+
+use alloc::collections::BTreeMap;
+use core::ops::Range;
+
+use crate::prelude::*;
+use crate::{Event, OffsetIter};
+
+/// The unit in which columns are counted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ColumnUnit {
+    /// Bytes of UTF-8.
+    Utf8,
+    /// UTF-16 code units, as in the Language Server Protocol and JavaScript.
+    Utf16,
+    /// Unicode scalar values, as in Rust's `char`.
+    Char,
+}
+
+/// A position in a text, with the line and column counted from 0.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LineCol {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl LineCol {
+    /// Returns the position with the line and column counted from 1, as
+    /// editors and compilers usually display them.
+    pub fn one_based(self) -> LineCol {
+        LineCol {
+            line: self.line + 1,
+            column: self.column + 1,
+        }
+    }
+}
+
+/// The positions of the start and the end of a range of a text.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SourceSpan {
+    pub start: LineCol,
+    pub end: LineCol,
+}
+
+/// The starts of the lines of a text, for converting between byte offsets and
+/// line and column positions.
+#[derive(Clone, Debug)]
+pub struct LineIndex<'a> {
+    text: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    /// Finds the lines of `text`.
+    pub fn new(text: &'a str) -> Self {
+        let bytes = text.as_bytes();
+        let mut line_starts = vec![0];
+        let mut ix = 0;
+        while let Some(offset) = memchr::memchr2(b'\n', b'\r', &bytes[ix..]) {
+            ix += offset + 1;
+            if bytes[ix - 1] == b'\r' && bytes.get(ix) == Some(&b'\n') {
+                ix += 1;
+            }
+            line_starts.push(ix);
+        }
+        LineIndex { text, line_starts }
+    }
+
+    /// Returns the text the index was built from.
+    pub fn text(&self) -> &'a str {
+        self.text
+    }
+
+    /// Returns the number of lines. A text that ends with a line ending has an
+    /// empty last line.
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// Returns the byte range of a line, including its line ending.
+    pub fn line_range(&self, line: usize) -> Option<Range<usize>> {
+        let start = *self.line_starts.get(line)?;
+        let end = self
+            .line_starts
+            .get(line + 1)
+            .copied()
+            .unwrap_or(self.text.len());
+        Some(start..end)
+    }
+
+    /// Returns the position of a byte offset. Offsets past the end of the text
+    /// are clamped to it, and offsets inside a character are moved to its start.
+    pub fn line_col(&self, offset: usize, unit: ColumnUnit) -> LineCol {
+        let (offset, line) = self.locate(offset);
+        let start = self.line_starts[line];
+        LineCol {
+            line,
+            column: column_len(&self.text[start..offset], unit),
+        }
+    }
+
+    /// Moves an offset into the text and to the start of its character, and
+    /// returns it with its line.
+    fn locate(&self, offset: usize) -> (usize, usize) {
+        let mut offset = core::cmp::min(offset, self.text.len());
+        while !self.text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next_line) => next_line - 1,
+        };
+        (offset, line)
+    }
+
+    /// Returns the position of a byte offset like `line_col`, and moves `near`
+    /// to it. On the line of `near`, the column is counted from there instead
+    /// of from the start of the line, so that offsets close to each other on a
+    /// long line are cheap to convert one after the other.
+    fn line_col_near(
+        &self,
+        offset: usize,
+        unit: ColumnUnit,
+        near: &mut (usize, LineCol),
+    ) -> LineCol {
+        let (offset, line) = self.locate(offset);
+        let (near_offset, near_position) = *near;
+        let column = if line != near_position.line {
+            column_len(&self.text[self.line_starts[line]..offset], unit)
+        } else if offset >= near_offset {
+            near_position.column + column_len(&self.text[near_offset..offset], unit)
+        } else {
+            near_position.column - column_len(&self.text[offset..near_offset], unit)
+        };
+        let position = LineCol { line, column };
+        *near = (offset, position);
+        position
+    }
+
+    /// Returns the byte offset of a position, or `None` if the line doesn't
+    /// exist. Columns past the end of the line are clamped to the start of its
+    /// line ending, and columns inside a character are moved to its start.
+    pub fn offset(&self, position: LineCol, unit: ColumnUnit) -> Option<usize> {
+        let range = self.line_range(position.line)?;
+        let line = self.text[range.clone()].trim_end_matches(&['\n', '\r'][..]);
+        let mut column = 0;
+        for (ix, c) in line.char_indices() {
+            column += match unit {
+                ColumnUnit::Utf8 => c.len_utf8(),
+                ColumnUnit::Utf16 => c.len_utf16(),
+                ColumnUnit::Char => 1,
+            };
+            if column > position.column {
+                return Some(range.start + ix);
+            }
+        }
+        Some(range.start + line.len())
+    }
+
+    /// Returns the positions of the start and the end of a byte range.
+    pub fn span(&self, range: Range<usize>, unit: ColumnUnit) -> SourceSpan {
+        SourceSpan {
+            start: self.line_col(range.start, unit),
+            end: self.line_col(range.end, unit),
+        }
+    }
+
+    /// Returns the positions of a byte range like `span`, counting them from
+    /// the positions in `near`, which are moved to them.
+    fn span_near(&self, range: Range<usize>, unit: ColumnUnit, near: &mut Near) -> SourceSpan {
+        SourceSpan {
+            start: self.line_col_near(range.start, unit, &mut near[0]),
+            end: self.line_col_near(range.end, unit, &mut near[1]),
+        }
+    }
+}
+
+// the offsets and positions of the start and the end of the last span
+type Near = [(usize, LineCol); 2];
+
+fn column_len(s: &str, unit: ColumnUnit) -> usize {
+    if s.is_ascii() {
+        return s.len();
+    }
+    match unit {
+        ColumnUnit::Utf8 => s.len(),
+        ColumnUnit::Utf16 => s.chars().map(char::len_utf16).sum(),
+        ColumnUnit::Char => s.chars().count(),
+    }
+}
+
+/// Markdown event and source span iterator.
+///
+/// Generates tuples where the first element is the markdown event and the second
+/// is the position of the corresponding range in the source string.
+///
+/// Constructed from a `Parser` using its
+/// [`into_span_iter`](crate::Parser::into_span_iter) method.
+pub struct SpanIter<'a, 'b> {
+    inner: OffsetIter<'a, 'b>,
+    unit: ColumnUnit,
+    index: Option<LineIndex<'a>>,
+    near: Near,
+    // the line starts of included documents and their last spans, by path
+    included: BTreeMap<String, (Vec<usize>, Near)>,
+}
+
+impl<'a, 'b> SpanIter<'a, 'b> {
+    pub(crate) fn new(inner: OffsetIter<'a, 'b>, unit: ColumnUnit) -> Self {
+        SpanIter {
+            inner,
+            unit,
+            index: None,
+            near: Near::default(),
+            included: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the path of the included document that the span of the last
+    /// event refers to, or `None` if it refers to the main document. See
+    /// [`OffsetIter::origin`].
+    pub fn origin(&self) -> Option<&str> {
+        self.inner.origin()
+    }
+}
+
+impl<'a, 'b> Iterator for SpanIter<'a, 'b> {
+    type Item = (Event<'a>, SourceSpan);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (event, range) = self.inner.next()?;
+        let path = match self.inner.origin() {
+            None => {
+                let text = self.inner.text();
+                let index = self.index.get_or_insert_with(|| LineIndex::new(text));
+                return Some((event, index.span_near(range, self.unit, &mut self.near)));
+            }
+            Some(path) => path,
+        };
+        let source = self.inner.source();
+        if !self.included.contains_key(path) {
+            let line_starts = LineIndex::new(source).line_starts;
+            self.included
+                .insert(path.to_owned(), (line_starts, Near::default()));
+        }
+        let (line_starts, near) = self.included.get_mut(path).unwrap();
+        let index = LineIndex {
+            text: source,
+            line_starts: core::mem::take(line_starts),
+        };
+        let span = index.span_near(range, self.unit, near);
+        *line_starts = index.line_starts;
+        Some((event, span))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Options, Parser, Tag};
+
+    #[test]
+    fn line_col() {
+        let text = "a\r\nbé\rc😀d\n";
+        let index = LineIndex::new(text);
+        assert_eq!(4, index.line_count());
+        assert_eq!(Some(3..7), index.line_range(1));
+        let units = [ColumnUnit::Utf8, ColumnUnit::Utf16, ColumnUnit::Char];
+        let cases = [
+            (0, [(0, 0), (0, 0), (0, 0)]),
+            (3, [(1, 0), (1, 0), (1, 0)]),
+            (6, [(1, 3), (1, 2), (1, 2)]),
+            (12, [(2, 5), (2, 3), (2, 2)]),
+            (14, [(3, 0), (3, 0), (3, 0)]),
+        ];
+        for &(offset, positions) in cases.iter() {
+            for (&unit, &(line, column)) in units.iter().zip(positions.iter()) {
+                let position = LineCol { line, column };
+                assert_eq!(position, index.line_col(offset, unit));
+                assert_eq!(Some(offset), index.offset(position, unit));
+            }
+        }
+        // inside the emoji
+        assert_eq!(
+            LineCol { line: 2, column: 1 },
+            index.line_col(9, ColumnUnit::Utf8)
+        );
+        let inside = LineCol { line: 2, column: 2 };
+        assert_eq!(Some(8), index.offset(inside, ColumnUnit::Utf16));
+        // past the end of the line, and of the text
+        let past = LineCol { line: 0, column: 9 };
+        assert_eq!(Some(1), index.offset(past, ColumnUnit::Char));
+        assert_eq!(
+            None,
+            index.offset(LineCol { line: 4, column: 0 }, ColumnUnit::Char)
+        );
+        assert_eq!(
+            LineCol { line: 3, column: 0 },
+            index.line_col(99, ColumnUnit::Char)
+        );
+        assert_eq!(LineCol { line: 3, column: 3 }, inside.one_based());
+    }
+
+    #[test]
+    fn span_iter() {
+        let text = "# é\n\n> *x*\n";
+        let spans = Parser::new(text)
+            .into_span_iter(ColumnUnit::Utf16)
+            .filter(|(event, _)| matches!(event, Event::Start(_)))
+            .map(|(_, span)| span)
+            .collect::<Vec<_>>();
+        let span = |start: (usize, usize), end: (usize, usize)| SourceSpan {
+            start: LineCol {
+                line: start.0,
+                column: start.1,
+            },
+            end: LineCol {
+                line: end.0,
+                column: end.1,
+            },
+        };
+        assert_eq!(
+            vec![
+                span((0, 0), (1, 0)),
+                span((2, 0), (3, 0)),
+                span((2, 2), (3, 0)),
+                span((2, 2), (2, 5)),
+            ],
+            spans
+        );
+
+        let mut resolver = |_: &str| Some("é *b*\n".into());
+        let mut iter = Parser::new("{{#include a.md}}\n")
+            .with_include_resolver(&mut resolver)
+            .into_span_iter(ColumnUnit::Char);
+        let mut emphasis = None;
+        while let Some((event, span)) = iter.next() {
+            if event == Event::Start(Tag::Emphasis) {
+                emphasis = Some((span, iter.origin().map(str::to_owned)));
+            }
+        }
+        assert_eq!(
+            Some((span((0, 2), (0, 5)), Some("a.md".to_owned()))),
+            emphasis
+        );
+    }
+
+    #[test]
+    fn span_iter_matches_index() {
+        let text = "é *a [b](/u) `c😀`* **d** é\r\n> x ~~y~~ é\n\n*a* b\rc *😀*\n";
+        let text = text.repeat(20);
+        let index = LineIndex::new(&text);
+        for &unit in [ColumnUnit::Utf8, ColumnUnit::Utf16, ColumnUnit::Char].iter() {
+            let spans = Parser::new_ext(&text, Options::all())
+                .into_span_iter(unit)
+                .map(|(_, span)| span);
+            let expected = Parser::new_ext(&text, Options::all())
+                .into_offset_iter()
+                .map(|(_, range)| index.span(range, unit));
+            assert!(spans.eq(expected));
+        }
+    }
+}
@@ -22,6 +22,7 @@
 
 use unicase::UniCase;
 
+use crate::prelude::*;
 use crate::scanners::{is_ascii_whitespace, scan_eol};
 use crate::strings::CowStr;
 
@@ -0,0 +1,1422 @@
+//! Markdown renderer that takes an iterator of events as input.
+//!
+//! The output is CommonMark, using the syntax of the extensions for the events
+//! that need them, like tables, footnotes, task lists and heading attributes.
+//! Parsing it with the options that produced the events gives the same events
+//! again, up to how text is split into [`Event::Text`] events. Text is escaped
+//! where it would otherwise be read as markup.
+//!
+//! Link reference definitions are only part of the event stream with
+//! [`Options::ENABLE_LINK_DEFINITION_EVENTS`], and are then written in place.
+//! Links that used other definitions are written with reference definitions
+//! that are collected at the end of the document.
+//!
+//! An indented code block right after a list would become part of the last
+//! list item, so the two are separated by an empty HTML comment. Parsing the
+//! output gives an extra [`Event::Html`] for it, unless HTML comments are
+//! treated as comments.
+//!
+//! Some event streams have no Markdown that parses to them. For example, ATX
+//! headings of level 3 and above can't contain line breaks. The syntax
+//! of custom blocks isn't known either, so they are written as fenced code
+//! blocks with their name and data as the info string, and custom inline
+//! elements as the text of their data.
+//!
+//! [`Options::ENABLE_LINK_DEFINITION_EVENTS`]: crate::Options::ENABLE_LINK_DEFINITION_EVENTS
+
+use alloc::collections::{BTreeMap, VecDeque};
+
+use crate::escape::StrWrite;
+#[cfg(feature = "std")]
+use crate::escape::WriteWrapper;
+use crate::prelude::*;
+use crate::scanners::scan_fancy_list_number;
+use crate::strings::CowStr;
+use crate::Event::*;
+use crate::{
+    Alignment, CodeBlockKind, Event, HeadingLevel, LinkType, ListMarker, ListNumbering, Tag,
+};
+
+/// Style choices for the Markdown output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MarkdownOptions {
+    /// The marker of bullet list items: `-`, `*` or `+`. By default, each list
+    /// keeps the marker it was written with.
+    pub bullet: Option<char>,
+    /// The delimiter of emphasis and strong emphasis: `*` or `_`.
+    pub emphasis: char,
+    /// The character of code fences: `` ` `` or `~`.
+    pub fence: char,
+    /// Write headings of level 1 and 2 as setext headings, underlined with `=`
+    /// or `-`, instead of ATX headings starting with `#`.
+    pub setext_headings: bool,
+}
+
+impl Default for MarkdownOptions {
+    fn default() -> Self {
+        MarkdownOptions {
+            bullet: None,
+            emphasis: '*',
+            fence: '`',
+            setext_headings: false,
+        }
+    }
+}
+
+enum Container<'a> {
+    BlockQuote,
+    List {
+        tight: bool,
+        marker: ListMarker,
+        numbering: ListNumbering,
+        /// The bullet or the delimiter of ordered items.
+        delimiter: char,
+        number: u64,
+        last_number: String,
+    },
+    Item {
+        tight: bool,
+        /// Whether a block has been started in the item.
+        started: bool,
+        /// The block id of the item, which is written after its first block.
+        id: Option<CowStr<'a>>,
+    },
+    FootnoteDefinition,
+}
+
+struct MarkdownWriter<'a, I, W> {
+    /// Iterator supplying events.
+    iter: I,
+    /// Events that have been read ahead, to be handled before those of `iter`.
+    pending: VecDeque<Event<'a>>,
+
+    /// Writer to write to.
+    writer: W,
+
+    options: MarkdownOptions,
+
+    /// The prefix of the lines in the open containers, like `> ` in block quotes.
+    prefix: String,
+    prefix_lens: Vec<usize>,
+    containers: Vec<Container<'a>>,
+
+    /// Whether nothing, not even the prefix, has been written on the current line.
+    at_line_start: bool,
+    /// Whether nothing but prefixes and markers has been written on the current line.
+    fresh_line: bool,
+    last_char: Option<char>,
+
+    /// Whether a block has ended, so that the next one needs to be separated from it.
+    block_ended: bool,
+    /// Whether the last event was a line of an HTML block.
+    in_html_block: bool,
+    /// Whether inline content is being written outside of a block that holds
+    /// inline content, like in the items of tight lists.
+    in_plain: bool,
+    /// Whether a paragraph, heading, table cell or line is being written.
+    in_inline_block: bool,
+    /// The level of the ATX heading that is being written.
+    atx_heading: Option<HeadingLevel>,
+    in_heading: bool,
+    in_table_cell: bool,
+    table_alignments: Vec<Alignment>,
+    /// The block id to write at the end of the current paragraph.
+    block_id: Option<CowStr<'a>>,
+    /// Whether a footnote definition has just been started, so that its first
+    /// block goes on the line after the label unless it is a paragraph.
+    footnote_start: bool,
+    /// The marker of a list that was just closed, so that a following list
+    /// uses a different one.
+    last_list: Option<char>,
+
+    emphasis: Vec<String>,
+    /// The text written for each open link, which becomes the label of
+    /// collapsed and shortcut references.
+    link_texts: Vec<String>,
+    after_shortcut: bool,
+    /// Whether the emphasis delimiters that were just written started inside a word.
+    run_in_word: bool,
+    /// The reference definitions to write at the end of the document, and
+    /// their labels by normalized label.
+    definitions: Vec<(String, CowStr<'a>, CowStr<'a>)>,
+    labels: BTreeMap<String, usize>,
+    /// The indices of the reference definitions that have been written where
+    /// they were defined, in order.
+    written_definitions: Vec<usize>,
+}
+
+impl<'a, I, W> MarkdownWriter<'a, I, W>
+where
+    I: Iterator<Item = Event<'a>>,
+    W: StrWrite,
+{
+    fn new(iter: I, writer: W, options: &MarkdownOptions) -> Self {
+        let mut options = *options;
+        if !matches!(options.bullet, None | Some('-') | Some('*') | Some('+')) {
+            options.bullet = Some('-');
+        }
+        if !matches!(options.emphasis, '*' | '_') {
+            options.emphasis = '*';
+        }
+        if !matches!(options.fence, '`' | '~') {
+            options.fence = '`';
+        }
+        Self {
+            iter,
+            pending: VecDeque::new(),
+            writer,
+            options,
+            prefix: String::new(),
+            prefix_lens: Vec::new(),
+            containers: Vec::new(),
+            at_line_start: true,
+            fresh_line: true,
+            last_char: None,
+            block_ended: false,
+            in_html_block: false,
+            in_plain: false,
+            in_inline_block: false,
+            atx_heading: None,
+            in_heading: false,
+            in_table_cell: false,
+            table_alignments: Vec::new(),
+            block_id: None,
+            footnote_start: false,
+            last_list: None,
+            emphasis: Vec::new(),
+            link_texts: Vec::new(),
+            after_shortcut: false,
+            run_in_word: false,
+            definitions: Vec::new(),
+            labels: BTreeMap::new(),
+            written_definitions: Vec::new(),
+        }
+    }
+
+    /// Writes a string that doesn't contain line breaks, preceded by the
+    /// prefix if it starts a line.
+    fn write(&mut self, s: &str) -> Result<(), W::Error> {
+        if s.is_empty() {
+            return Ok(());
+        }
+        if self.at_line_start {
+            self.writer.write_str(&self.prefix)?;
+            self.at_line_start = false;
+        }
+        self.writer.write_str(s)?;
+        for text in &mut self.link_texts {
+            text.push_str(s);
+        }
+        self.fresh_line = false;
+        self.after_shortcut = false;
+        self.run_in_word = false;
+        self.last_char = s.chars().last();
+        Ok(())
+    }
+
+    /// Writes a marker like `- `, after which the content of the line starts.
+    fn write_marker(&mut self, marker: &str) -> Result<(), W::Error> {
+        self.write(marker)?;
+        self.fresh_line = true;
+        Ok(())
+    }
+
+    fn newline(&mut self) -> Result<(), W::Error> {
+        if self.at_line_start {
+            self.writer.write_str(self.prefix.trim_end())?;
+        }
+        self.writer.write_str("\n")?;
+        for text in &mut self.link_texts {
+            text.push('\n');
+        }
+        self.at_line_start = true;
+        self.fresh_line = true;
+        self.last_char = Some('\n');
+        Ok(())
+    }
+
+    /// Writes text that may span several lines.
+    fn write_lines(&mut self, s: &str) -> Result<(), W::Error> {
+        for (i, line) in s.split('\n').enumerate() {
+            if i > 0 {
+                self.newline()?;
+            }
+            self.write(line)?;
+        }
+        Ok(())
+    }
+
+    fn push_prefix(&mut self, prefix: &str) {
+        self.prefix_lens.push(self.prefix.len());
+        self.prefix.push_str(prefix);
+    }
+
+    fn pop_prefix(&mut self) {
+        if let Some(len) = self.prefix_lens.pop() {
+            self.prefix.truncate(len);
+        }
+    }
+
+    fn is_tight(&self) -> bool {
+        match self.containers.last() {
+            Some(Container::List { tight, .. }) | Some(Container::Item { tight, .. }) => *tight,
+            // footnote definitions end at blank lines
+            Some(Container::FootnoteDefinition) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the block id of the enclosing list item if no block has been
+    /// started in it yet.
+    fn take_item_id(&mut self) -> Option<CowStr<'a>> {
+        match self.containers.last_mut() {
+            Some(Container::Item {
+                started: false, id, ..
+            }) => id.take(),
+            _ => None,
+        }
+    }
+
+    /// Starts a block, separating it from the previous one.
+    fn start_block(&mut self) -> Result<(), W::Error> {
+        self.end_plain()?;
+        self.last_list = None;
+        if core::mem::replace(&mut self.footnote_start, false) {
+            self.newline()?;
+        }
+        if let Some(Container::Item { started, .. }) = self.containers.last_mut() {
+            *started = true;
+        }
+        if self.block_ended {
+            self.block_ended = false;
+            if !self.is_tight() {
+                self.newline()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn end_block(&mut self) -> Result<(), W::Error> {
+        if !self.at_line_start {
+            self.newline()?;
+        }
+        self.block_ended = true;
+        Ok(())
+    }
+
+    /// Starts inline content outside of a paragraph, if it isn't in one.
+    fn start_inline(&mut self) -> Result<(), W::Error> {
+        if !self.in_inline_block && !self.in_plain {
+            let item_id = self.take_item_id();
+            self.start_block()?;
+            self.block_id = item_id;
+            self.in_plain = true;
+        }
+        Ok(())
+    }
+
+    fn end_plain(&mut self) -> Result<(), W::Error> {
+        if self.in_plain {
+            self.in_plain = false;
+            self.write_block_id()?;
+            self.end_block()?;
+        }
+        Ok(())
+    }
+
+    fn write_block_id(&mut self) -> Result<(), W::Error> {
+        if let Some(id) = self.block_id.take() {
+            self.write(" ^")?;
+            self.write(&id)?;
+        }
+        Ok(())
+    }
+
+    fn end_container(&mut self) -> Result<(), W::Error> {
+        self.end_plain()?;
+        if !self.at_line_start {
+            // an empty container, or one that ended inside a paragraph
+            self.newline()?;
+        }
+        self.block_ended = true;
+        Ok(())
+    }
+
+    fn next_event(&mut self) -> Option<Event<'a>> {
+        self.pending.pop_front().or_else(|| self.iter.next())
+    }
+
+    fn peek_event(&mut self) -> Option<&Event<'a>> {
+        self.peek_nth(0)
+    }
+
+    fn peek_nth(&mut self, n: usize) -> Option<&Event<'a>> {
+        while self.pending.len() <= n {
+            let event = self.iter.next()?;
+            self.pending.push_back(event);
+        }
+        self.pending.get(n)
+    }
+
+    /// Returns the event after the end of the element that has just started.
+    fn peek_after_end(&mut self) -> Option<&Event<'a>> {
+        let mut depth = 0;
+        let mut n = 0;
+        loop {
+            match self.peek_nth(n)? {
+                Start(_) => depth += 1,
+                End(_) if depth == 0 => return self.peek_nth(n + 1),
+                End(_) => depth -= 1,
+                _ => {}
+            }
+            n += 1;
+        }
+    }
+
+    /// Joins `text` with the text events that directly follow it, which the
+    /// parser splits off at escapes and entities, so that markup spanning
+    /// them is escaped.
+    fn merge_text(&mut self, text: CowStr<'a>) -> CowStr<'a> {
+        if !matches!(self.peek_event(), Some(Text(_))) {
+            return text;
+        }
+        let mut merged = text.into_string();
+        while let Some(Text(next)) = self.peek_event() {
+            merged.push_str(next);
+            self.next_event();
+        }
+        merged.into()
+    }
+
+    fn run(mut self) -> Result<(), W::Error> {
+        while let Some(event) = self.next_event() {
+            let html_block = core::mem::replace(&mut self.in_html_block, false);
+            match event {
+                Start(tag) => {
+                    self.start_tag(tag)?;
+                }
+                End(tag) => {
+                    self.end_tag(tag)?;
+                }
+                Text(text) => {
+                    self.start_inline()?;
+                    let text = self.merge_text(text);
+                    let mut escaped = self.escape_text(&text);
+                    match (escaped.chars().last(), self.peek_event()) {
+                        (Some('!'), Some(Start(Tag::Link(..)))) => {
+                            // don't turn the link into an image
+                            escaped.insert(escaped.len() - 1, '\\');
+                        }
+                        (Some(c), Some(End(_)))
+                        | (Some(c), Some(SoftBreak))
+                        | (Some(c), Some(HardBreak))
+                            if c.is_ascii_whitespace() || c == '\u{b}' =>
+                        {
+                            // whitespace at the end of a line would be stripped
+                            escaped.pop();
+                            escaped.push_str(&format!("&#{};", c as u32));
+                        }
+                        _ => {}
+                    }
+                    self.write(&escaped)?;
+                }
+                Code(text) => {
+                    self.start_inline()?;
+                    self.write_code_span(&text)?;
+                }
+                Html(html) => {
+                    if !self.in_inline_block && (html_block || html.ends_with('\n')) {
+                        if !html_block {
+                            self.start_block()?;
+                        }
+                        self.write_lines(&html)?;
+                        self.block_ended = true;
+                        self.in_html_block = true;
+                    } else {
+                        self.start_inline()?;
+                        self.write_lines(&html)?;
+                    }
+                }
+                Raw { format, content } => {
+                    if self.in_inline_block || self.in_plain {
+                        self.write_code_span(&content)?;
+                        self.write("{=")?;
+                        self.write(&format)?;
+                        self.write("}")?;
+                    } else {
+                        self.start_block()?;
+                        let info = format!("{{={}}}", format);
+                        self.write_fenced_code(&info, &content)?;
+                    }
+                }
+                Comment(text) => {
+                    if self.in_inline_block || self.in_plain {
+                        self.write("%%")?;
+                        self.write_lines(&text)?;
+                        self.write("%%")?;
+                    } else if matches!(self.peek_event(), Some(Html(_))) {
+                        // an HTML comment that starts an HTML block
+                        self.start_block()?;
+                        self.write("<!--")?;
+                        self.write_lines(&text)?;
+                        self.write("-->")?;
+                        self.block_ended = true;
+                        self.in_html_block = true;
+                    } else {
+                        self.start_block()?;
+                        self.write("%%")?;
+                        self.write_lines(&text)?;
+                        self.write("%%")?;
+                        self.end_block()?;
+                    }
+                }
+                Hashtag(tag) => {
+                    self.start_inline()?;
+                    self.write("#")?;
+                    self.write(&tag)?;
+                }
+                Custom(_, data) => {
+                    self.start_inline()?;
+                    let escaped = self.escape_text(&data);
+                    self.write(&escaped)?;
+                }
+                SoftBreak => {
+                    if self.atx_heading.is_some() || self.in_table_cell {
+                        self.write(" ")?;
+                    } else {
+                        self.newline()?;
+                    }
+                }
+                HardBreak => {
+                    if self.atx_heading.is_some() || self.in_table_cell {
+                        self.write(" ")?;
+                    } else {
+                        self.write("\\")?;
+                        self.newline()?;
+                    }
+                }
+                Rule => {
+                    self.start_block()?;
+                    self.write("***")?;
+                    self.end_block()?;
+                }
+                FootnoteReference(name) => {
+                    self.start_inline()?;
+                    self.write("[^")?;
+                    self.write(&name)?;
+                    self.write("]")?;
+                }
+                TaskListMarker(checked) => {
+                    self.write_marker(if checked { "[x] " } else { "[ ] " })?;
+                }
+                LinkDefinition { label, dest, title } => {
+                    // like the titles of links, empty titles are left out
+                    let title = title.unwrap_or_else(|| "".into());
+                    if self.define(&label, &dest, &title) {
+                        let ix = self.labels[&normalize_label(&label)];
+                        if !self.written_definitions.contains(&ix) {
+                            self.written_definitions.push(ix);
+                        }
+                    }
+                    self.start_block()?;
+                    let mut definition = format!("[{}]: ", label);
+                    write_link_destination(&mut definition, &dest, &title);
+                    self.write_lines(&definition)?;
+                    self.end_block()?;
+                }
+            }
+        }
+        self.end_plain()?;
+        if !self.at_line_start {
+            self.newline()?;
+        }
+        self.write_definitions()
+    }
+
+    /// Writes the start of a Markdown element.
+    fn start_tag(&mut self, tag: Tag<'a>) -> Result<(), W::Error> {
+        match tag {
+            Tag::Paragraph(id) => {
+                self.footnote_start = false;
+                let item_id = self.take_item_id();
+                self.start_block()?;
+                self.block_id = id.or(item_id);
+                self.in_inline_block = true;
+            }
+            Tag::Heading(level, ..) => {
+                self.start_block()?;
+                self.in_inline_block = true;
+                self.in_heading = true;
+                // line breaks can only be written in setext headings
+                let mut line_break = false;
+                let mut n = 0;
+                while let Some(event) = self.peek_nth(n) {
+                    match event {
+                        End(Tag::Heading(..)) => break,
+                        SoftBreak | HardBreak => line_break = true,
+                        _ => {}
+                    }
+                    n += 1;
+                }
+                let setext = (self.options.setext_headings || line_break)
+                    && level <= HeadingLevel::H2
+                    // setext headings can't be empty
+                    && n > 0;
+                if !setext {
+                    self.atx_heading = Some(level);
+                    let marker = format!("{} ", "#".repeat(level as usize));
+                    self.write_marker(&marker)?;
+                }
+            }
+            Tag::BlockQuote => {
+                self.start_block()?;
+                self.write_marker("> ")?;
+                self.push_prefix("> ");
+                self.containers.push(Container::BlockQuote);
+            }
+            Tag::CodeBlock(kind) => {
+                let mut content = String::new();
+                while let Some(event) = self.next_event() {
+                    match event {
+                        Text(text) => content.push_str(&text),
+                        _ => break,
+                    }
+                }
+                let after_list = self.last_list.is_some();
+                self.start_block()?;
+                match kind {
+                    CodeBlockKind::Fenced(info) => self.write_fenced_code(&info, &content)?,
+                    CodeBlockKind::Indented => {
+                        if after_list {
+                            // the code block would continue the last list item
+                            self.write("<!-- -->")?;
+                            self.newline()?;
+                        }
+                        if !self.at_line_start {
+                            self.write_marker("    ")?;
+                        }
+                        self.push_prefix("    ");
+                        self.write_lines(&content)?;
+                        self.pop_prefix();
+                    }
+                }
+                self.end_block()?;
+            }
+            Tag::Custom(name, data) => {
+                let mut content = String::new();
+                while let Some(event) = self.next_event() {
+                    match event {
+                        Text(text) => content.push_str(&text),
+                        _ => break,
+                    }
+                }
+                self.start_block()?;
+                let info = if data.is_empty() {
+                    name.into_string()
+                } else {
+                    format!("{} {}", name, data)
+                };
+                self.write_fenced_code(&info, &content)?;
+            }
+            Tag::List(start, numbering, marker, tight) => {
+                let last_list = self.last_list.take();
+                self.start_block()?;
+                let mut delimiter = match marker {
+                    ListMarker::Dash => self.options.bullet.unwrap_or('-'),
+                    ListMarker::Star => self.options.bullet.unwrap_or('*'),
+                    ListMarker::Plus => self.options.bullet.unwrap_or('+'),
+                    ListMarker::Period => '.',
+                    ListMarker::OneParen => ')',
+                    ListMarker::TwoParens => '(',
+                };
+                if last_list == Some(delimiter) {
+                    // a list right after one with the same marker would continue it
+                    delimiter = match delimiter {
+                        '-' => '*',
+                        '*' | '+' => '-',
+                        '.' => ')',
+                        _ => '.',
+                    };
+                }
+                self.containers.push(Container::List {
+                    tight,
+                    marker,
+                    numbering,
+                    delimiter,
+                    number: start.unwrap_or(1),
+                    last_number: String::new(),
+                });
+            }
+            Tag::Item(id) => {
+                self.start_block()?;
+                let (tight, marker) = match self.containers.last_mut() {
+                    Some(Container::List {
+                        tight,
+                        marker,
+                        numbering,
+                        delimiter,
+                        number,
+                        last_number,
+                    }) => {
+                        let marker = if marker.is_ordered() {
+                            if let Some(n) = format_list_number(*number, *numbering) {
+                                *last_number = n;
+                            }
+                            *number += 1;
+                            let initial = matches!(
+                                numbering,
+                                ListNumbering::UpperAlpha | ListNumbering::UpperRoman
+                            ) && last_number.len() == 1
+                                && *delimiter == '.';
+                            match *delimiter {
+                                '(' => format!("({}) ", last_number),
+                                // single capital letters need two spaces
+                                _ if initial => format!("{}{}  ", last_number, delimiter),
+                                _ => format!("{}{} ", last_number, delimiter),
+                            }
+                        } else {
+                            format!("{} ", delimiter)
+                        };
+                        (*tight, marker)
+                    }
+                    _ => (false, "- ".to_owned()),
+                };
+                self.write_marker(&marker)?;
+                self.push_prefix(&" ".repeat(marker.len()));
+                self.containers.push(Container::Item {
+                    tight,
+                    started: false,
+                    id,
+                });
+            }
+            Tag::FootnoteDefinition(name) => {
+                self.start_block()?;
+                self.write("[^")?;
+                self.write(&name)?;
+                self.write_marker("]: ")?;
+                // the content of footnote definitions isn't indented
+                self.push_prefix("");
+                self.containers.push(Container::FootnoteDefinition);
+                self.footnote_start = true;
+            }
+            Tag::Table(alignments) => {
+                self.start_block()?;
+                self.table_alignments = alignments;
+            }
+            Tag::TableHead | Tag::TableRow => {
+                self.write("|")?;
+            }
+            Tag::TableCell => {
+                self.write(" ")?;
+                self.in_inline_block = true;
+                self.in_table_cell = true;
+            }
+            Tag::LineBlock => {
+                self.start_block()?;
+            }
+            Tag::Line => {
+                self.write_marker("| ")?;
+                self.in_inline_block = true;
+            }
+            Tag::Emphasis => self.start_emphasis(1)?,
+            Tag::Strong => self.start_emphasis(2)?,
+            Tag::Strikethrough => {
+                self.start_inline()?;
+                self.write("~~")?;
+            }
+            Tag::Link(LinkType::Autolink, dest, _) | Tag::Link(LinkType::Email, dest, _) => {
+                self.start_inline()?;
+                self.write("<")?;
+                self.write(&dest)?;
+                self.write(">")?;
+                // the text of the link is its destination
+                while let Some(event) = self.next_event() {
+                    if let End(_) = event {
+                        break;
+                    }
+                }
+            }
+            Tag::Link(..) => {
+                self.start_inline()?;
+                self.write("[")?;
+                self.link_texts.push(String::new());
+            }
+            Tag::Image(..) => {
+                self.start_inline()?;
+                self.write("![")?;
+                self.link_texts.push(String::new());
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the end of a Markdown element.
+    fn end_tag(&mut self, tag: Tag<'a>) -> Result<(), W::Error> {
+        match tag {
+            Tag::Paragraph(_) => {
+                self.in_inline_block = false;
+                self.write_block_id()?;
+                self.end_block()?;
+            }
+            Tag::Heading(level, id, classes) => {
+                self.in_inline_block = false;
+                self.in_heading = false;
+                let setext = self.atx_heading.take().is_none();
+                if id.is_some() || !classes.is_empty() {
+                    self.write(" {")?;
+                    let mut attrs = Vec::new();
+                    if let Some(id) = &id {
+                        attrs.push(format!("#{}", id));
+                    }
+                    attrs.extend(classes.iter().map(|class| format!(".{}", class)));
+                    self.write(&attrs.join(" "))?;
+                    self.write("}")?;
+                }
+                if setext {
+                    if !self.at_line_start {
+                        self.newline()?;
+                    }
+                    self.write(if level == HeadingLevel::H1 {
+                        "==="
+                    } else {
+                        "---"
+                    })?;
+                }
+                self.end_block()?;
+            }
+            Tag::BlockQuote | Tag::Item(_) | Tag::FootnoteDefinition(_) => {
+                self.end_container()?;
+                self.pop_prefix();
+                self.containers.pop();
+                self.last_list = None;
+            }
+            Tag::List(..) => {
+                self.end_container()?;
+                if let Some(Container::List { delimiter, .. }) = self.containers.pop() {
+                    self.last_list = Some(delimiter);
+                }
+            }
+            Tag::CodeBlock(_) | Tag::Custom(..) => {}
+            Tag::Table(_) => {
+                self.block_ended = true;
+            }
+            Tag::TableHead => {
+                self.newline()?;
+                self.write("|")?;
+                let alignments = core::mem::take(&mut self.table_alignments);
+                for alignment in &alignments {
+                    self.write(match alignment {
+                        Alignment::None => " --- |",
+                        Alignment::Left => " :-- |",
+                        Alignment::Center => " :-: |",
+                        Alignment::Right => " --: |",
+                    })?;
+                }
+                self.newline()?;
+            }
+            Tag::TableRow => {
+                self.newline()?;
+            }
+            Tag::TableCell => {
+                self.in_inline_block = false;
+                self.in_table_cell = false;
+                self.write(" |")?;
+            }
+            Tag::LineBlock => {
+                self.block_ended = true;
+            }
+            Tag::Line => {
+                self.in_inline_block = false;
+                self.newline()?;
+            }
+            Tag::Emphasis | Tag::Strong => {
+                if let Some(delimiter) = self.emphasis.pop() {
+                    self.write(&delimiter)?;
+                }
+            }
+            Tag::Strikethrough => {
+                self.write("~~")?;
+            }
+            Tag::Link(link_type, dest, title) | Tag::Image(link_type, dest, title) => {
+                let text = self.link_texts.pop().unwrap_or_default();
+                self.end_link(link_type, text, dest, title)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn start_emphasis(&mut self, len: usize) -> Result<(), W::Error> {
+        self.start_inline()?;
+        let mut c = self.options.emphasis;
+        let in_word = matches!(self.last_char, Some(prev) if prev.is_alphanumeric());
+        let word_after = c == '_'
+            && matches!(self.peek_after_end(), Some(Text(text))
+            if text.starts_with(char::is_alphanumeric));
+        if in_word || word_after || self.run_in_word {
+            // underscores don't work inside words, and a delimiter run that
+            // starts inside a word can only be extended
+            c = '*';
+        } else if self.last_char == Some(c) {
+            // don't extend the delimiter run of an enclosing element
+            c = if c == '*' { '_' } else { '*' };
+        }
+        let delimiter = c.to_string().repeat(len);
+        let run_in_word = in_word || self.run_in_word;
+        self.write(&delimiter)?;
+        self.run_in_word = run_in_word;
+        self.emphasis.push(delimiter);
+        Ok(())
+    }
+
+    fn end_link(
+        &mut self,
+        link_type: LinkType,
+        text: String,
+        dest: CowStr<'a>,
+        title: CowStr<'a>,
+    ) -> Result<(), W::Error> {
+        match link_type {
+            LinkType::Reference | LinkType::Collapsed | LinkType::Shortcut => {
+                let own_label = link_type != LinkType::Reference
+                    && !text.trim().is_empty()
+                    && text.len() <= 999
+                    && !text.contains("\n\n");
+                if own_label && self.define(&text, &dest, &title) {
+                    if link_type == LinkType::Collapsed {
+                        self.write("][]")?;
+                    } else {
+                        self.write("]")?;
+                        self.after_shortcut = true;
+                    }
+                } else if let Some(label) = self.written_label(&dest, &title) {
+                    self.write("][")?;
+                    self.write(&label)?;
+                    self.write("]")?;
+                } else {
+                    let mut n = self.definitions.len() + 1;
+                    while !self.define(&n.to_string(), &dest, &title) {
+                        n += 1;
+                    }
+                    self.write("][")?;
+                    self.write(&n.to_string())?;
+                    self.write("]")?;
+                }
+            }
+            _ => {
+                self.write("](")?;
+                let mut destination = String::new();
+                write_link_destination(&mut destination, &dest, &title);
+                self.write_lines(&destination)?;
+                self.write(")")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds a reference definition, unless one with the same label and a
+    /// different destination or title exists. Returns whether the label can
+    /// be used.
+    fn define(&mut self, label: &str, dest: &CowStr<'a>, title: &CowStr<'a>) -> bool {
+        let normalized = normalize_label(label);
+        match self.labels.get(&normalized) {
+            Some(&ix) => {
+                let (_, existing_dest, existing_title) = &self.definitions[ix];
+                existing_dest == dest && existing_title == title
+            }
+            None => {
+                self.labels.insert(normalized, self.definitions.len());
+                self.definitions
+                    .push((label.to_owned(), dest.clone(), title.clone()));
+                true
+            }
+        }
+    }
+
+    /// Returns the label of a reference definition with the given destination
+    /// and title that has been written where it was defined.
+    fn written_label(&self, dest: &str, title: &str) -> Option<String> {
+        self.written_definitions.iter().find_map(|&ix| {
+            let (label, existing_dest, existing_title) = &self.definitions[ix];
+            if &**existing_dest == dest && &**existing_title == title {
+                Some(label.clone())
+            } else {
+                None
+            }
+        })
+    }
+
+    fn write_definitions(&mut self) -> Result<(), W::Error> {
+        let written_definitions = &self.written_definitions;
+        let definitions = (core::mem::take(&mut self.definitions).into_iter())
+            .enumerate()
+            .filter(|(ix, _)| !written_definitions.contains(ix))
+            .map(|(_, definition)| definition)
+            .collect::<Vec<_>>();
+        if definitions.is_empty() {
+            return Ok(());
+        }
+        if self.last_char.is_some() {
+            self.newline()?;
+        }
+        for (label, dest, title) in definitions {
+            let mut definition = format!("[{}]: ", label);
+            write_link_destination(&mut definition, &dest, &title);
+            self.write_lines(&definition)?;
+            self.newline()?;
+        }
+        Ok(())
+    }
+
+    fn write_fenced_code(&mut self, info: &str, content: &str) -> Result<(), W::Error> {
+        let mut c = self.options.fence;
+        if c == '`' && info.contains('`') {
+            c = '~';
+        }
+        let longest = longest_run(content, c);
+        let fence = c.to_string().repeat(core::cmp::max(3, longest + 1));
+        self.write(&fence)?;
+        self.write(info)?;
+        self.newline()?;
+        self.write_lines(content)?;
+        if !self.at_line_start {
+            self.newline()?;
+        }
+        self.write(&fence)?;
+        self.end_block()
+    }
+
+    fn write_code_span(&mut self, code: &str) -> Result<(), W::Error> {
+        self.start_inline()?;
+        if code.is_empty() && self.atx_heading.is_none() && !self.in_table_cell {
+            // a line ending is the only content that is stripped entirely
+            self.write("`")?;
+            self.newline()?;
+            return self.write("`");
+        }
+        let fence = "`".repeat(longest_run(code, '`') + 1);
+        // a single space on both sides is stripped
+        let pad = code.starts_with('`')
+            || code.ends_with('`')
+            || (code.starts_with(' ') && code.ends_with(' ') && code.bytes().any(|b| b != b' '));
+        self.write(&fence)?;
+        if pad {
+            self.write(" ")?;
+        }
+        self.write(code)?;
+        if pad {
+            self.write(" ")?;
+        }
+        self.write(&fence)
+    }
+
+    /// Escapes the characters of text that could be read as markup.
+    fn escape_text(&self, text: &str) -> String {
+        let line_start = self.fresh_line;
+        let list_delimiter = if line_start {
+            list_marker_delimiter(text)
+        } else {
+            None
+        };
+        let mut escaped = String::with_capacity(text.len());
+        let mut prev = if line_start { None } else { self.last_char };
+        for (i, c) in text.char_indices() {
+            let next = text[i + c.len_utf8()..].chars().next();
+            if c == '\n' || (i == 0 && line_start && (c == ' ' || c == '\t')) {
+                // line endings and leading whitespace can only be written as references
+                escaped.push_str(match c {
+                    ' ' => "&#32;",
+                    '\t' => "&#9;",
+                    _ => "&#10;",
+                });
+                prev = Some(c);
+                continue;
+            }
+            let escape = match c {
+                '\\' | '`' | '*' | '[' | ']' | '<' | '~' | '|' => true,
+                '_' => {
+                    !(matches!(prev, Some(p) if p.is_alphanumeric())
+                        && matches!(next, Some(n) if n.is_alphanumeric()))
+                }
+                '&' => matches!(next, Some(n) if n.is_ascii_alphanumeric() || n == '#'),
+                '#' => !matches!(prev, Some(p) if p.is_alphanumeric()),
+                '^' => matches!(prev, Some(' ') | Some('\t') | Some('[')),
+                '%' => next == Some('%'),
+                '{' => self.in_heading || matches!(next, Some('=') | Some('{')),
+                // a heading ending in `}` would have an attribute block
+                '}' => self.in_heading,
+                '>' | '-' | '+' | '=' => i == 0 && line_start,
+                '(' | ':' => i == 0 && self.after_shortcut,
+                _ => false,
+            } || list_delimiter == Some(i);
+            if escape {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+            prev = Some(c);
+        }
+        escaped
+    }
+}
+
+/// Returns the index of the character that has to be escaped so that `text`
+/// doesn't start an ordered list item.
+fn list_marker_delimiter(text: &str) -> Option<usize> {
+    let (len, c, _, _) = scan_fancy_list_number(text.as_bytes())?;
+    if !matches!(text.as_bytes().get(len), None | Some(b' ') | Some(b'\t')) {
+        return None;
+    }
+    Some(if c == b'(' { 0 } else { len - 1 })
+}
+
+/// Returns the length of the longest run of `c` in `text`.
+fn longest_run(text: &str, c: char) -> usize {
+    let mut longest = 0;
+    let mut run = 0;
+    for ch in text.chars() {
+        if ch == c {
+            run += 1;
+            longest = core::cmp::max(longest, run);
+        } else {
+            run = 0;
+        }
+    }
+    longest
+}
+
+fn format_list_number(number: u64, numbering: ListNumbering) -> Option<String> {
+    match numbering {
+        ListNumbering::Decimal if number <= 999_999_999 => Some(number.to_string()),
+        ListNumbering::LowerAlpha | ListNumbering::UpperAlpha if (1..=26).contains(&number) => {
+            let base = if numbering == ListNumbering::LowerAlpha {
+                b'a'
+            } else {
+                b'A'
+            };
+            Some(char::from(base + (number - 1) as u8).to_string())
+        }
+        ListNumbering::LowerRoman | ListNumbering::UpperRoman if (1..4000).contains(&number) => {
+            const NUMERALS: [(u64, &str); 13] = [
+                (1000, "m"),
+                (900, "cm"),
+                (500, "d"),
+                (400, "cd"),
+                (100, "c"),
+                (90, "xc"),
+                (50, "l"),
+                (40, "xl"),
+                (10, "x"),
+                (9, "ix"),
+                (5, "v"),
+                (4, "iv"),
+                (1, "i"),
+            ];
+            let mut rest = number;
+            let mut roman = String::new();
+            for &(value, numeral) in &NUMERALS {
+                while rest >= value {
+                    roman.push_str(numeral);
+                    rest -= value;
+                }
+            }
+            if numbering == ListNumbering::UpperRoman {
+                roman.make_ascii_uppercase();
+            }
+            Some(roman)
+        }
+        _ => None,
+    }
+}
+
+/// Returns the label that reference labels are matched by.
+fn normalize_label(label: &str) -> String {
+    label
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Writes the destination and title of a link or reference definition.
+fn write_link_destination(s: &mut String, dest: &str, title: &str) {
+    let pointy = dest.is_empty()
+        || dest
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || matches!(c, '(' | ')' | '<' | '>'));
+    if pointy {
+        s.push('<');
+    }
+    let mut chars = dest.chars().peekable();
+    while let Some(c) = chars.next() {
+        let escape = match c {
+            '\\' | '<' | '>' => true,
+            '&' => matches!(chars.peek(), Some(n) if n.is_ascii_alphanumeric() || *n == '#'),
+            _ => false,
+        };
+        if escape {
+            s.push('\\');
+        }
+        s.push(c);
+    }
+    if pointy {
+        s.push('>');
+    }
+    if !title.is_empty() {
+        s.push_str(" \"");
+        let mut chars = title.chars().peekable();
+        while let Some(c) = chars.next() {
+            let escape = match c {
+                '\\' | '"' => true,
+                '&' => matches!(chars.peek(), Some(n) if n.is_ascii_alphanumeric() || *n == '#'),
+                _ => false,
+            };
+            if escape {
+                s.push('\\');
+            }
+            s.push(c);
+        }
+        s.push('"');
+    }
+}
+
+/// Iterate over an `Iterator` of `Event`s, generate Markdown for each `Event`,
+/// and push it to a `String`.
+///
+/// # Examples
+///
+/// ```
+/// use pulldown_cmark::{markdown, Parser};
+///
+/// let markdown_str = r#"
+/// hello
+/// =====
+///
+/// * alpha
+/// * _beta_
+/// "#;
+/// let parser = Parser::new(markdown_str);
+///
+/// let mut markdown_buf = String::new();
+/// markdown::push_markdown(&mut markdown_buf, parser);
+///
+/// assert_eq!(markdown_buf, r#"# hello
+///
+/// * alpha
+/// * *beta*
+/// "#);
+/// ```
+pub fn push_markdown<'a, I>(s: &mut String, iter: I)
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    push_markdown_with_options(s, iter, &MarkdownOptions::default())
+}
+
+/// Like [`push_markdown`], with the given style choices.
+///
+/// # Examples
+///
+/// ```
+/// use pulldown_cmark::markdown::{self, MarkdownOptions};
+/// use pulldown_cmark::Parser;
+///
+/// let parser = Parser::new("# hello\n\n* alpha\n");
+/// let options = MarkdownOptions {
+///     bullet: Some('-'),
+///     setext_headings: true,
+///     ..MarkdownOptions::default()
+/// };
+///
+/// let mut markdown_buf = String::new();
+/// markdown::push_markdown_with_options(&mut markdown_buf, parser, &options);
+///
+/// assert_eq!(markdown_buf, "hello\n===\n\n- alpha\n");
+/// ```
+pub fn push_markdown_with_options<'a, I>(s: &mut String, iter: I, options: &MarkdownOptions)
+where
+    I: Iterator<Item = Event<'a>>,
+{
+    MarkdownWriter::new(iter, s, options).run().unwrap();
+}
+
+/// Iterate over an `Iterator` of `Event`s, generate Markdown for each `Event`,
+/// and write it out to a writable stream.
+///
+/// **Note**: using this function with an unbuffered writer like a file or socket
+/// will result in poor performance. Wrap these in a
+/// [`BufWriter`](https://doc.rust-lang.org/std/io/struct.BufWriter.html) to
+/// prevent unnecessary slowdowns.
+///
+/// # Examples
+///
+/// ```
+/// use pulldown_cmark::{markdown, Parser};
+/// use std::io::Cursor;
+///
+/// let mut bytes = Vec::new();
+/// let parser = Parser::new("1) *a*\n2) b\n");
+///
+/// markdown::write_markdown(Cursor::new(&mut bytes), parser);
+///
+/// assert_eq!(&String::from_utf8_lossy(&bytes)[..], "1) *a*\n2) b\n");
+/// ```
+#[cfg(feature = "std")]
+pub fn write_markdown<'a, I, W>(writer: W, iter: I) -> std::io::Result<()>
+where
+    I: Iterator<Item = Event<'a>>,
+    W: std::io::Write,
+{
+    write_markdown_with_options(writer, iter, &MarkdownOptions::default())
+}
+
+/// Like [`write_markdown`], with the given style choices.
+#[cfg(feature = "std")]
+pub fn write_markdown_with_options<'a, I, W>(
+    writer: W,
+    iter: I,
+    options: &MarkdownOptions,
+) -> std::io::Result<()>
+where
+    I: Iterator<Item = Event<'a>>,
+    W: std::io::Write,
+{
+    MarkdownWriter::new(iter, WriteWrapper(writer), options).run()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Options, Parser};
+
+    /// Parses `text`, merging adjacent text events, which the writer doesn't
+    /// preserve.
+    fn events(text: &str, options: Options) -> Vec<Event<'_>> {
+        let mut events: Vec<Event<'_>> = Vec::new();
+        for event in Parser::new_ext(text, options) {
+            match (events.last_mut(), event) {
+                (_, Text(text)) if text.is_empty() => {}
+                (Some(Text(prev)), Text(text)) => {
+                    *prev = format!("{}{}", prev, text).into();
+                }
+                (_, event) => events.push(event),
+            }
+        }
+        events
+    }
+
+    fn assert_round_trip(text: &str, options: Options, markdown_options: &MarkdownOptions) {
+        let expected = events(text, options);
+        let mut markdown = String::new();
+        push_markdown_with_options(
+            &mut markdown,
+            expected.clone().into_iter(),
+            markdown_options,
+        );
+        // ignore the comments that separate lists from indented code blocks
+        let separators = [Html("<!-- -->\n".into()), Comment(" ".into())];
+        let mut actual = events(&markdown, options);
+        actual.retain(|event| !separators.contains(event));
+        assert_eq!(expected, actual, "{}", markdown);
+    }
+
+    #[test]
+    fn round_trip() {
+        let texts = [
+            "# Title {#top .big}\n\nSetext\nheading\n---\n\n> quote *with* **strong** ~~gone~~\n> > nested\n",
+            "* a\n* b\n\n  more\n\n  - [ ] task\n  - [x] done\n\n3) three\n4) four\n",
+            "(a) alpha\n(b) beta\n\nB. Initial\nC. next\n\nix. nine\nx. ten\n",
+            "```rust\nfn main() {}\n```\n\n````\n```\n````\n\n    indented\n\n~~~ {=html}\n<b>raw</b>\n~~~\n",
+            "| a | *b* | c \\| d |\n|:--|:-:|--:|\n| 1 | `2` | 3 |\n",
+            "text[^1] and #tag\n\n[^1]: note\n    continued\n",
+            "[inline](/u \"t\") [ref][r] [coll][] [short] <https://x.com> <a@b.c> ![img](<a b>)\n\n[r]: /r\n[coll]: /c\n[short]: /s 'title'\n",
+            "para ^p1\n\n- item ^i1\n- plain\n\n| line one\n|   line two\n",
+            "<div>\nblock\n</div>\n\n%% comment %%\n\ninline <b>html</b> %%c%% `{=tex}`{=latex}\n",
+            "hard  \nbreak\\\nsoft\nbreak\n\n***\n\n- \n- empty above\n",
+            "\\*not em\\* \\_ 1\\. \\# &amp; &lt; \\[x\\] a_b_c snake_case __init__\n\n\\+ plus\n\n\\- minus\n\n2\\) paren\n",
+            " -    one\n\n     two\n",
+            "1. a\n\n  2. b\n\n    3. c\n",
+            "# H1 \\{}\n\n## H2 {.foo\\}\n\n{}\n==\n",
+            // spec example 308 and other escaped list markers at the start of a line
+            "1986\\. What a great season.\n\n1\\. foo\n\n1\\) bar\n",
+        ];
+        let options = Options::all()
+            - Options::ENABLE_SMART_PUNCTUATION
+            - Options::ENABLE_LINK_DEFINITION_EVENTS;
+        let styles = [
+            MarkdownOptions::default(),
+            MarkdownOptions {
+                bullet: None,
+                emphasis: '_',
+                fence: '~',
+                setext_headings: true,
+            },
+        ];
+        for text in texts.iter() {
+            for style in styles.iter() {
+                assert_round_trip(text, options, style);
+                assert_round_trip(text, Options::empty(), style);
+            }
+        }
+    }
+
+    #[test]
+    fn split_text() {
+        // the parser splits the text at the escape, so the writer only sees
+        // the list marker when it looks past a single text event
+        let text = "1986\\. What a great season.\n\n1\\) bar\n";
+        let mut markdown = String::new();
+        push_markdown(&mut markdown, Parser::new(text));
+        assert_eq!(text, markdown);
+    }
+
+    #[test]
+    fn link_definitions() {
+        let text = "[a]: /a\n\n[x][a] [b] [c][] [y][d]\n\n[b]: /b 'title'\n[c]: /c\n";
+        let options = Options::ENABLE_LINK_DEFINITION_EVENTS;
+        let mut markdown = String::new();
+        push_markdown(&mut markdown, Parser::new_ext(text, options));
+        assert_eq!(
+            "[a]: /a\n\n[x][a] [b] [c][] \\[y\\]\\[d\\]\n\n[b]: /b \"title\"\n\n[c]: /c\n",
+            markdown
+        );
+        assert_round_trip(text, options, &MarkdownOptions::default());
+    }
+
+    #[test]
+    fn styles() {
+        let text = "Title\n=====\n\n- *a*\n- b\n\n```\n~~~\n```\n";
+        let options = MarkdownOptions {
+            bullet: Some('+'),
+            emphasis: '_',
+            fence: '~',
+            setext_headings: false,
+        };
+        let mut markdown = String::new();
+        push_markdown_with_options(&mut markdown, Parser::new(text), &options);
+        assert_eq!("# Title\n\n+ _a_\n+ b\n\n~~~~\n~~~\n~~~~\n", markdown);
+
+        let options = MarkdownOptions {
+            setext_headings: true,
+            ..MarkdownOptions::default()
+        };
+        let mut markdown = String::new();
+        push_markdown_with_options(&mut markdown, Parser::new(text), &options);
+        assert_eq!("Title\n===\n\n- *a*\n- b\n\n```\n~~~\n```\n", markdown);
+    }
+
+    #[test]
+    fn escaping() {
+        let mut markdown = String::new();
+        let events = vec![
+            Start(Tag::Paragraph(None)),
+            Text("- not a list, 1. or *emphasis* or a_b or _c_ &amp; ".into()),
+            SoftBreak,
+            Text("10) x # y".into()),
+            End(Tag::Paragraph(None)),
+        ];
+        push_markdown(&mut markdown, events.into_iter());
+        assert_eq!(
+            "\\- not a list, 1. or \\*emphasis\\* or a_b or \\_c\\_ \\&amp;&#32;\n10\\) x \\# y\n",
+            markdown
+        );
+    }
+}
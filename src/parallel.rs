@@ -0,0 +1,313 @@
+//! Parallel parsing.
+//!
+//! A [`Parser`] resolves the block structure of the whole document and then
+//! parses the inline content of one block after the other, all on the calling
+//! thread. A [`ParallelParser`] splits the document into chunks of top-level
+//! blocks and parses them on the [rayon](https://docs.rs/rayon) thread pool,
+//! which only pays off for very large documents.
+//!
+//! The chunks are split at the start of a line that follows a blank line, and
+//! the block structure of each chunk is resolved in parallel, assuming that no
+//! block is open at its start. A chunk for which that turns out to be wrong,
+//! for instance because it starts inside a fenced code block, is parsed again
+//! from where the previous chunk ended. Once all reference definitions are
+//! known, the inline content of the chunks is parsed in parallel as well, a
+//! batch of chunks at a time, and their events are returned in order.
+//!
+//! The events are the same as those of a [`Parser`] with the same options, with
+//! a few exceptions: a table of contents can't be generated, so
+//! [`Options::ENABLE_TOC`] only enables heading identifiers, and
+//! [`Options::ENABLE_DIAGNOSTICS`] is ignored. Heading identifiers have to be
+//! unique across the document, so with [`Options::ENABLE_HEADING_IDS`] the
+//! inline content of the chunks is parsed one chunk after the other.
+//!
+//! This module requires the `parallel` feature.
+//!
+//! # Example
+//! ```rust
+//! use pulldown_cmark::parallel::ParallelParser;
+//! use pulldown_cmark::html;
+//!
+//! let markdown_input = "[url]: /url\n\n# Log\n\n- a [link][url]\n- *b*\n";
+//! let parser = ParallelParser::new(markdown_input).with_chunk_size(16);
+//!
+//! let mut html_output = String::new();
+//! html::push_html(&mut html_output, parser);
+//!
+//! let expected_html = "<h1>Log</h1>\n<ul>\n<li>a <a href=\"/url\">link</a></li>\n<li><em>b</em></li>\n</ul>\n";
+//! assert_eq!(expected_html, html_output);
+//! ```
+
+use std::cmp::{max, min};
+use std::collections::VecDeque;
+use std::iter::Flatten;
+use std::vec;
+
+use rayon::prelude::*;
+
+use crate::firstpass::run_first_pass_from;
+use crate::parse::{Allocations, Item};
+use crate::scanners::{scan_blank_line, scan_nextline, LineStart};
+use crate::slug::HeadingIds;
+use crate::tree::Tree;
+use crate::{Event, Options, Parser, RefDefs};
+
+/// The default number of bytes in a chunk.
+const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Markdown event iterator that parses a document on a thread pool.
+///
+/// Nothing is parsed until the first event is requested.
+pub struct ParallelParser<'input> {
+    text: &'input str,
+    options: Options,
+    chunk_size: usize,
+    // whether the block structure has been resolved
+    started: bool,
+    // the chunks whose inline content hasn't been parsed yet, in order
+    parts: VecDeque<Part<'input>>,
+    refdefs: RefDefs<'input>,
+    heading_ids: HeadingIds<'static>,
+    events: Flatten<vec::IntoIter<Vec<Event<'input>>>>,
+}
+
+/// The block structure of a chunk of the document.
+struct Part<'input> {
+    tree: Tree<Item>,
+    allocs: Allocations<'input>,
+}
+
+impl<'input> ParallelParser<'input> {
+    /// Creates a new event iterator for a markdown string without any options enabled.
+    pub fn new(text: &'input str) -> Self {
+        ParallelParser::new_ext(text, Options::empty())
+    }
+
+    /// Creates a new event iterator for a markdown string with given options.
+    pub fn new_ext(text: &'input str, mut options: Options) -> Self {
+        options.remove(Options::ENABLE_DIAGNOSTICS);
+        if options.contains(Options::ENABLE_TOC) {
+            options.remove(Options::ENABLE_TOC);
+            options.insert(Options::ENABLE_HEADING_IDS);
+        }
+        ParallelParser {
+            text,
+            options,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            started: false,
+            parts: VecDeque::new(),
+            refdefs: RefDefs::default(),
+            heading_ids: HeadingIds::default(),
+            events: Vec::new().into_iter().flatten(),
+        }
+    }
+
+    /// Sets the number of bytes after which the document is split into a new
+    /// chunk, at the next line that follows a blank line. The default is 1 MiB.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = max(1, chunk_size);
+        self
+    }
+
+    /// Resolves the block structure of all chunks and collects their
+    /// reference definitions.
+    fn parse_blocks(&mut self) {
+        let (text, options) = (self.text, self.options);
+        let starts = split_points(text, self.chunk_size);
+        let parts = (0..starts.len())
+            .into_par_iter()
+            .map(|k| parse_part(text, options, starts[k], starts.get(k + 1).copied()))
+            .collect::<Vec<_>>();
+
+        // The chunks after the first one are only correct if the previous one
+        // ended where they start.
+        let mut end = 0;
+        for (k, (part, part_end)) in parts.into_iter().enumerate() {
+            if end >= part_end {
+                continue;
+            }
+            let (part, part_end) = if end == starts[k] {
+                (part, part_end)
+            } else {
+                parse_part(text, options, end, starts.get(k + 1).copied())
+            };
+            end = part_end;
+            for (label, def) in part.allocs.refdefs.0.iter() {
+                if !self.refdefs.0.contains_key(label) {
+                    self.refdefs.0.insert(label.clone(), def.clone());
+                }
+            }
+            for (id, _) in &part.allocs.headings {
+                if let Some(id) = id {
+                    self.heading_ids.reserve(id);
+                }
+            }
+            self.parts.push_back(part);
+        }
+    }
+
+    /// Parses the inline content of the next batch of chunks, and returns
+    /// their events.
+    fn parse_inlines(&mut self) -> Vec<Vec<Event<'input>>> {
+        let (text, options) = (self.text, self.options);
+        if options.contains(Options::ENABLE_HEADING_IDS) {
+            let Part { tree, mut allocs } = self.parts.pop_front().unwrap();
+            allocs.refdefs = self.refdefs.clone();
+            let mut parser = Parser::from_first_pass(text, options, tree, allocs, None);
+            parser.continue_heading_ids(&mut self.heading_ids);
+            let events = parser.by_ref().collect();
+            parser.save_heading_ids(&mut self.heading_ids);
+            return vec![events];
+        }
+
+        let batch = min(self.parts.len(), rayon::current_num_threads());
+        let refdefs = &self.refdefs;
+        let parts = (self.parts.drain(..batch))
+            .map(|mut part| {
+                part.allocs.refdefs = refdefs.clone();
+                part
+            })
+            .collect::<Vec<_>>();
+        parts
+            .into_par_iter()
+            .map(|Part { tree, allocs }| {
+                Parser::from_first_pass(text, options, tree, allocs, None).collect()
+            })
+            .collect()
+    }
+}
+
+impl<'input> Iterator for ParallelParser<'input> {
+    type Item = Event<'input>;
+
+    fn next(&mut self) -> Option<Event<'input>> {
+        loop {
+            if let Some(event) = self.events.next() {
+                return Some(event);
+            }
+            if !self.started {
+                self.started = true;
+                self.parse_blocks();
+            }
+            if self.parts.is_empty() {
+                return None;
+            }
+            self.events = self.parse_inlines().into_iter().flatten();
+        }
+    }
+}
+
+/// Resolves the block structure of the text from `start`, which is assumed
+/// to be the start of a line at which no block is open, and stops at the
+/// first such line at or after `next`. Returns the offset at which it stopped.
+fn parse_part<'input>(
+    text: &'input str,
+    options: Options,
+    start: usize,
+    next: Option<usize>,
+) -> (Part<'input>, usize) {
+    let len = next.unwrap_or(text.len()).saturating_sub(start);
+    let tree = Tree::with_capacity(max(128, len / 32));
+    let (tree, allocs, reparse) = run_first_pass_from(
+        text,
+        options,
+        tree,
+        Allocations::new(),
+        start,
+        |ix| matches!(next, Some(next) if ix >= next),
+    );
+    (Part { tree, allocs }, reparse.end)
+}
+
+/// Returns the offsets at which the text is split into chunks of at least
+/// `chunk_size` bytes. Each chunk after the first one starts at a line that
+/// follows a blank line and that neither is indented nor starts a block quote
+/// or list item, so that it likely starts a new top-level block.
+fn split_points(text: &str, chunk_size: usize) -> Vec<usize> {
+    let bytes = text.as_bytes();
+    let mut starts = vec![0];
+    let mut ix = chunk_size;
+    let mut blank = false;
+    while ix < bytes.len() {
+        if bytes[ix - 1] != b'\n' {
+            ix += scan_nextline(&bytes[ix..]);
+            blank = false;
+            continue;
+        }
+        let line = &bytes[ix..];
+        if scan_blank_line(line).is_some() {
+            blank = true;
+        } else if blank && !line[0].is_ascii_whitespace() && !starts_container(line) {
+            starts.push(ix);
+            ix += chunk_size;
+            blank = false;
+            continue;
+        } else {
+            blank = false;
+        }
+        ix += scan_nextline(line);
+    }
+    starts
+}
+
+/// Returns whether a line starts with a block quote or list item marker.
+fn starts_container(line: &[u8]) -> bool {
+    LineStart::new(line).scan_list_marker(true).is_some()
+        || LineStart::new(line).scan_blockquote_marker()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parallel_events(text: &str, options: Options, chunk_size: usize) -> Vec<Event<'_>> {
+        ParallelParser::new_ext(text, options)
+            .with_chunk_size(chunk_size)
+            .collect()
+    }
+
+    #[test]
+    fn same_as_parser() {
+        let texts = [
+            "",
+            "\n\n",
+            "# a\n\nb\nc\n===\n\n- d\n\n  e\n- f\n\n> g\nlazy\n\n    code\n\n```\nfenced\n\n```\n",
+            "| a | b |\n|---|---|\n| 1 | 2 |\n\n[^1]: note\n\n  more\n\ntext[^1]\n",
+            "<div>\n\nhtml\n</div>\n\n1. x\n2. y\n\n3) z\r\n\r\n***\nlast",
+            "[a] [b]\n\n[a]: /a\n\n[b]: /b 'title'\n\n[A]: /c\n[a]\n",
+            "# a\n\n# a {#a-1}\n\n# a\n\n[TOC]\n",
+        ];
+        let options = Options::all() - Options::ENABLE_TOC - Options::ENABLE_DIAGNOSTICS;
+        for text in texts.iter() {
+            for &options in [options, options - Options::ENABLE_HEADING_IDS].iter() {
+                let expected = Parser::new_ext(text, options).collect::<Vec<_>>();
+                for &chunk_size in [1, 7, 1000].iter() {
+                    assert_eq!(
+                        expected,
+                        parallel_events(text, options, chunk_size),
+                        "{:?}",
+                        text
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn split_points_after_blank_lines() {
+        let text = "a\n\nb\n\n    c\n\n- d\n\n> e\n\nf\n";
+        assert_eq!(vec![0, 3, 23], split_points(text, 1));
+        assert_eq!(vec![0, 23], split_points(text, 4));
+        assert_eq!(vec![0], split_points(text, 100));
+    }
+
+    #[test]
+    fn chunk_inside_fenced_code() {
+        let text = "```\na\n\nb\n\n```\n\nc\n\n*d*\n";
+        assert_eq!(
+            Parser::new(text).collect::<Vec<_>>(),
+            parallel_events(text, Options::empty(), 1)
+        );
+        assert_eq!(vec![0, 7, 10, 15, 18], split_points(text, 1));
+    }
+}
@@ -20,20 +20,33 @@
 
 //! Tree-based two pass parser.
 
-use std::cmp::{max, min};
-use std::collections::{HashMap, VecDeque};
-use std::iter::FusedIterator;
-use std::num::NonZeroUsize;
-use std::ops::{Index, Range};
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::rc::Rc;
+use core::cmp::{max, min};
+use core::convert::TryFrom;
+use core::iter::FusedIterator;
+use core::num::{NonZeroU32, NonZeroUsize};
+use core::ops::{Index, Range};
 
 use unicase::UniCase;
 
-use crate::firstpass::run_first_pass;
+use crate::cst::{self, SyntaxTree};
+use crate::diagnostic::{Diagnostic, DiagnosticCode};
+use crate::extension::{BlockParser, InlineParser};
+use crate::firstpass::{run_first_pass, run_first_pass_with, run_first_pass_with_limits};
+use crate::limits::check_blocks;
+use crate::line_index::{ColumnUnit, SpanIter};
 use crate::linklabel::{scan_link_label_rest, LinkLabel, ReferenceLabel};
+use crate::prelude::*;
 use crate::scanners::*;
+use crate::slug::{HeadingIds, Slugifier};
 use crate::strings::CowStr;
+use crate::toc::{self, TocEntry};
 use crate::tree::{Tree, TreeIndex};
-use crate::{Alignment, CodeBlockKind, Event, HeadingLevel, LinkType, Options, Tag};
+use crate::{
+    Alignment, CodeBlockKind, Event, HeadingLevel, LinkType, ListMarker, ListNumbering, Options,
+    ParseError, ParseLimits, Tag,
+};
 
 // Allowing arbitrary depth nested parentheses inside link destinations
 // can create denial of service vulnerabilities if we're not careful.
@@ -51,7 +64,7 @@ pub(crate) struct Item {
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub(crate) enum ItemBody {
-    Paragraph,
+    Paragraph(Option<BlockIdIndex>),
     Text,
     SoftBreak,
     HardBreak,
@@ -68,6 +81,7 @@ pub(crate) enum ItemBody {
     // bool indicates whether or not the preceeding section could be a reference
     MaybeLinkClose(bool),
     MaybeImage,
+    MaybeComment,
 
     // These are inline items after resolution.
     Emphasis,
@@ -86,11 +100,19 @@ pub(crate) enum ItemBody {
     Html,
     OwnedHtml(CowIndex),
     BlockQuote,
-    List(bool, u8, u64), // is_tight, list character, list start index
-    ListItem(usize),     // indent level
+    List(bool, u8, ListNumbering, u64), // is_tight, list character, numbering, list start index
+    ListItem(usize, Option<BlockIdIndex>), // indent level, block id
     SynthesizeText(CowIndex),
     SynthesizeChar(char),
     FootnoteDefinition(CowIndex),
+    Raw(RawIndex),
+    LineBlock,
+    Line,
+    Custom(CustomIndex),
+    Comment(CowIndex),
+    Hashtag,
+    CustomInline(CustomIndex),
+    LinkDefinition(DefinitionIndex),
 
     // Tables
     Table(AlignmentIndex),
@@ -113,6 +135,19 @@ impl<'a> ItemBody {
                 | ItemBody::MaybeLinkOpen
                 | ItemBody::MaybeLinkClose(..)
                 | ItemBody::MaybeImage
+                | ItemBody::MaybeComment
+        )
+    }
+
+    /// Returns whether this is an inline element that can contain others.
+    fn is_inline_element(&self) -> bool {
+        matches!(
+            *self,
+            ItemBody::Emphasis
+                | ItemBody::Strong
+                | ItemBody::Strikethrough
+                | ItemBody::Link(..)
+                | ItemBody::Image(..)
         )
     }
 }
@@ -124,7 +159,7 @@ impl<'a> Default for ItemBody {
 }
 
 pub struct BrokenLink<'a> {
-    pub span: std::ops::Range<usize>,
+    pub span: core::ops::Range<usize>,
     pub link_type: LinkType,
     pub reference: CowStr<'a>,
 }
@@ -137,10 +172,41 @@ pub struct Parser<'input, 'callback> {
     allocs: Allocations<'input>,
     broken_link_callback: BrokenLinkCallback<'input, 'callback>,
     html_scan_guard: HtmlScanGuard,
+    heading_ids: HeadingIds<'callback>,
+    include_resolver: IncludeResolver<'callback>,
+    // paths and texts of the documents from the main one to this one, which
+    // can't be included again
+    include_chain: Vec<(&'input str, &'input str)>,
+    // events of an included document that are yet to be returned, with the
+    // document their range refers to
+    included: VecDeque<(Event<'input>, Range<usize>, Option<Rc<IncludedDocument>>)>,
 
     // used by inline passes. store them here for reuse
     inline_stack: InlineStack,
     link_stack: LinkStack,
+    code_delims: CodeDelims,
+    // the heights of the elements built by the inline passes that contain
+    // others, when their nesting is limited
+    inline_heights: BTreeMap<TreeIndex, usize>,
+    // how deeply the inline passes can nest elements in the current block
+    inline_depth_left: usize,
+    diagnostic_state: DiagnosticState<'input>,
+    limits: ParseLimits,
+    // the outline of the document once it has been collected, which doesn't
+    // change afterwards, and the number of `[TOC]` placeholders expanded
+    toc: Option<Vec<TocEntry<'input>>>,
+    toc_count: usize,
+}
+
+/// What a parser keeps track of to find the diagnostics that depend on the
+/// whole document.
+#[derive(Default)]
+struct DiagnosticState<'a> {
+    // the labels of the reference definitions that links refer to
+    used_definitions: BTreeSet<LinkLabel<'a>>,
+    // the labels and ranges of footnote references
+    footnote_references: Vec<(CowIndex, Range<usize>)>,
+    finished: bool,
 }
 
 impl<'input, 'callback> Parser<'input, 'callback> {
@@ -164,11 +230,73 @@ impl<'input, 'callback> Parser<'input, 'callback> {
         options: Options,
         broken_link_callback: BrokenLinkCallback<'input, 'callback>,
     ) -> Self {
-        let (mut tree, allocs) = run_first_pass(text, options);
+        let (tree, allocs) = run_first_pass(text, options, &mut [], &mut []);
+        Parser::from_first_pass(text, options, tree, allocs, broken_link_callback)
+    }
+
+    /// Creates a new event iterator for a markdown string with given options,
+    /// which also recognizes the custom blocks of the given parsers. When more
+    /// than one parser recognizes a block, the first one is used.
+    pub fn new_with_block_parsers(
+        text: &'input str,
+        options: Options,
+        block_parsers: &mut [&mut dyn BlockParser],
+    ) -> Self {
+        Parser::new_with_extensions(text, options, block_parsers, &mut [])
+    }
+
+    /// Creates a new event iterator for a markdown string with given options,
+    /// which also recognizes the custom blocks and inline elements of the given
+    /// parsers. When more than one parser recognizes a block or element, the
+    /// first one is used.
+    pub fn new_with_extensions(
+        text: &'input str,
+        options: Options,
+        block_parsers: &mut [&mut dyn BlockParser],
+        inline_parsers: &mut [&mut dyn InlineParser],
+    ) -> Self {
+        let (tree, allocs) = run_first_pass(text, options, block_parsers, inline_parsers);
+        Parser::from_first_pass(text, options, tree, allocs, None)
+    }
+
+    /// Creates a new event iterator for a markdown string with given options,
+    /// or returns an error if the string exceeds one of the limits, like
+    /// [`with_limits`](Self::with_limits). Unlike that, it doesn't parse a
+    /// string that is too long, and stops parsing at the block that exceeds a
+    /// limit.
+    pub fn try_new(
+        text: &'input str,
+        options: Options,
+        limits: ParseLimits,
+    ) -> Result<Self, ParseError> {
+        let (tree, allocs) = run_first_pass_with_limits(text, options, limits);
+        Parser::from_first_pass(text, options, tree, allocs, None).with_limits(limits)
+    }
+
+    /// Creates a parser from the result of the first pass over `text`.
+    pub(crate) fn from_first_pass(
+        text: &'input str,
+        options: Options,
+        mut tree: Tree<Item>,
+        allocs: Allocations<'input>,
+        broken_link_callback: BrokenLinkCallback<'input, 'callback>,
+    ) -> Self {
         tree.reset();
         let inline_stack = Default::default();
         let link_stack = Default::default();
         let html_scan_guard = Default::default();
+        let mut options = options;
+        if options.contains(Options::ENABLE_TOC) {
+            options.insert(Options::ENABLE_HEADING_IDS);
+        }
+        let mut heading_ids = HeadingIds::default();
+        if options.contains(Options::ENABLE_HEADING_IDS) {
+            for (id, _) in &allocs.headings {
+                if let Some(id) = id {
+                    heading_ids.reserve(id);
+                }
+            }
+        }
         Parser {
             text,
             options,
@@ -177,16 +305,510 @@ impl<'input, 'callback> Parser<'input, 'callback> {
             broken_link_callback,
             inline_stack,
             link_stack,
+            code_delims: CodeDelims::new(),
+            inline_heights: BTreeMap::new(),
+            inline_depth_left: usize::MAX,
+            diagnostic_state: DiagnosticState::default(),
+            limits: ParseLimits::default(),
+            toc: None,
+            toc_count: 0,
             html_scan_guard,
+            heading_ids,
+            include_resolver: None,
+            include_chain: Vec::new(),
+            included: VecDeque::new(),
         }
     }
 
+    /// Moves the heading identifiers used by the parsers of earlier parts of a
+    /// document to this one, so that identifiers are unique across parts.
+    pub(crate) fn continue_heading_ids(&mut self, heading_ids: &mut HeadingIds<'_>) {
+        self.heading_ids.extend(heading_ids);
+    }
+
+    /// Moves the heading identifiers used so far to `heading_ids`, see
+    /// `continue_heading_ids`.
+    #[cfg(feature = "std")]
+    pub(crate) fn save_heading_ids(&mut self, heading_ids: &mut HeadingIds<'_>) {
+        heading_ids.extend(&mut self.heading_ids);
+    }
+
+    /// Sets the slugifier used to generate heading identifiers when
+    /// [`Options::ENABLE_HEADING_IDS`] is set. The default is [`GitHubSlugifier`].
+    ///
+    /// [`GitHubSlugifier`]: crate::GitHubSlugifier
+    pub fn with_slugifier(mut self, slugifier: &'callback mut dyn Slugifier) -> Self {
+        self.heading_ids.set_slugifier(slugifier);
+        self
+    }
+
+    /// Enables the `{{#include path}}` directive.
+    ///
+    /// A paragraph consisting of just the directive is replaced by the events of
+    /// the document returned by the resolver for `path`. The included document is
+    /// parsed separately with the same options. It can use the reference
+    /// definitions of the documents that include it, but not the other way
+    /// around, and footnotes don't carry over between documents. Heading
+    /// identifiers are unique across all documents. Includes can be nested up to
+    /// a depth of 32. If the resolver returns `None`, or a document would include
+    /// itself, the directive is left as a paragraph.
+    ///
+    /// The ranges returned by [`OffsetIter`] for included events are offsets into
+    /// the included document, which is given by [`OffsetIter::origin`].
+    pub fn with_include_resolver(
+        mut self,
+        resolver: &'callback mut dyn FnMut(&str) -> Option<CowStr<'static>>,
+    ) -> Self {
+        self.include_resolver = Some(resolver);
+        self
+    }
+
+    /// Limits the resources the parser uses for the document, for parsing
+    /// untrusted input, and returns an error if its block structure exceeds
+    /// one of the limits. Inline elements are kept within the limits while the
+    /// events are read, see [`ParseLimits`]. This has to be called before any
+    /// events are read, and works with parsers created in any way.
+    ///
+    /// The blocks of the whole document are parsed when the parser is created,
+    /// so [`try_new`](Self::try_new) is cheaper when the input can exceed the
+    /// limits. Included documents are checked against the limits separately,
+    /// and a directive whose document exceeds them is left as a paragraph.
+    pub fn with_limits(mut self, limits: ParseLimits) -> Result<Self, ParseError> {
+        check_blocks(self.text, &self.tree, &self.allocs, &limits)?;
+        self.limits = limits;
+        Ok(self)
+    }
+
     /// Returns a reference to the internal `RefDefs` object, which provides access
     /// to the internal map of reference definitions.
     pub fn reference_definitions(&self) -> &RefDefs {
         &self.allocs.refdefs
     }
 
+    /// Returns the diagnostics found so far, when [`Options::ENABLE_DIAGNOSTICS`]
+    /// is set. Those about blocks are found when the parser is created, and
+    /// those about inline markup when its events are read. Once all events have
+    /// been read, the diagnostics are complete and sorted by position.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.allocs.diagnostics
+    }
+
+    /// Adds a diagnostic when they are enabled.
+    fn report(&mut self, code: DiagnosticCode, range: Range<usize>) {
+        if self.options.contains(Options::ENABLE_DIAGNOSTICS) {
+            self.allocs.report(code, range);
+        }
+    }
+
+    /// Adds the diagnostics that can only be found after all events have been
+    /// read, and sorts them.
+    fn finish_diagnostics(&mut self) {
+        if !self.options.contains(Options::ENABLE_DIAGNOSTICS) || self.diagnostic_state.finished {
+            return;
+        }
+        self.diagnostic_state.finished = true;
+
+        let used_definitions = &self.diagnostic_state.used_definitions;
+        let unused_definitions = (self.allocs.refdefs.0.iter())
+            .filter(|(label, _)| !used_definitions.contains(*label))
+            .map(|(_, def)| def.span.clone())
+            .collect::<Vec<_>>();
+        for range in unused_definitions {
+            self.report(DiagnosticCode::UnusedDefinition, range);
+        }
+
+        let mut footnote_definitions = BTreeSet::new();
+        for ix in 1..self.tree.len() {
+            if let ItemBody::FootnoteDefinition(cow_ix) = self.tree[TreeIndex::new(ix)].item.body {
+                footnote_definitions.insert(self.allocs[cow_ix].as_ref());
+            }
+        }
+        let undefined_footnotes = (self.diagnostic_state.footnote_references.iter())
+            .filter(|(cow_ix, _)| !footnote_definitions.contains(self.allocs[*cow_ix].as_ref()))
+            .map(|(_, range)| range.clone())
+            .collect::<Vec<_>>();
+        for range in undefined_footnotes {
+            self.report(DiagnosticCode::UndefinedFootnote, range);
+        }
+
+        (self.allocs.diagnostics)
+            .sort_by_key(|diagnostic| (diagnostic.range.start, diagnostic.range.end));
+    }
+
+    /// Returns the outline of the document, made of all of its headings.
+    ///
+    /// This can be called at any point during iteration, and doesn't change the
+    /// events that are returned afterwards.
+    pub fn table_of_contents(&mut self) -> Vec<TocEntry<'input>> {
+        self.outline().to_vec()
+    }
+
+    /// Returns the outline of the document, collecting it the first time.
+    pub(crate) fn outline(&mut self) -> &[TocEntry<'input>] {
+        if self.toc.is_none() {
+            self.toc = Some(toc::nest(self.headings()));
+        }
+        self.toc.as_ref().unwrap()
+    }
+
+    /// Returns the distinct tags of the document in order of their first
+    /// occurrence, without the leading `#`. Requires [`Options::ENABLE_HASHTAGS`].
+    ///
+    /// Like [`table_of_contents`](Self::table_of_contents), this can be called at
+    /// any point during iteration. Tags in included documents are not collected.
+    pub fn hashtags(&mut self) -> Vec<CowStr<'input>> {
+        let position = self.tree.position();
+        self.tree.reset();
+        let mut tags: Vec<CowStr<'input>> = Vec::new();
+        let mut seen = BTreeSet::new();
+        let text = self.text;
+        loop {
+            match self.tree.cur() {
+                None => match self.tree.pop() {
+                    Some(ix) => {
+                        self.tree.next_sibling(ix);
+                    }
+                    None => break,
+                },
+                Some(cur_ix) => {
+                    if self.tree[cur_ix].item.body.is_inline() {
+                        self.handle_inline();
+                    }
+                    let node = self.tree[cur_ix];
+                    if node.item.body == ItemBody::Hashtag {
+                        let tag = &text[node.item.start + 1..node.item.end];
+                        if seen.insert(tag) {
+                            tags.push(tag.into());
+                        }
+                    }
+                    if node.child.is_some() {
+                        self.tree.push();
+                    } else {
+                        self.tree.next_sibling(cur_ix);
+                    }
+                }
+            }
+        }
+        self.tree.set_position(position);
+        tags
+    }
+
+    /// Collects all headings of the document in order, generating identifiers
+    /// for them if enabled.
+    fn headings(&mut self) -> Vec<TocEntry<'input>> {
+        let position = self.tree.position();
+        self.tree.reset();
+        let mut headings = Vec::new();
+        loop {
+            match self.tree.cur() {
+                None => match self.tree.pop() {
+                    Some(ix) => {
+                        self.tree.next_sibling(ix);
+                    }
+                    None => break,
+                },
+                Some(cur_ix) => match self.tree[cur_ix].item.body {
+                    ItemBody::Heading(level, _) => {
+                        if self.options.contains(Options::ENABLE_HEADING_IDS) {
+                            self.generate_heading_id(cur_ix);
+                        }
+                        let text = self.heading_text();
+                        let id = match self.tree[cur_ix].item.body {
+                            ItemBody::Heading(_, Some(attrs_ix)) => self.allocs[attrs_ix].0.clone(),
+                            _ => None,
+                        };
+                        let item = self.tree[cur_ix].item;
+                        headings.push(TocEntry {
+                            level,
+                            text,
+                            id,
+                            range: item.start..item.end,
+                            children: Vec::new(),
+                        });
+                        self.tree.next_sibling(cur_ix);
+                    }
+                    ItemBody::BlockQuote
+                    | ItemBody::List(..)
+                    | ItemBody::ListItem(..)
+                    | ItemBody::FootnoteDefinition(..) => {
+                        self.tree.push();
+                    }
+                    _ => {
+                        self.tree.next_sibling(cur_ix);
+                    }
+                },
+            }
+        }
+        self.tree.set_position(position);
+        headings
+    }
+
+    /// Generates an identifier for the heading at the current position if it
+    /// doesn't have one yet.
+    fn generate_heading_id(&mut self, heading_ix: TreeIndex) {
+        let (level, attrs_ix) = match self.tree[heading_ix].item.body {
+            ItemBody::Heading(level, attrs_ix) => (level, attrs_ix),
+            _ => return,
+        };
+        if let Some(attrs_ix) = attrs_ix {
+            if self.allocs[attrs_ix].0.is_some() {
+                return;
+            }
+        }
+
+        let text = self.heading_text();
+        let id = self.heading_ids.generate(&text).into();
+        match attrs_ix {
+            Some(attrs_ix) => self.allocs.headings[attrs_ix.0.get() - 1].0 = Some(id),
+            None => {
+                let attrs_ix = self.allocs.allocate_heading((None, Vec::new()));
+                self.allocs.headings[attrs_ix.0.get() - 1].0 = Some(id);
+                self.tree[heading_ix].item.body = ItemBody::Heading(level, Some(attrs_ix));
+            }
+        }
+    }
+
+    /// Returns the plain text of the heading at the current position.
+    fn heading_text(&mut self) -> String {
+        // The inline markup of the heading has to be resolved first.
+        let heading_ix = self.tree.push();
+        let mut child = self.tree.cur();
+        while let Some(child_ix) = child {
+            if self.tree[child_ix].item.body.is_inline() {
+                self.handle_inline();
+                break;
+            }
+            child = self.tree[child_ix].next;
+        }
+        let mut text = String::new();
+        self.append_plain_text(self.tree[heading_ix].child, &mut text);
+        self.tree.pop();
+        text
+    }
+
+    /// Replaces the paragraph at the current position by a table of contents if
+    /// it consists of just `[TOC]`, unless that is a reference link or too many
+    /// have been replaced already.
+    fn expand_toc_placeholder(&mut self, paragraph_ix: TreeIndex) {
+        let range = match self.paragraph_content(paragraph_ix) {
+            Some(range) => range,
+            None => return,
+        };
+        if self.text[range.clone()].trim() != "[TOC]"
+            || self.allocs.refdefs.get("TOC").is_some()
+            || self.toc_count == MAX_TOC_PLACEHOLDERS
+        {
+            return;
+        }
+        self.toc_count += 1;
+
+        self.outline();
+        let headings = self.toc.take().unwrap();
+        if self.toc_fits(&headings) {
+            let items = self.append_toc_items(&headings, range);
+            self.tree[paragraph_ix].item.body =
+                ItemBody::List(true, b'-', ListNumbering::Decimal, 1);
+            self.tree[paragraph_ix].child = items;
+        }
+        self.toc = Some(headings);
+    }
+
+    /// Returns whether a table of contents with the given entries stays within
+    /// the limits in place of the paragraph at the current position.
+    fn toc_fits(&self, entries: &[TocEntry<'input>]) -> bool {
+        // Returns the number of nodes of the list items and their nesting.
+        fn size(entries: &[TocEntry<'_>]) -> (usize, usize) {
+            let mut nodes = 0;
+            let mut depth = 0;
+            for entry in entries {
+                let (child_nodes, child_depth) = size(&entry.children);
+                // a list item with a link and its text, and a list of children
+                nodes += 3 + child_nodes + (!entry.children.is_empty()) as usize;
+                depth = max(depth, 1 + child_depth);
+            }
+            (nodes, depth)
+        }
+        let (nodes, depth) = size(entries);
+        let containers = (self.tree.walk_spine())
+            .filter(|&&ix| {
+                matches!(
+                    self.tree[ix].item.body,
+                    ItemBody::BlockQuote | ItemBody::ListItem(..)
+                )
+            })
+            .count();
+        self.tree.len() - 1 + nodes <= self.limits.max_nodes
+            && containers + depth <= self.limits.max_block_depth
+            && (entries.is_empty() || self.limits.max_inline_depth > 0)
+    }
+
+    /// Returns whether the node is a comment that is left out of the events.
+    fn is_hidden_comment(&self, ix: TreeIndex) -> bool {
+        matches!(self.tree[ix].item.body, ItemBody::Comment(..))
+            && !self.options.contains(Options::ENABLE_COMMENT_EVENTS)
+    }
+
+    /// Returns the source range of the inline content of a paragraph.
+    fn paragraph_content(&self, paragraph_ix: TreeIndex) -> Option<Range<usize>> {
+        let first = self.tree[paragraph_ix].child?;
+        let mut last = first;
+        while let Some(next) = self.tree[last].next {
+            last = next;
+        }
+        Some(self.tree[first].item.start..self.tree[last].item.end)
+    }
+
+    /// If the node at the current position is a paragraph with an include
+    /// directive, parses the included document into `self.included` and moves
+    /// past the paragraph. Returns whether that happened.
+    fn splice_include(&mut self, paragraph_ix: TreeIndex) -> bool {
+        if self.include_resolver.is_none()
+            || !matches!(self.tree[paragraph_ix].item.body, ItemBody::Paragraph(_))
+        {
+            return false;
+        }
+        let path = match self
+            .paragraph_content(paragraph_ix)
+            .and_then(|range| scan_include_directive(&self.text[range]))
+        {
+            Some(path) => path,
+            None => return false,
+        };
+        if self.include_chain.is_empty() {
+            self.include_chain.push(("", self.text));
+        }
+        if self.include_chain.len() > MAX_INCLUDE_DEPTH
+            || self
+                .include_chain
+                .iter()
+                .any(|&(chain_path, _)| chain_path == path)
+        {
+            return false;
+        }
+        let resolver = self.include_resolver.as_mut().unwrap();
+        let document = match resolver(path) {
+            Some(text) => Rc::new(IncludedDocument {
+                path: path.to_owned(),
+                text,
+            }),
+            None => return false,
+        };
+        let text = &*document.text;
+        if self
+            .include_chain
+            .iter()
+            .any(|&(_, chain_text)| chain_text == text)
+        {
+            return false;
+        }
+
+        let mut parser = match Parser::new_ext(text, self.options).with_limits(self.limits) {
+            Ok(parser) => parser,
+            Err(_) => return false,
+        };
+        parser.include_resolver = Some(&mut **resolver);
+        parser.include_chain = self.include_chain.clone();
+        parser.include_chain.push((path, text));
+        // the definitions the included document doesn't override
+        let mut inherited = BTreeSet::new();
+        for (label, def) in &self.allocs.refdefs.0 {
+            if !parser.allocs.refdefs.0.contains_key(label) {
+                parser.allocs.refdefs.0.insert(label.clone(), def.clone());
+                inherited.insert(label.clone());
+            }
+        }
+        parser.heading_ids.extend(&mut self.heading_ids);
+        if let Some(slugifier) = self.heading_ids.slugifier() {
+            parser.heading_ids.set_slugifier(slugifier);
+        }
+        let mut iter = parser.into_offset_iter();
+        while let Some((event, range)) = iter.next() {
+            let origin = iter.origin.clone().unwrap_or_else(|| document.clone());
+            self.included
+                .push_back((event.into_static(), range, Some(origin)));
+        }
+        let used_definitions = &iter.inner.diagnostic_state.used_definitions;
+        (self.diagnostic_state.used_definitions).extend(
+            inherited
+                .into_iter()
+                .filter(|label| used_definitions.contains(label)),
+        );
+        let mut heading_ids = HeadingIds::default();
+        heading_ids.extend(&mut iter.inner.heading_ids);
+        drop(iter);
+        self.heading_ids.extend(&mut heading_ids);
+        self.tree.next_sibling(paragraph_ix);
+        true
+    }
+
+    /// Creates the list items of a table of contents, and returns the first one.
+    fn append_toc_items(
+        &mut self,
+        entries: &[TocEntry<'input>],
+        range: Range<usize>,
+    ) -> Option<TreeIndex> {
+        let node = |body| Item {
+            start: range.start,
+            end: range.end,
+            body,
+        };
+        let mut first = None;
+        let mut prev: Option<TreeIndex> = None;
+        for entry in entries {
+            let url = match &entry.id {
+                Some(id) => format!("#{}", id).into(),
+                None => "".into(),
+            };
+            let link_ix = self.allocs.allocate_link(LinkType::Inline, url, "".into());
+            let link = self.tree.create_node(node(ItemBody::Link(link_ix)));
+            let cow_ix = self.allocs.allocate_cow(entry.text.clone().into());
+            self.tree[link].child = Some(
+                self.tree
+                    .create_node(node(ItemBody::SynthesizeText(cow_ix))),
+            );
+            if !entry.children.is_empty() {
+                let list = self.tree.create_node(node(ItemBody::List(
+                    true,
+                    b'-',
+                    ListNumbering::Decimal,
+                    1,
+                )));
+                self.tree[list].child = self.append_toc_items(&entry.children, range.clone());
+                self.tree[link].next = Some(list);
+            }
+
+            let list_item = self.tree.create_node(node(ItemBody::ListItem(0, None)));
+            self.tree[list_item].child = Some(link);
+            match prev {
+                Some(prev) => self.tree[prev].next = Some(list_item),
+                None => first = Some(list_item),
+            }
+            prev = Some(list_item);
+        }
+        first
+    }
+
+    /// Appends the text of the given node and its siblings, and of all of their
+    /// descendants, to `buf`. Markup and inline HTML are left out.
+    fn append_plain_text(&self, mut node: Option<TreeIndex>, buf: &mut String) {
+        while let Some(node_ix) = node {
+            let item = self.tree[node_ix].item;
+            match item.body {
+                ItemBody::Text | ItemBody::Hashtag => {
+                    buf.push_str(&self.text[item.start..item.end])
+                }
+                ItemBody::Code(cow_ix) | ItemBody::SynthesizeText(cow_ix) => {
+                    buf.push_str(&self.allocs[cow_ix])
+                }
+                ItemBody::SynthesizeChar(c) => buf.push(c),
+                ItemBody::SoftBreak | ItemBody::HardBreak => buf.push(' '),
+                _ => (),
+            }
+            self.append_plain_text(self.tree[node_ix].child, buf);
+            node = self.tree[node_ix].next;
+        }
+    }
+
     /// Handle inline markup.
     ///
     /// When the parser encounters any item indicating potential inline markup, all
@@ -194,17 +816,39 @@ impl<'input, 'callback> Parser<'input, 'callback> {
     ///
     /// Note: there's some potential for optimization here, but that's future work.
     fn handle_inline(&mut self) {
+        if self.limits.max_inline_depth != usize::MAX {
+            let depth = (self.tree.walk_spine())
+                .filter(|&&ix| self.tree[ix].item.body.is_inline_element())
+                .count();
+            self.inline_depth_left = self.limits.max_inline_depth.saturating_sub(depth);
+        }
         self.handle_inline_pass1();
         self.handle_emphasis();
     }
 
+    /// Returns how deeply inline elements are nested in the node, including the
+    /// node itself.
+    fn inline_height(&self, ix: TreeIndex) -> usize {
+        if !self.tree[ix].item.body.is_inline_element() {
+            return 0;
+        }
+        self.inline_heights.get(&ix).copied().unwrap_or(1)
+    }
+
+    /// Records the height of an element that the inline passes built.
+    fn set_inline_height(&mut self, ix: TreeIndex, height: usize) {
+        if height > 1 && self.inline_depth_left != usize::MAX {
+            self.inline_heights.insert(ix, height);
+        }
+    }
+
     /// Handle inline HTML, code spans, and links.
     ///
     /// This function handles both inline HTML and code spans, because they have
     /// the same precedence. It also handles links, even though they have lower
     /// precedence, because the URL of links must not be processed.
     fn handle_inline_pass1(&mut self) {
-        let mut code_delims = CodeDelims::new();
+        self.code_delims.clear();
         let mut cur = self.tree.cur();
         let mut prev = None;
 
@@ -220,6 +864,10 @@ impl<'input, 'callback> Parser<'input, 'callback> {
                     } else {
                         None
                     };
+                    // an autolink adds a node for its text, nested in the link
+                    let autolink = autolink.filter(|_| {
+                        self.inline_depth_left > 0 && self.tree.len() <= self.limits.max_nodes
+                    });
 
                     if let Some((ix, uri, link_type)) = autolink {
                         let node = scan_nodes_to_ix(&self.tree, next, ix);
@@ -233,6 +881,7 @@ impl<'input, 'callback> Parser<'input, 'callback> {
                         self.tree[cur_ix].item.end = ix;
                         self.tree[cur_ix].next = node;
                         self.tree[cur_ix].child = Some(text_node);
+                        self.link_stack.contain(1);
                         prev = cur;
                         cur = node;
                         if let Some(node_ix) = cur {
@@ -248,15 +897,28 @@ impl<'input, 'callback> Parser<'input, 'callback> {
                         });
                         if let Some((span, ix)) = inline_html {
                             let node = scan_nodes_to_ix(&self.tree, next, ix);
-                            self.tree[cur_ix].item.body = if !span.is_empty() {
-                                let converted_string =
-                                    String::from_utf8(span).expect("invalid utf8");
-                                ItemBody::OwnedHtml(
-                                    self.allocs.allocate_cow(converted_string.into()),
-                                )
-                            } else {
-                                ItemBody::Html
-                            };
+                            let start = self.tree[cur_ix].item.start;
+                            self.tree[cur_ix].item.body =
+                                if self.options.contains(Options::ENABLE_STRIP_HTML_COMMENTS)
+                                    && self.text[start..].starts_with("<!--")
+                                {
+                                    let comment = if span.is_empty() {
+                                        self.text[start..ix].into()
+                                    } else {
+                                        String::from_utf8(span).expect("invalid utf8").into()
+                                    };
+                                    ItemBody::Comment(
+                                        self.allocs.allocate_cow(html_comment_content(comment)),
+                                    )
+                                } else if !span.is_empty() {
+                                    let converted_string =
+                                        String::from_utf8(span).expect("invalid utf8");
+                                    ItemBody::OwnedHtml(
+                                        self.allocs.allocate_cow(converted_string.into()),
+                                    )
+                                } else {
+                                    ItemBody::Html
+                                };
                             self.tree[cur_ix].item.end = ix;
                             self.tree[cur_ix].next = node;
                             prev = cur;
@@ -281,10 +943,10 @@ impl<'input, 'callback> Parser<'input, 'callback> {
                         }
                     }
 
-                    if code_delims.is_populated() {
+                    if self.code_delims.is_populated() {
                         // we have previously scanned all codeblock delimiters,
                         // so we can reuse that work
-                        if let Some(scan_ix) = code_delims.find(cur_ix, search_count) {
+                        if let Some(scan_ix) = self.code_delims.find(cur_ix, search_count) {
                             self.make_code_span(cur_ix, scan_ix, preceded_by_backslash);
                         } else {
                             self.tree[cur_ix].item.body = ItemBody::Text;
@@ -303,10 +965,10 @@ impl<'input, 'callback> Parser<'input, 'callback> {
                             {
                                 if search_count == delim_count {
                                     self.make_code_span(cur_ix, scan_ix, preceded_by_backslash);
-                                    code_delims.clear();
+                                    self.code_delims.clear();
                                     break;
                                 } else {
-                                    code_delims.insert(delim_count, scan_ix);
+                                    self.code_delims.insert(delim_count, scan_ix);
                                 }
                             }
                             scan = self.tree[scan_ix].next;
@@ -321,6 +983,7 @@ impl<'input, 'callback> Parser<'input, 'callback> {
                     self.link_stack.push(LinkStackEl {
                         node: cur_ix,
                         ty: LinkStackTy::Link,
+                        height: 0,
                     });
                 }
                 ItemBody::MaybeImage => {
@@ -328,18 +991,26 @@ impl<'input, 'callback> Parser<'input, 'callback> {
                     self.link_stack.push(LinkStackEl {
                         node: cur_ix,
                         ty: LinkStackTy::Image,
+                        height: 0,
                     });
                 }
                 ItemBody::MaybeLinkClose(could_be_ref) => {
                     self.tree[cur_ix].item.body = ItemBody::Text;
                     if let Some(tos) = self.link_stack.pop() {
+                        // the elements inside the brackets end up inside the
+                        // element around them, whether or not they form a link
+                        self.link_stack.contain(tos.height);
                         if tos.ty == LinkStackTy::Disabled {
                             continue;
                         }
+                        let too_deep = tos.height >= self.inline_depth_left;
                         let next = self.tree[cur_ix].next;
                         if let Some((next_ix, url, title)) =
                             self.scan_inline_link(block_text, self.tree[cur_ix].item.end, next)
                         {
+                            if too_deep {
+                                continue;
+                            }
                             let next_node = scan_nodes_to_ix(&self.tree, next, next_ix);
                             if let Some(prev_ix) = prev {
                                 self.tree[prev_ix].next = None;
@@ -359,6 +1030,8 @@ impl<'input, 'callback> Parser<'input, 'callback> {
                                 self.tree[next_node_ix].item.start =
                                     max(self.tree[next_node_ix].item.start, next_ix);
                             }
+                            self.set_inline_height(cur_ix, tos.height + 1);
+                            self.link_stack.contain(tos.height + 1);
 
                             if tos.ty == LinkStackTy::Link {
                                 self.link_stack.disable_all_links();
@@ -429,16 +1102,31 @@ impl<'input, 'callback> Parser<'input, 'callback> {
 
                             // see if it's a footnote reference
                             if let Some((ReferenceLabel::Footnote(l), end)) = label {
+                                let cow_ix = self.allocs.allocate_cow(l);
+                                if self.options.contains(Options::ENABLE_DIAGNOSTICS) {
+                                    let range = self.tree[tos.node].item.start..end;
+                                    (self.diagnostic_state.footnote_references)
+                                        .push((cow_ix, range));
+                                }
                                 self.tree[tos.node].next = node_after_link;
                                 self.tree[tos.node].child = None;
-                                self.tree[tos.node].item.body =
-                                    ItemBody::FootnoteReference(self.allocs.allocate_cow(l));
+                                self.tree[tos.node].item.body = ItemBody::FootnoteReference(cow_ix);
                                 self.tree[tos.node].item.end = end;
                                 prev = Some(tos.node);
                                 cur = node_after_link;
                                 self.link_stack.clear();
                                 continue;
                             } else if let Some((ReferenceLabel::Link(link_label), end)) = label {
+                                let link_start = self.tree[tos.node].item.start;
+                                if self.options.contains(Options::ENABLE_DIAGNOSTICS) {
+                                    let key = UniCase::new(link_label.clone());
+                                    if let Some((label, _)) =
+                                        self.allocs.refdefs.0.get_key_value(&key)
+                                    {
+                                        (self.diagnostic_state.used_definitions)
+                                            .insert(label.clone());
+                                    }
+                                }
                                 let type_url_title = self
                                     .allocs
                                     .refdefs
@@ -458,7 +1146,7 @@ impl<'input, 'callback> Parser<'input, 'callback> {
                                             Some(callback) => {
                                                 // Construct a BrokenLink struct, which will be passed to the callback
                                                 let broken_link = BrokenLink {
-                                                    span: (self.tree[tos.node].item.start)..end,
+                                                    span: link_start..end,
                                                     link_type,
                                                     reference: link_label,
                                                 };
@@ -471,7 +1159,12 @@ impl<'input, 'callback> Parser<'input, 'callback> {
                                         }
                                     });
 
-                                if let Some((def_link_type, url, title)) = type_url_title {
+                                if type_url_title.is_none() {
+                                    self.report(DiagnosticCode::UnresolvedLink, link_start..end);
+                                }
+                                if let (Some((def_link_type, url, title)), false) =
+                                    (type_url_title, too_deep)
+                                {
                                     let link_ix =
                                         self.allocs.allocate_link(def_link_type, url, title);
                                     self.tree[tos.node].item.body = if tos.ty == LinkStackTy::Image
@@ -497,6 +1190,8 @@ impl<'input, 'callback> Parser<'input, 'callback> {
                                     }
 
                                     self.tree[tos.node].item.end = end;
+                                    self.set_inline_height(tos.node, tos.height + 1);
+                                    self.link_stack.contain(tos.height + 1);
 
                                     // set up cur so next node will be node_after_link
                                     cur = Some(tos.node);
@@ -510,7 +1205,24 @@ impl<'input, 'callback> Parser<'input, 'callback> {
                         }
                     }
                 }
-                _ => (),
+                ItemBody::MaybeComment => {
+                    let mut scan = self.tree[cur_ix].next;
+                    while let Some(scan_ix) = scan {
+                        if self.tree[scan_ix].item.body == ItemBody::MaybeComment {
+                            break;
+                        }
+                        scan = self.tree[scan_ix].next;
+                    }
+                    match scan {
+                        Some(close_ix) => self.make_comment(cur_ix, close_ix),
+                        None => self.tree[cur_ix].item.body = ItemBody::Text,
+                    }
+                }
+                _ => {
+                    // elements built by the inline passes of an enclosing chain
+                    let height = self.inline_height(cur_ix);
+                    self.link_stack.contain(height);
+                }
             }
             prev = cur;
             cur = self.tree[cur_ix].next;
@@ -518,7 +1230,30 @@ impl<'input, 'callback> Parser<'input, 'callback> {
         self.link_stack.clear();
     }
 
+    /// Turns the `%%` delimiters `open` and `close` and everything in between into
+    /// a single comment node.
+    fn make_comment(&mut self, open: TreeIndex, close: TreeIndex) {
+        let mut content = String::new();
+        let mut node = self.tree[open].next;
+        while let Some(node_ix) = node {
+            if node_ix == close {
+                break;
+            }
+            let item = self.tree[node_ix].item;
+            content.push_str(&self.text[item.start..item.end]);
+            node = self.tree[node_ix].next;
+        }
+        self.tree[open].item.body = ItemBody::Comment(self.allocs.allocate_cow(content.into()));
+        self.tree[open].item.end = self.tree[close].item.end;
+        self.tree[open].next = self.tree[close].next;
+    }
+
     fn handle_emphasis(&mut self) {
+        let mut unclosed = if self.options.contains(Options::ENABLE_DIAGNOSTICS) {
+            Some(&mut self.allocs.diagnostics)
+        } else {
+            None
+        };
         let mut prev = None;
         let mut prev_ix: TreeIndex;
         let mut cur = self.tree.cur();
@@ -532,42 +1267,63 @@ impl<'input, 'callback> Parser<'input, 'callback> {
                     let c = self.text.as_bytes()[self.tree[cur_ix].item.start];
                     let both = can_open && can_close;
                     if can_close {
-                        while let Some(el) =
-                            self.inline_stack.find_match(&mut self.tree, c, count, both)
-                        {
+                        while let Some(el) = self.inline_stack.find_match(
+                            &mut self.tree,
+                            c,
+                            count,
+                            both,
+                            unclosed.as_deref_mut(),
+                        ) {
                             // have a match!
-                            if let Some(prev_ix) = prev {
-                                self.tree[prev_ix].next = None;
-                            }
                             let match_count = min(count, el.count);
-                            // start, end are tree node indices
-                            let mut end = cur_ix - 1;
-                            let mut start = el.start + el.count;
-
-                            // work from the inside out
-                            while start > el.start + el.count - match_count {
-                                let (inc, ty) = if c == b'~' {
-                                    (2, ItemBody::Strikethrough)
-                                } else if start > el.start + el.count - match_count + 1 {
-                                    (2, ItemBody::Strong)
-                                } else {
-                                    (1, ItemBody::Emphasis)
-                                };
-
-                                let root = start - inc;
-                                end = end + inc;
-                                self.tree[root].item.body = ty;
-                                self.tree[root].item.end = self.tree[end].item.end;
-                                self.tree[root].child = Some(start);
-                                self.tree[root].next = None;
-                                start = root;
+                            // each level takes one or two delimiters on each side,
+                            // and those of the levels nested too deeply stay text
+                            let levels = self.inline_depth_left.saturating_sub(el.height);
+                            let fit = min(match_count, levels.saturating_mul(2));
+                            for i in fit..match_count {
+                                self.tree[el.start + el.count - 1 - i].item.body = ItemBody::Text;
+                                self.tree[cur_ix + i].item.body = ItemBody::Text;
                             }
+                            let mut height = el.height;
+                            cur = self.tree[cur_ix + match_count - 1].next;
+                            if fit > 0 {
+                                if let Some(prev_ix) = prev {
+                                    self.tree[prev_ix].next = None;
+                                }
+                                // start, end are tree node indices
+                                let mut end = cur_ix - 1;
+                                let mut start = el.start + el.count;
+
+                                // work from the inside out
+                                while start > el.start + el.count - fit {
+                                    let (inc, ty) = if c == b'~' {
+                                        (2, ItemBody::Strikethrough)
+                                    } else if start > el.start + el.count - fit + 1 {
+                                        (2, ItemBody::Strong)
+                                    } else {
+                                        (1, ItemBody::Emphasis)
+                                    };
+
+                                    let root = start - inc;
+                                    end = end + inc;
+                                    self.tree[root].item.body = ty;
+                                    self.tree[root].item.end = self.tree[end].item.end;
+                                    self.tree[root].child = Some(start);
+                                    self.tree[root].next = None;
+                                    start = root;
+                                    height += 1;
+                                }
 
-                            // set next for top most emph level
-                            prev_ix = el.start + el.count - match_count;
+                                // set next for top most emph level
+                                let top_ix = el.start + el.count - fit;
+                                self.tree[top_ix].next = self.tree[cur_ix + fit - 1].next;
+                            }
+                            prev_ix = if fit < match_count {
+                                cur_ix + match_count - 1
+                            } else {
+                                el.start + el.count - fit
+                            };
                             prev = Some(prev_ix);
-                            cur = self.tree[cur_ix + match_count - 1].next;
-                            self.tree[prev_ix].next = cur;
 
                             if el.count > match_count {
                                 self.inline_stack.push(InlineEl {
@@ -575,7 +1331,10 @@ impl<'input, 'callback> Parser<'input, 'callback> {
                                     count: el.count - match_count,
                                     c: el.c,
                                     both,
+                                    height,
                                 })
+                            } else {
+                                self.inline_stack.contain(height);
                             }
                             count -= match_count;
                             if count > 0 {
@@ -592,6 +1351,7 @@ impl<'input, 'callback> Parser<'input, 'callback> {
                                 count,
                                 c,
                                 both,
+                                height: 0,
                             });
                         } else {
                             for i in 0..count {
@@ -630,12 +1390,19 @@ impl<'input, 'callback> Parser<'input, 'callback> {
                     cur = self.tree[cur_ix].next;
                 }
                 _ => {
+                    // `unclosed` borrows the allocations, so no `inline_height`
+                    let height = if self.tree[cur_ix].item.body.is_inline_element() {
+                        self.inline_heights.get(&cur_ix).copied().unwrap_or(1)
+                    } else {
+                        0
+                    };
+                    self.inline_stack.contain(height);
                     prev = cur;
                     cur = self.tree[cur_ix].next;
                 }
             }
         }
-        self.inline_stack.pop_all(&mut self.tree);
+        self.inline_stack.pop_all(&mut self.tree, unclosed);
     }
 
     /// Returns next byte index, url and title.
@@ -810,17 +1577,36 @@ impl<'input, 'callback> Parser<'input, 'callback> {
         } else {
             self.text[span_start..span_end].into()
         };
-        if preceding_backslash {
+        let code_ix = if preceding_backslash {
             self.tree[open].item.body = ItemBody::Text;
             self.tree[open].item.end = self.tree[open].item.start + 1;
             self.tree[open].next = Some(close);
-            self.tree[close].item.body = ItemBody::Code(self.allocs.allocate_cow(cow));
             self.tree[close].item.start = self.tree[open].item.start + 1;
+            close
         } else {
-            self.tree[open].item.body = ItemBody::Code(self.allocs.allocate_cow(cow));
             self.tree[open].item.end = self.tree[close].item.end;
             self.tree[open].next = self.tree[close].next;
-        }
+            open
+        };
+
+        let span_end = self.tree[code_ix].item.end;
+        let raw_attribute = if self.options.contains(Options::ENABLE_RAW_ATTRIBUTES) {
+            scan_raw_attribute(&self.text[span_end..])
+        } else {
+            None
+        };
+        self.tree[code_ix].item.body = if let Some((attr_len, format)) = raw_attribute {
+            let attr_end = span_end + attr_len;
+            let next = scan_nodes_to_ix(&self.tree, self.tree[code_ix].next, attr_end);
+            if let Some(next_ix) = next {
+                self.tree[next_ix].item.start = max(self.tree[next_ix].item.start, attr_end);
+            }
+            self.tree[code_ix].next = next;
+            self.tree[code_ix].item.end = attr_end;
+            ItemBody::Raw(self.allocs.allocate_raw(format.into(), cow))
+        } else {
+            ItemBody::Code(self.allocs.allocate_cow(cow))
+        };
     }
 
     /// On success, returns a buffer containing the inline html and byte offset.
@@ -856,7 +1642,25 @@ impl<'input, 'callback> Parser<'input, 'callback> {
     /// `(Event, Range)` pairs, where the `Range` value maps to the corresponding
     /// range in the markdown source.
     pub fn into_offset_iter(self) -> OffsetIter<'input, 'callback> {
-        OffsetIter { inner: self }
+        OffsetIter {
+            inner: self,
+            origin: None,
+        }
+    }
+
+    /// Consumes the event iterator and produces an iterator that produces
+    /// `(Event, SourceSpan)` pairs, where the span gives the line and column
+    /// of the start and end of the event, counting columns in `unit`. See
+    /// [`LineIndex`](crate::line_index::LineIndex).
+    pub fn into_span_iter(self, unit: ColumnUnit) -> SpanIter<'input, 'callback> {
+        SpanIter::new(self.into_offset_iter(), unit)
+    }
+
+    /// Consumes the parser and builds a lossless syntax tree of the document,
+    /// from which the source can be reproduced exactly. See the [`cst`] module.
+    pub fn into_syntax_tree(self) -> SyntaxTree<'input> {
+        let text = self.text;
+        cst::build(text, self.into_offset_iter())
     }
 }
 
@@ -871,7 +1675,7 @@ pub(crate) fn scan_containers(tree: &Tree<Item>, line_start: &mut LineStart) ->
                     break;
                 }
             }
-            ItemBody::ListItem(indent) => {
+            ItemBody::ListItem(indent, _) => {
                 let save = line_start.clone();
                 if !line_start.scan_space(indent) && !line_start.is_at_eol() {
                     *line_start = save;
@@ -909,6 +1713,9 @@ struct InlineEl {
     count: usize,
     c: u8,      // b'*' or b'_'
     both: bool, // can both open and close
+    // how deeply the elements after the delimiters are nested, up to the
+    // delimiters above on the stack
+    height: usize,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -931,15 +1738,32 @@ impl InlineStack {
     const TILDES: usize = 5;
     const UNDERSCORE_BOTH: usize = 6;
 
-    fn pop_all(&mut self, tree: &mut Tree<Item>) {
+    fn clear(&mut self) {
+        self.stack.clear();
+        self.lower_bounds = [0; 7];
+    }
+
+    fn pop_all(&mut self, tree: &mut Tree<Item>, mut unclosed: Option<&mut Vec<Diagnostic>>) {
         for el in self.stack.drain(..) {
-            for i in 0..el.count {
-                tree[el.start + i].item.body = ItemBody::Text;
-            }
+            InlineStack::discard(tree, &el, unclosed.as_deref_mut());
         }
         self.lower_bounds = [0; 7];
     }
 
+    /// Turns the delimiters of an element that isn't matched into text, and
+    /// reports them to `unclosed` if they could only open emphasis.
+    fn discard(tree: &mut Tree<Item>, el: &InlineEl, unclosed: Option<&mut Vec<Diagnostic>>) {
+        for i in 0..el.count {
+            tree[el.start + i].item.body = ItemBody::Text;
+        }
+        if let (Some(diagnostics), false) = (unclosed, el.both) {
+            diagnostics.push(Diagnostic {
+                code: DiagnosticCode::UnclosedEmphasis,
+                range: tree[el.start].item.start..tree[el.start + (el.count - 1)].item.end,
+            });
+        }
+    }
+
     fn get_lowerbound(&self, c: u8, count: usize, both: bool) -> usize {
         if c == b'_' {
             if both {
@@ -985,6 +1809,7 @@ impl InlineStack {
         c: u8,
         count: usize,
         both: bool,
+        mut unclosed: Option<&mut Vec<Diagnostic>>,
     ) -> Option<InlineEl> {
         let lowerbound = min(self.stack.len(), self.get_lowerbound(c, count, both));
         let res = self.stack[lowerbound..]
@@ -995,12 +1820,11 @@ impl InlineStack {
                 el.c == c && (!both && !el.both || (count + el.count) % 3 != 0 || count % 3 == 0)
             });
 
-        if let Some((matching_ix, matching_el)) = res {
+        if let Some((matching_ix, mut matching_el)) = res {
             let matching_ix = matching_ix + lowerbound;
             for el in &self.stack[(matching_ix + 1)..] {
-                for i in 0..el.count {
-                    tree[el.start + i].item.body = ItemBody::Text;
-                }
+                InlineStack::discard(tree, el, unclosed.as_deref_mut());
+                matching_el.height = max(matching_el.height, el.height);
             }
             self.stack.truncate(matching_ix);
             Some(matching_el)
@@ -1013,6 +1837,14 @@ impl InlineStack {
     fn push(&mut self, el: InlineEl) {
         self.stack.push(el)
     }
+
+    /// Records an element of the given height after the delimiters on top of
+    /// the stack.
+    fn contain(&mut self, height: usize) {
+        if let Some(el) = self.stack.last_mut() {
+            el.height = max(el.height, height);
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -1024,6 +1856,16 @@ enum RefScan<'a> {
     Failed,
 }
 
+/// Returns the text of an HTML comment without the `<!--` and `-->` delimiters.
+fn html_comment_content(comment: CowStr<'_>) -> CowStr<'_> {
+    // `<!-->` and `<!--->` are empty comments
+    let end = max(4, comment.len() - 3);
+    match comment {
+        CowStr::Borrowed(s) => s[4..end].into(),
+        comment => comment[4..end].to_owned().into(),
+    }
+}
+
 /// Skips forward within a block to a node which spans (ends inclusive) the given
 /// index into the source.
 fn scan_nodes_to_ix(
@@ -1105,7 +1947,7 @@ impl LinkStack {
 
     fn pop(&mut self) -> Option<LinkStackEl> {
         let el = self.inner.pop();
-        self.disabled_ix = std::cmp::min(self.disabled_ix, self.inner.len());
+        self.disabled_ix = core::cmp::min(self.disabled_ix, self.inner.len());
         el
     }
 
@@ -1122,12 +1964,23 @@ impl LinkStack {
         }
         self.disabled_ix = self.inner.len();
     }
+
+    /// Records an element of the given height after the bracket on top of the
+    /// stack.
+    fn contain(&mut self, height: usize) {
+        if let Some(el) = self.inner.last_mut() {
+            el.height = max(el.height, height);
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 struct LinkStackEl {
     node: TreeIndex,
     ty: LinkStackTy,
+    // how deeply the elements after the bracket are nested, up to the
+    // brackets above on the stack
+    height: usize,
 }
 
 #[derive(PartialEq, Clone, Debug)]
@@ -1146,9 +1999,10 @@ pub struct LinkDef<'a> {
 }
 
 /// Tracks tree indices of code span delimiters of each length. It should prevent
-/// quadratic scanning behaviours by providing (amortized) constant time lookups.
+/// quadratic scanning behaviours by providing logarithmic time lookups.
+#[derive(Default)]
 struct CodeDelims {
-    inner: HashMap<usize, VecDeque<TreeIndex>>,
+    inner: BTreeMap<usize, VecDeque<TreeIndex>>,
     seen_first: bool,
 }
 
@@ -1204,18 +2058,36 @@ pub(crate) struct AlignmentIndex(usize);
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub(crate) struct HeadingIndex(NonZeroUsize);
 
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) struct RawIndex(usize);
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) struct CustomIndex(usize);
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) struct DefinitionIndex(usize);
+
+// Kept small so that list items fit their indent and block id into an `ItemBody`.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub(crate) struct BlockIdIndex(NonZeroU32);
+
 #[derive(Clone)]
 pub(crate) struct Allocations<'a> {
     pub refdefs: RefDefs<'a>,
     links: Vec<(LinkType, CowStr<'a>, CowStr<'a>)>,
     cows: Vec<CowStr<'a>>,
     alignments: Vec<Vec<Alignment>>,
-    headings: Vec<(Option<&'a str>, Vec<&'a str>)>,
+    pub headings: Vec<(Option<CowStr<'a>>, Vec<CowStr<'a>>)>,
+    raws: Vec<(CowStr<'a>, CowStr<'a>)>,
+    customs: Vec<(CowStr<'a>, CowStr<'a>)>,
+    block_ids: Vec<CowStr<'a>>,
+    definitions: Vec<(CowStr<'a>, CowStr<'a>, Option<CowStr<'a>>)>,
+    pub diagnostics: Vec<Diagnostic>,
 }
 
 /// Keeps track of the reference definitions defined in the document.
 #[derive(Clone, Default)]
-pub struct RefDefs<'input>(pub(crate) HashMap<LinkLabel<'input>, LinkDef<'input>>);
+pub struct RefDefs<'input>(pub(crate) BTreeMap<LinkLabel<'input>, LinkDef<'input>>);
 
 impl<'input, 'b, 's> RefDefs<'input>
 where
@@ -1232,14 +2104,35 @@ where
     }
 }
 
+impl<'input> RefDefs<'input> {
+    /// Converts the definitions into ones that don't borrow from the input.
+    pub(crate) fn into_owned(self) -> RefDefs<'static> {
+        let refdefs = self.0.into_iter().map(|(label, def)| {
+            let label = UniCase::new(label.into_inner().into_static());
+            let def = LinkDef {
+                dest: def.dest.into_static(),
+                title: def.title.map(CowStr::into_static),
+                span: def.span,
+            };
+            (label, def)
+        });
+        RefDefs(refdefs.collect())
+    }
+}
+
 impl<'a> Allocations<'a> {
     pub fn new() -> Self {
         Self {
             refdefs: RefDefs::default(),
-            links: Vec::with_capacity(128),
+            links: Vec::new(),
             cows: Vec::new(),
             alignments: Vec::new(),
             headings: Vec::new(),
+            raws: Vec::new(),
+            customs: Vec::new(),
+            block_ids: Vec::new(),
+            definitions: Vec::new(),
+            diagnostics: Vec::new(),
         }
     }
 
@@ -1263,12 +2156,147 @@ impl<'a> Allocations<'a> {
 
     pub fn allocate_heading(&mut self, attrs: (Option<&'a str>, Vec<&'a str>)) -> HeadingIndex {
         let ix = self.headings.len();
-        self.headings.push(attrs);
+        let (id, classes) = attrs;
+        let classes = classes.into_iter().map(CowStr::Borrowed).collect();
+        self.headings.push((id.map(CowStr::Borrowed), classes));
         // This won't panic. `self.headings.len()` can't be `usize::MAX` since
         // such a long Vec cannot fit in memory.
         let ix_nonzero = NonZeroUsize::new(ix.wrapping_add(1)).expect("too many headings");
         HeadingIndex(ix_nonzero)
     }
+
+    pub fn allocate_raw(&mut self, format: CowStr<'a>, content: CowStr<'a>) -> RawIndex {
+        let ix = self.raws.len();
+        self.raws.push((format, content));
+        RawIndex(ix)
+    }
+
+    pub fn report(&mut self, code: DiagnosticCode, range: Range<usize>) {
+        self.diagnostics.push(Diagnostic { code, range });
+    }
+
+    pub fn allocate_custom(&mut self, name: CowStr<'a>, data: CowStr<'a>) -> CustomIndex {
+        let ix = self.customs.len();
+        self.customs.push((name, data));
+        CustomIndex(ix)
+    }
+
+    pub fn allocate_definition(
+        &mut self,
+        label: CowStr<'a>,
+        dest: CowStr<'a>,
+        title: Option<CowStr<'a>>,
+    ) -> DefinitionIndex {
+        let ix = self.definitions.len();
+        self.definitions.push((label, dest, title));
+        DefinitionIndex(ix)
+    }
+
+    pub fn allocate_block_id(&mut self, id: &'a str) -> BlockIdIndex {
+        self.block_ids.push(CowStr::Borrowed(id));
+        // Every block id takes at least three bytes of input, so there can't be
+        // `u32::MAX` of them in any realistic document.
+        let ix = u32::try_from(self.block_ids.len()).expect("too many block ids");
+        BlockIdIndex(NonZeroU32::new(ix).unwrap())
+    }
+
+    /// Removes all allocations, keeping the memory of those that don't borrow
+    /// from the input for parsing another input. The others can't be reused
+    /// without unsafe code, since they borrow from the input.
+    pub fn recycle(mut self) -> Allocations<'static> {
+        self.alignments.clear();
+        self.diagnostics.clear();
+        Allocations {
+            alignments: self.alignments,
+            diagnostics: self.diagnostics,
+            ..Allocations::new()
+        }
+    }
+
+    /// Removes the identifier of a heading that is no longer in the tree, so
+    /// that it isn't reserved by a parser.
+    pub fn forget_heading_id(&mut self, ix: HeadingIndex) {
+        self.headings[ix.0.get() - 1].0 = None;
+    }
+
+    /// Converts the strings that borrow from the input into owned ones.
+    pub fn into_owned(self) -> Allocations<'static> {
+        let heading = |(id, classes): (Option<CowStr<'a>>, Vec<CowStr<'a>>)| {
+            let classes = classes.into_iter().map(CowStr::into_static).collect();
+            (id.map(CowStr::into_static), classes)
+        };
+        Allocations {
+            refdefs: self.refdefs.into_owned(),
+            links: (self.links.into_iter())
+                .map(|(ty, url, title)| (ty, url.into_static(), title.into_static()))
+                .collect(),
+            cows: self.cows.into_iter().map(CowStr::into_static).collect(),
+            alignments: self.alignments,
+            headings: self.headings.into_iter().map(heading).collect(),
+            raws: (self.raws.into_iter())
+                .map(|(format, content)| (format.into_static(), content.into_static()))
+                .collect(),
+            customs: (self.customs.into_iter())
+                .map(|(name, data)| (name.into_static(), data.into_static()))
+                .collect(),
+            block_ids: self
+                .block_ids
+                .into_iter()
+                .map(CowStr::into_static)
+                .collect(),
+            definitions: (self.definitions.into_iter())
+                .map(|(label, dest, title)| {
+                    let title = title.map(CowStr::into_static);
+                    (label.into_static(), dest.into_static(), title)
+                })
+                .collect(),
+            diagnostics: self.diagnostics,
+        }
+    }
+
+    /// Returns a copy whose strings borrow from these allocations.
+    pub fn borrowed(&self) -> Allocations<'_> {
+        fn borrow<'b>(s: &'b CowStr<'_>) -> CowStr<'b> {
+            CowStr::Borrowed(s)
+        }
+        let refdefs = self.refdefs.0.iter().map(|(label, def)| {
+            let def = LinkDef {
+                dest: borrow(&def.dest),
+                title: def.title.as_ref().map(borrow),
+                span: def.span.clone(),
+            };
+            (UniCase::new(borrow(label)), def)
+        });
+        Allocations {
+            refdefs: RefDefs(refdefs.collect()),
+            links: (self.links.iter())
+                .map(|(ty, url, title)| (*ty, borrow(url), borrow(title)))
+                .collect(),
+            cows: self.cows.iter().map(borrow).collect(),
+            alignments: self.alignments.clone(),
+            headings: (self.headings.iter())
+                .map(|(id, classes)| {
+                    (
+                        id.as_ref().map(borrow),
+                        classes.iter().map(borrow).collect(),
+                    )
+                })
+                .collect(),
+            raws: (self.raws.iter())
+                .map(|(format, content)| (borrow(format), borrow(content)))
+                .collect(),
+            customs: (self.customs.iter())
+                .map(|(name, data)| (borrow(name), borrow(data)))
+                .collect(),
+            block_ids: self.block_ids.iter().map(borrow).collect(),
+            definitions: (self.definitions.iter())
+                .map(|(label, dest, title)| {
+                    (borrow(label), borrow(dest), title.as_ref().map(borrow))
+                })
+                .collect(),
+            diagnostics: self.diagnostics.clone(),
+        }
+    }
 }
 
 impl<'a> Index<CowIndex> for Allocations<'a> {
@@ -1295,8 +2323,40 @@ impl<'a> Index<AlignmentIndex> for Allocations<'a> {
     }
 }
 
+impl<'a> Index<RawIndex> for Allocations<'a> {
+    type Output = (CowStr<'a>, CowStr<'a>);
+
+    fn index(&self, ix: RawIndex) -> &Self::Output {
+        self.raws.index(ix.0)
+    }
+}
+
+impl<'a> Index<CustomIndex> for Allocations<'a> {
+    type Output = (CowStr<'a>, CowStr<'a>);
+
+    fn index(&self, ix: CustomIndex) -> &Self::Output {
+        self.customs.index(ix.0)
+    }
+}
+
+impl<'a> Index<DefinitionIndex> for Allocations<'a> {
+    type Output = (CowStr<'a>, CowStr<'a>, Option<CowStr<'a>>);
+
+    fn index(&self, ix: DefinitionIndex) -> &Self::Output {
+        self.definitions.index(ix.0)
+    }
+}
+
+impl<'a> Index<BlockIdIndex> for Allocations<'a> {
+    type Output = CowStr<'a>;
+
+    fn index(&self, ix: BlockIdIndex) -> &Self::Output {
+        self.block_ids.index(ix.0.get() as usize - 1)
+    }
+}
+
 impl<'a> Index<HeadingIndex> for Allocations<'a> {
-    type Output = (Option<&'a str>, Vec<&'a str>);
+    type Output = (Option<CowStr<'a>>, Vec<CowStr<'a>>);
 
     fn index(&self, ix: HeadingIndex) -> &Self::Output {
         self.headings.index(ix.0.get() - 1)
@@ -1318,6 +2378,22 @@ pub(crate) struct HtmlScanGuard {
 pub type BrokenLinkCallback<'input, 'borrow> =
     Option<&'borrow mut dyn FnMut(BrokenLink<'input>) -> Option<(CowStr<'input>, CowStr<'input>)>>;
 
+type IncludeResolver<'borrow> = Option<&'borrow mut dyn FnMut(&str) -> Option<CowStr<'static>>>;
+
+/// The path and text of an included document.
+struct IncludedDocument {
+    path: String,
+    text: CowStr<'static>,
+}
+
+/// The maximum nesting depth of included documents.
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+/// The number of `[TOC]` placeholders that are replaced by a table of contents.
+/// Each one copies all headings, so the output would grow quadratically with
+/// the input otherwise.
+const MAX_TOC_PLACEHOLDERS: usize = 8;
+
 /// Markdown event and source range iterator.
 ///
 /// Generates tuples where the first element is the markdown event and the second
@@ -1327,6 +2403,7 @@ pub type BrokenLinkCallback<'input, 'borrow> =
 /// [`into_offset_iter`](struct.Parser.html#method.into_offset_iter) method.
 pub struct OffsetIter<'a, 'b> {
     inner: Parser<'a, 'b>,
+    origin: Option<Rc<IncludedDocument>>,
 }
 
 impl<'a, 'b> OffsetIter<'a, 'b> {
@@ -1334,44 +2411,122 @@ impl<'a, 'b> OffsetIter<'a, 'b> {
     pub fn reference_definitions(&self) -> &RefDefs {
         self.inner.reference_definitions()
     }
+
+    /// Returns the diagnostics found so far, see [`Parser::diagnostics`].
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        self.inner.diagnostics()
+    }
+
+    /// Returns the path of the included document that the range of the last
+    /// event refers to, or `None` if it refers to the main document. See
+    /// [`Parser::with_include_resolver`].
+    pub fn origin(&self) -> Option<&str> {
+        self.origin.as_ref().map(|document| &*document.path)
+    }
+
+    /// Returns the events of the top-level block `ix` with their ranges,
+    /// without moving on to the blocks after it.
+    pub(crate) fn block_events(&mut self, ix: TreeIndex) -> Vec<(Event<'a>, Range<usize>)> {
+        let next = self.inner.tree[ix].next.take();
+        self.inner.tree.set_position((Vec::new(), Some(ix)));
+        let events = self.by_ref().collect();
+        self.inner.tree[ix].next = next;
+        events
+    }
+
+    /// Returns the tree and the allocations, with the inline content of the
+    /// blocks whose events have been returned resolved.
+    pub(crate) fn into_parts(self) -> (Tree<Item>, Allocations<'a>) {
+        (self.inner.tree, self.inner.allocs)
+    }
+
+    /// Returns the text of the main document.
+    pub(crate) fn text(&self) -> &'a str {
+        self.inner.text
+    }
+
+    /// Returns the text that the range of the last event refers to.
+    pub(crate) fn source(&self) -> &str {
+        self.origin
+            .as_ref()
+            .map_or(self.inner.text, |document| &*document.text)
+    }
 }
 
 impl<'a, 'b> Iterator for OffsetIter<'a, 'b> {
     type Item = (Event<'a>, Range<usize>);
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.inner.tree.cur() {
-            None => {
-                let ix = self.inner.tree.pop()?;
-                let tag = item_to_tag(&self.inner.tree[ix].item, &self.inner.allocs);
-                self.inner.tree.next_sibling(ix);
-                let span = self.inner.tree[ix].item.start..self.inner.tree[ix].item.end;
-                debug_assert!(span.start <= span.end);
-                Some((Event::End(tag), span))
-            }
-            Some(cur_ix) => {
-                if self.inner.tree[cur_ix].item.body.is_inline() {
-                    self.inner.handle_inline();
+        if let Some((event, range, origin)) = self.inner.included.pop_front() {
+            self.origin = origin;
+            return Some((event, range));
+        }
+        self.origin = None;
+        loop {
+            match self.inner.tree.cur() {
+                None => {
+                    let ix = match self.inner.tree.pop() {
+                        Some(ix) => ix,
+                        None => {
+                            self.inner.finish_diagnostics();
+                            return None;
+                        }
+                    };
+                    let tag = item_to_tag(&self.inner.tree[ix].item, &self.inner.allocs);
+                    self.inner.tree.next_sibling(ix);
+                    let span = self.inner.tree[ix].item.start..self.inner.tree[ix].item.end;
+                    debug_assert!(span.start <= span.end);
+                    return Some((Event::End(tag), span));
                 }
+                Some(cur_ix) => {
+                    if self.inner.tree[cur_ix].item.body.is_inline() {
+                        self.inner.handle_inline();
+                    } else if self.inner.splice_include(cur_ix) {
+                        return self.next();
+                    } else if self.inner.options.contains(Options::ENABLE_HEADING_IDS) {
+                        self.inner.generate_heading_id(cur_ix);
+                        if self.inner.options.contains(Options::ENABLE_TOC)
+                            && matches!(self.inner.tree[cur_ix].item.body, ItemBody::Paragraph(_))
+                        {
+                            self.inner.expand_toc_placeholder(cur_ix);
+                        }
+                    }
+                    if self.inner.is_hidden_comment(cur_ix) {
+                        self.inner.tree.next_sibling(cur_ix);
+                        continue;
+                    }
 
-                let node = self.inner.tree[cur_ix];
-                let item = node.item;
-                let event = item_to_event(item, self.inner.text, &self.inner.allocs);
-                if let Event::Start(..) = event {
-                    self.inner.tree.push();
-                } else {
-                    self.inner.tree.next_sibling(cur_ix);
+                    let node = self.inner.tree[cur_ix];
+                    let item = node.item;
+                    let event = item_to_event(item, self.inner.text, &self.inner.allocs);
+                    if let Event::Start(..) = event {
+                        self.inner.tree.push();
+                    } else {
+                        self.inner.tree.next_sibling(cur_ix);
+                    }
+                    debug_assert!(item.start <= item.end);
+                    return Some((event, item.start..item.end));
                 }
-                debug_assert!(item.start <= item.end);
-                Some((event, item.start..item.end))
             }
         }
     }
 }
 
+fn list_marker(c: u8) -> ListMarker {
+    match c {
+        b'-' => ListMarker::Dash,
+        b'*' => ListMarker::Star,
+        b'+' => ListMarker::Plus,
+        b'.' => ListMarker::Period,
+        b')' => ListMarker::OneParen,
+        b'(' => ListMarker::TwoParens,
+        _ => panic!("unexpected list marker {:?}", c as char),
+    }
+}
+
 fn item_to_tag<'a>(item: &Item, allocs: &Allocations<'a>) -> Tag<'a> {
     match item.body {
-        ItemBody::Paragraph => Tag::Paragraph,
+        ItemBody::Paragraph(block_id) => Tag::Paragraph(block_id.map(|ix| allocs[ix].clone())),
         ItemBody::Emphasis => Tag::Emphasis,
         ItemBody::Strong => Tag::Strong,
         ItemBody::Strikethrough => Tag::Strikethrough,
@@ -1385,7 +2540,7 @@ fn item_to_tag<'a>(item: &Item, allocs: &Allocations<'a>) -> Tag<'a> {
         }
         ItemBody::Heading(level, Some(heading_ix)) => {
             let (id, classes) = allocs.index(heading_ix);
-            Tag::Heading(level, *id, classes.clone())
+            Tag::Heading(level, id.clone(), classes.clone())
         }
         ItemBody::Heading(level, None) => Tag::Heading(level, None, Vec::new()),
         ItemBody::FencedCodeBlock(cow_ix) => {
@@ -1393,17 +2548,25 @@ fn item_to_tag<'a>(item: &Item, allocs: &Allocations<'a>) -> Tag<'a> {
         }
         ItemBody::IndentCodeBlock => Tag::CodeBlock(CodeBlockKind::Indented),
         ItemBody::BlockQuote => Tag::BlockQuote,
-        ItemBody::List(_, c, listitem_start) => {
-            if c == b'.' || c == b')' {
-                Tag::List(Some(listitem_start))
+        ItemBody::List(is_tight, c, numbering, listitem_start) => {
+            let marker = list_marker(c);
+            let start = if marker.is_ordered() {
+                Some(listitem_start)
             } else {
-                Tag::List(None)
-            }
+                None
+            };
+            Tag::List(start, numbering, marker, is_tight)
         }
-        ItemBody::ListItem(_) => Tag::Item,
+        ItemBody::ListItem(_, block_id) => Tag::Item(block_id.map(|ix| allocs[ix].clone())),
         ItemBody::TableHead => Tag::TableHead,
         ItemBody::TableCell => Tag::TableCell,
         ItemBody::TableRow => Tag::TableRow,
+        ItemBody::LineBlock => Tag::LineBlock,
+        ItemBody::Line => Tag::Line,
+        ItemBody::Custom(custom_ix) => {
+            let (name, data) = &allocs[custom_ix];
+            Tag::Custom(name.clone(), data.clone())
+        }
         ItemBody::Table(alignment_ix) => Tag::Table(allocs[alignment_ix].clone()),
         ItemBody::FootnoteDefinition(cow_ix) => Tag::FootnoteDefinition(allocs[cow_ix].clone()),
         _ => panic!("unexpected item body {:?}", item.body),
@@ -1425,8 +2588,29 @@ fn item_to_event<'a>(item: Item, text: &'a str, allocs: &Allocations<'a>) -> Eve
         }
         ItemBody::TaskListMarker(checked) => return Event::TaskListMarker(checked),
         ItemBody::Rule => return Event::Rule,
+        ItemBody::Raw(raw_ix) => {
+            let (format, content) = &allocs[raw_ix];
+            return Event::Raw {
+                format: format.clone(),
+                content: content.clone(),
+            };
+        }
+        ItemBody::Comment(cow_ix) => return Event::Comment(allocs[cow_ix].clone()),
+        ItemBody::Hashtag => return Event::Hashtag(text[item.start + 1..item.end].into()),
+        ItemBody::CustomInline(custom_ix) => {
+            let (name, data) = &allocs[custom_ix];
+            return Event::Custom(name.clone(), data.clone());
+        }
+        ItemBody::LinkDefinition(definition_ix) => {
+            let (label, dest, title) = &allocs[definition_ix];
+            return Event::LinkDefinition {
+                label: label.clone(),
+                dest: dest.clone(),
+                title: title.clone(),
+            };
+        }
 
-        ItemBody::Paragraph => Tag::Paragraph,
+        ItemBody::Paragraph(block_id) => Tag::Paragraph(block_id.map(|ix| allocs[ix].clone())),
         ItemBody::Emphasis => Tag::Emphasis,
         ItemBody::Strong => Tag::Strong,
         ItemBody::Strikethrough => Tag::Strikethrough,
@@ -1440,7 +2624,7 @@ fn item_to_event<'a>(item: Item, text: &'a str, allocs: &Allocations<'a>) -> Eve
         }
         ItemBody::Heading(level, Some(heading_ix)) => {
             let (id, classes) = allocs.index(heading_ix);
-            Tag::Heading(level, *id, classes.clone())
+            Tag::Heading(level, id.clone(), classes.clone())
         }
         ItemBody::Heading(level, None) => Tag::Heading(level, None, Vec::new()),
         ItemBody::FencedCodeBlock(cow_ix) => {
@@ -1448,17 +2632,25 @@ fn item_to_event<'a>(item: Item, text: &'a str, allocs: &Allocations<'a>) -> Eve
         }
         ItemBody::IndentCodeBlock => Tag::CodeBlock(CodeBlockKind::Indented),
         ItemBody::BlockQuote => Tag::BlockQuote,
-        ItemBody::List(_, c, listitem_start) => {
-            if c == b'.' || c == b')' {
-                Tag::List(Some(listitem_start))
+        ItemBody::List(is_tight, c, numbering, listitem_start) => {
+            let marker = list_marker(c);
+            let start = if marker.is_ordered() {
+                Some(listitem_start)
             } else {
-                Tag::List(None)
-            }
+                None
+            };
+            Tag::List(start, numbering, marker, is_tight)
         }
-        ItemBody::ListItem(_) => Tag::Item,
+        ItemBody::ListItem(_, block_id) => Tag::Item(block_id.map(|ix| allocs[ix].clone())),
         ItemBody::TableHead => Tag::TableHead,
         ItemBody::TableCell => Tag::TableCell,
         ItemBody::TableRow => Tag::TableRow,
+        ItemBody::LineBlock => Tag::LineBlock,
+        ItemBody::Line => Tag::Line,
+        ItemBody::Custom(custom_ix) => {
+            let (name, data) = &allocs[custom_ix];
+            Tag::Custom(name.clone(), data.clone())
+        }
         ItemBody::Table(alignment_ix) => Tag::Table(allocs[alignment_ix].clone()),
         ItemBody::FootnoteDefinition(cow_ix) => Tag::FootnoteDefinition(allocs[cow_ix].clone()),
         _ => panic!("unexpected item body {:?}", item.body),
@@ -1471,27 +2663,51 @@ impl<'a, 'b> Iterator for Parser<'a, 'b> {
     type Item = Event<'a>;
 
     fn next(&mut self) -> Option<Event<'a>> {
-        match self.tree.cur() {
-            None => {
-                let ix = self.tree.pop()?;
-                let tag = item_to_tag(&self.tree[ix].item, &self.allocs);
-                self.tree.next_sibling(ix);
-                Some(Event::End(tag))
-            }
-            Some(cur_ix) => {
-                if self.tree[cur_ix].item.body.is_inline() {
-                    self.handle_inline();
+        if let Some((event, _, _)) = self.included.pop_front() {
+            return Some(event);
+        }
+        loop {
+            match self.tree.cur() {
+                None => {
+                    let ix = match self.tree.pop() {
+                        Some(ix) => ix,
+                        None => {
+                            self.finish_diagnostics();
+                            return None;
+                        }
+                    };
+                    let tag = item_to_tag(&self.tree[ix].item, &self.allocs);
+                    self.tree.next_sibling(ix);
+                    return Some(Event::End(tag));
                 }
+                Some(cur_ix) => {
+                    if self.tree[cur_ix].item.body.is_inline() {
+                        self.handle_inline();
+                    } else if self.splice_include(cur_ix) {
+                        return self.next();
+                    } else if self.options.contains(Options::ENABLE_HEADING_IDS) {
+                        self.generate_heading_id(cur_ix);
+                        if self.options.contains(Options::ENABLE_TOC)
+                            && matches!(self.tree[cur_ix].item.body, ItemBody::Paragraph(_))
+                        {
+                            self.expand_toc_placeholder(cur_ix);
+                        }
+                    }
+                    if self.is_hidden_comment(cur_ix) {
+                        self.tree.next_sibling(cur_ix);
+                        continue;
+                    }
 
-                let node = self.tree[cur_ix];
-                let item = node.item;
-                let event = item_to_event(item, self.text, &self.allocs);
-                if let Event::Start(..) = event {
-                    self.tree.push();
-                } else {
-                    self.tree.next_sibling(cur_ix);
+                    let node = self.tree[cur_ix];
+                    let item = node.item;
+                    let event = item_to_event(item, self.text, &self.allocs);
+                    if let Event::Start(..) = event {
+                        self.tree.push();
+                    } else {
+                        self.tree.next_sibling(cur_ix);
+                    }
+                    return Some(event);
                 }
-                Some(event)
             }
         }
     }
@@ -1499,6 +2715,92 @@ impl<'a, 'b> Iterator for Parser<'a, 'b> {
 
 impl FusedIterator for Parser<'_, '_> {}
 
+/// Buffers that parsers can reuse, to avoid allocating them anew for every
+/// document when parsing many small ones.
+///
+/// A parser created by the pool takes its buffers, and gives them back when it
+/// is passed to [`recycle`](Self::recycle) after use.
+///
+/// # Example
+/// ```rust
+/// use pulldown_cmark::{html, Options, ParserPool};
+///
+/// let mut pool = ParserPool::new();
+/// let mut html_output = String::new();
+/// for comment in ["*first*", "second"].iter() {
+///     let mut parser = pool.parser(comment, Options::empty());
+///     html::push_html(&mut html_output, &mut parser);
+///     pool.recycle(parser);
+/// }
+/// assert_eq!("<p><em>first</em></p>\n<p>second</p>\n", html_output);
+/// ```
+#[derive(Default)]
+pub struct ParserPool {
+    tree: Option<Tree<Item>>,
+    allocs: Option<Allocations<'static>>,
+    inline_stack: InlineStack,
+    link_stack: LinkStack,
+    code_delims: CodeDelims,
+}
+
+impl ParserPool {
+    /// Creates an empty pool. Buffers are allocated by the first parser.
+    pub fn new() -> Self {
+        ParserPool::default()
+    }
+
+    /// Creates a new event iterator for a markdown string with given options,
+    /// like [`Parser::new_ext`].
+    pub fn parser<'input, 'callback>(
+        &mut self,
+        text: &'input str,
+        options: Options,
+    ) -> Parser<'input, 'callback> {
+        self.parser_with_broken_link_callback(text, options, None)
+    }
+
+    /// Creates a new event iterator like [`Parser::new_with_broken_link_callback`].
+    pub fn parser_with_broken_link_callback<'input, 'callback>(
+        &mut self,
+        text: &'input str,
+        options: Options,
+        broken_link_callback: BrokenLinkCallback<'input, 'callback>,
+    ) -> Parser<'input, 'callback> {
+        let (tree, allocs) = match (self.tree.take(), self.allocs.take()) {
+            (Some(tree), Some(allocs)) => {
+                run_first_pass_with(text, options, tree, allocs, &mut [], &mut [])
+            }
+            _ => run_first_pass(text, options, &mut [], &mut []),
+        };
+        let mut parser = Parser::from_first_pass(text, options, tree, allocs, broken_link_callback);
+        parser.inline_stack = core::mem::take(&mut self.inline_stack);
+        parser.link_stack = core::mem::take(&mut self.link_stack);
+        parser.code_delims = core::mem::take(&mut self.code_delims);
+        parser
+    }
+
+    /// Takes back the buffers of a parser, to be reused by the next one.
+    pub fn recycle(&mut self, parser: Parser<'_, '_>) {
+        let Parser {
+            mut tree,
+            allocs,
+            mut inline_stack,
+            mut link_stack,
+            mut code_delims,
+            ..
+        } = parser;
+        tree.clear();
+        inline_stack.clear();
+        link_stack.clear();
+        code_delims.clear();
+        self.tree = Some(tree);
+        self.allocs = Some(allocs.recycle());
+        self.inline_stack = inline_stack;
+        self.link_stack = link_stack;
+        self.code_delims = code_delims;
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -1519,14 +2821,14 @@ mod test {
     #[test]
     #[cfg(target_pointer_width = "64")]
     fn node_size() {
-        let node_size = std::mem::size_of::<Node<Item>>();
+        let node_size = core::mem::size_of::<Node<Item>>();
         assert_eq!(48, node_size);
     }
 
     #[test]
     #[cfg(target_pointer_width = "64")]
     fn body_size() {
-        let body_size = std::mem::size_of::<ItemBody>();
+        let body_size = core::mem::size_of::<ItemBody>();
         assert_eq!(16, body_size);
     }
 
@@ -1584,7 +2886,7 @@ mod test {
 
     #[test]
     fn issue_283() {
-        let input = std::str::from_utf8(b"\xf0\x9b\xb2\x9f<td:^\xf0\x9b\xb2\x9f").unwrap();
+        let input = core::str::from_utf8(b"\xf0\x9b\xb2\x9f<td:^\xf0\x9b\xb2\x9f").unwrap();
         // dont crash
         parser_with_extensions(input).count();
     }
@@ -1700,6 +3002,369 @@ mod test {
         assert_eq!(expected_offsets, event_offsets);
     }
 
+    #[test]
+    fn list_tags() {
+        let lists: Vec<_> = Parser::new("- a\n- b\n\n3) c\n\n   d\n\n+ e\n")
+            .filter_map(|event| match event {
+                Event::Start(tag @ Tag::List(..)) => Some(tag),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            lists,
+            vec![
+                Tag::List(None, ListNumbering::Decimal, ListMarker::Dash, true),
+                Tag::List(Some(3), ListNumbering::Decimal, ListMarker::OneParen, false),
+                Tag::List(None, ListNumbering::Decimal, ListMarker::Plus, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn tight_list_paragraphs() {
+        let text = "* a\n* b\n";
+        let paragraphs = |options| {
+            Parser::new_ext(text, options)
+                .filter(|event| *event == Event::Start(Tag::Paragraph(None)))
+                .count()
+        };
+        assert_eq!(0, paragraphs(Options::empty()));
+        assert_eq!(2, paragraphs(Options::ENABLE_TIGHT_LIST_PARAGRAPHS));
+    }
+
+    #[test]
+    fn heading_ids() {
+        let text =
+            "# Foo *bar* `baz`\n\n## Foo bar baz\n\n# Explicit {#foo-bar-baz-2}\n\n# Foo bar baz\n";
+        let ids = |parser: Parser| {
+            parser
+                .filter_map(|event| match event {
+                    Event::Start(Tag::Heading(_, id, _)) => Some(id.map(|id| id.into_string())),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let options = Options::ENABLE_HEADING_IDS | Options::ENABLE_HEADING_ATTRIBUTES;
+        assert_eq!(
+            vec![
+                Some("foo-bar-baz".to_owned()),
+                Some("foo-bar-baz-1".to_owned()),
+                Some("foo-bar-baz-2".to_owned()),
+                Some("foo-bar-baz-3".to_owned()),
+            ],
+            ids(Parser::new_ext(text, options))
+        );
+
+        let mut slugify = |text: &str| text.to_uppercase();
+        let parser =
+            Parser::new_ext("# a\n# b", Options::ENABLE_HEADING_IDS).with_slugifier(&mut slugify);
+        assert_eq!(
+            vec![Some("A".to_owned()), Some("B".to_owned())],
+            ids(parser)
+        );
+        assert_eq!(vec![None], ids(Parser::new("# a")));
+    }
+
+    #[test]
+    fn raw_attributes() {
+        let text = "`a``b`{=tex_2}x\n\n~~~{=tex}\n  c\n\td\n~~~\n";
+        let events = Parser::new_ext(text, Options::ENABLE_RAW_ATTRIBUTES)
+            .into_offset_iter()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (Event::Start(Tag::Paragraph(None)), 0..16),
+                (
+                    Event::Raw {
+                        format: "tex_2".into(),
+                        content: "a``b".into(),
+                    },
+                    0..14
+                ),
+                (Event::Text("x".into()), 14..15),
+                (Event::End(Tag::Paragraph(None)), 0..16),
+                (
+                    Event::Raw {
+                        format: "tex".into(),
+                        content: "  c\n\td\n".into(),
+                    },
+                    17..37
+                ),
+            ],
+            events
+        );
+    }
+
+    #[test]
+    fn comment_events() {
+        let text = "a %%TODO: b%% <!--c-->\n\n%%\nd\n%%\n<!-->\n";
+        let comments = |options| {
+            Parser::new_ext(text, options)
+                .into_offset_iter()
+                .filter_map(|(event, range)| match event {
+                    Event::Comment(text) => Some((text.into_string(), range)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let options = Options::ENABLE_COMMENTS | Options::ENABLE_STRIP_HTML_COMMENTS;
+        assert_eq!(Vec::<(String, Range<usize>)>::new(), comments(options));
+        assert_eq!(
+            vec![
+                ("TODO: b".to_owned(), 2..13),
+                ("c".to_owned(), 14..22),
+                ("\nd\n".to_owned(), 24..31),
+                ("".to_owned(), 32..37),
+            ],
+            comments(options | Options::ENABLE_COMMENT_EVENTS)
+        );
+    }
+
+    #[test]
+    fn link_definition_events() {
+        let text = "[a]: /a\n> [B  b]:\n> /b 'c'\n\n[a]: /d ''\n[a]\n";
+        let definitions = |options| {
+            Parser::new_ext(text, options)
+                .into_offset_iter()
+                .filter(|(event, _)| matches!(event, Event::LinkDefinition { .. }))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            Vec::<(Event, Range<usize>)>::new(),
+            definitions(Options::empty())
+        );
+        assert_eq!(
+            vec![
+                (
+                    Event::LinkDefinition {
+                        label: "a".into(),
+                        dest: "/a".into(),
+                        title: None,
+                    },
+                    0..7
+                ),
+                (
+                    Event::LinkDefinition {
+                        label: "B b".into(),
+                        dest: "/b".into(),
+                        title: Some("c".into()),
+                    },
+                    10..26
+                ),
+                (
+                    Event::LinkDefinition {
+                        label: "a".into(),
+                        dest: "/d".into(),
+                        title: Some("".into()),
+                    },
+                    28..38
+                ),
+            ],
+            definitions(Options::ENABLE_LINK_DEFINITION_EVENTS)
+        );
+    }
+
+    #[test]
+    fn hashtags() {
+        let text = "# Notes #b\n\n#a and `#code` <span title=\" #html\"></span> #a\n\
+                    [#c/d](http://x.com/#frag) page#e #123\n";
+        let mut parser = Parser::new_ext(text, Options::ENABLE_HASHTAGS);
+        assert_eq!(
+            Some(Event::Start(Tag::Heading(HeadingLevel::H1, None, vec![]))),
+            parser.next()
+        );
+        let summary: Vec<CowStr> = vec!["b".into(), "a".into(), "c/d".into()];
+        assert_eq!(summary, parser.hashtags());
+        let tags = parser
+            .filter_map(|event| match event {
+                Event::Hashtag(tag) => Some(tag.into_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["b", "a", "a", "c/d"], tags);
+    }
+
+    #[test]
+    fn block_ids() {
+        let text = "*a* ^one\n\n- [x] b ^two\n\nc\n^three\n";
+        let tags = Parser::new_ext(text, Options::ENABLE_BLOCK_IDS | Options::ENABLE_TASKLISTS)
+            .into_offset_iter()
+            .filter(|(event, _)| matches!(event, Event::Start(..) | Event::Text(..)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (Event::Start(Tag::Paragraph(Some("one".into()))), 0..9),
+                (Event::Start(Tag::Emphasis), 0..3),
+                (Event::Text("a".into()), 1..2),
+                (
+                    Event::Start(Tag::List(
+                        None,
+                        ListNumbering::Decimal,
+                        ListMarker::Dash,
+                        true
+                    )),
+                    10..24
+                ),
+                (Event::Start(Tag::Item(Some("two".into()))), 10..24),
+                (Event::Text("b".into()), 16..17),
+                (Event::Start(Tag::Paragraph(None)), 24..33),
+                (Event::Text("c".into()), 24..25),
+                (Event::Text("^three".into()), 26..32),
+            ],
+            tags
+        );
+    }
+
+    #[test]
+    fn block_ids_in_tight_item() {
+        let text = "- a ^one\n  ***\n  b ^two\n- c\n";
+        let events = Parser::new_ext(text, Options::ENABLE_BLOCK_IDS)
+            .skip(1)
+            .take(8)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                Event::Start(Tag::Item(Some("one".into()))),
+                Event::Text("a".into()),
+                Event::Rule,
+                Event::Start(Tag::Paragraph(Some("two".into()))),
+                Event::Text("b".into()),
+                Event::End(Tag::Paragraph(Some("two".into()))),
+                Event::End(Tag::Item(Some("one".into()))),
+                Event::Start(Tag::Item(None)),
+            ],
+            events
+        );
+    }
+
+    #[test]
+    fn include_offsets() {
+        let text = "# Main\n\n{{#include a.md}}\n\nend\n";
+        let mut resolver = |path: &str| match path {
+            "a.md" => Some("*a*\n\n{{#include b.md}}\n".into()),
+            "b.md" => Some("b [link]\n\n[link]: /b\n".into()),
+            _ => None,
+        };
+        let mut iter = Parser::new(text)
+            .with_include_resolver(&mut resolver)
+            .into_offset_iter();
+        let mut texts = Vec::new();
+        while let Some((event, range)) = iter.next() {
+            if let Event::Text(text) = event {
+                texts.push((text.into_string(), range, iter.origin().map(str::to_owned)));
+            }
+        }
+        assert_eq!(
+            vec![
+                ("Main".to_owned(), 2..6, None),
+                ("a".to_owned(), 1..2, Some("a.md".to_owned())),
+                ("b ".to_owned(), 0..2, Some("b.md".to_owned())),
+                ("link".to_owned(), 3..7, Some("b.md".to_owned())),
+                ("end".to_owned(), 27..30, None),
+            ],
+            texts
+        );
+    }
+
+    #[test]
+    fn include_shares_definitions_and_heading_ids() {
+        let text = "# Intro\n\n{{#include a.md}}\n\n{{#include main.md}}\n\n[x]: /main\n";
+        let mut resolver = |path: &str| match path {
+            "a.md" => Some("# Intro\n\n[x] [y]\n\n[y]: /a\n".into()),
+            "main.md" => Some(text.to_owned().into()),
+            _ => None,
+        };
+        let mut s = String::new();
+        let parser =
+            Parser::new_ext(text, Options::ENABLE_HEADING_IDS).with_include_resolver(&mut resolver);
+        crate::html::push_html(&mut s, parser);
+        assert_eq!(
+            "<h1 id=\"intro\">Intro</h1>\n<h1 id=\"intro-1\">Intro</h1>\n\
+             <p><a href=\"/main\">x</a> <a href=\"/a\">y</a></p>\n\
+             <p>{{#include main.md}}</p>\n",
+            s
+        );
+    }
+
+    #[test]
+    fn include_uses_definitions() {
+        let text = "{{#include a.md}}\n\n[x]: /x\n[y]: /y\n[Z]: /z\n\n[z]\n";
+        let mut resolver = |path: &str| match path {
+            "a.md" => Some("[X] [y]\n\n[y]: /a\n".into()),
+            _ => None,
+        };
+        let mut parser =
+            Parser::new_ext(text, Options::ENABLE_DIAGNOSTICS).with_include_resolver(&mut resolver);
+        parser.by_ref().for_each(drop);
+        let unused = (parser.diagnostics().iter())
+            .filter(|diagnostic| diagnostic.code == DiagnosticCode::UnusedDefinition)
+            .map(|diagnostic| &text[diagnostic.range.clone()])
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["[y]: /y"], unused);
+    }
+
+    #[test]
+    fn toc_placeholder_limit() {
+        let text = "[TOC]\n\n# h\n\n".repeat(MAX_TOC_PLACEHOLDERS + 2);
+        let events = Parser::new_ext(&text, Options::ENABLE_TOC).collect::<Vec<_>>();
+        let lists = events
+            .iter()
+            .filter(|event| matches!(event, Event::Start(Tag::List(..))))
+            .count();
+        let placeholders = events
+            .iter()
+            .filter(|event| matches!(event, Event::Start(Tag::Paragraph(_))))
+            .count();
+        assert_eq!(MAX_TOC_PLACEHOLDERS, lists);
+        assert_eq!(2, placeholders);
+    }
+
+    #[test]
+    fn table_of_contents_mid_iteration() {
+        let text = "# One *a*\n\n> ## Two\n\n- ### Three\n\n# One a\n";
+        let mut parser = Parser::new_ext(text, Options::ENABLE_HEADING_IDS);
+        let expected = Parser::new_ext(text, Options::ENABLE_HEADING_IDS).collect::<Vec<_>>();
+        let mut events = parser.by_ref().take(3).collect::<Vec<_>>();
+
+        let toc = parser.table_of_contents();
+        assert_eq!(2, toc.len());
+        assert_eq!("One a", toc[0].text);
+        assert_eq!(Some("two"), toc[0].children[0].id.as_deref());
+        assert_eq!("Three", toc[0].children[0].children[0].text);
+        assert_eq!(Some("one-a-1"), toc[1].id.as_deref());
+        assert_eq!(text.len() - 8..text.len(), toc[1].range);
+
+        events.extend(parser);
+        assert_eq!(expected, events);
+    }
+
+    #[test]
+    fn link_def_title_followed_by_text() {
+        let text = "[foo]: /url\n\"title\" ok\n";
+        let events = Parser::new_ext(text, Options::ENABLE_LINK_DEFINITION_EVENTS)
+            .into_offset_iter()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            (
+                Event::LinkDefinition {
+                    label: "foo".into(),
+                    dest: "/url".into(),
+                    title: None,
+                },
+                0..11
+            ),
+            events[0]
+        );
+        assert_eq!(
+            None,
+            Parser::new(text)
+                .reference_definitions()
+                .get("foo")
+                .unwrap()
+                .title
+        );
+    }
+
     // FIXME: add this one regression suite
     #[test]
     fn link_def_at_eof() {
@@ -1889,4 +3554,27 @@ text
             Some(&mut function),
         ) {}
     }
+
+    #[test]
+    fn parser_pool() {
+        let texts = [
+            "# a {#x .y}\n\n[b]: /b\n\n[b] `c` <d@e.f> *g*\n",
+            "| h |\n|---|\n| i |\n\n```{=html}\n<j>\n```\n",
+            "[b] ``k`` [^l] ^m\n",
+        ];
+        let mut pool = ParserPool::new();
+        for _ in 0..2 {
+            for text in texts.iter() {
+                let mut parser = pool.parser(text, Options::all());
+                let events = parser.by_ref().collect::<Vec<_>>();
+                pool.recycle(parser);
+                assert_eq!(
+                    Parser::new_ext(text, Options::all()).collect::<Vec<_>>(),
+                    events
+                );
+            }
+        }
+        let tree = pool.tree.as_ref().unwrap().len();
+        assert_eq!(1, tree);
+    }
 }
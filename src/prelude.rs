@@ -0,0 +1,8 @@
+//! The parts of the standard prelude that live in `alloc`, so that the crate
+//! can use them the same way with and without `std`.
+
+pub(crate) use alloc::borrow::ToOwned;
+pub(crate) use alloc::boxed::Box;
+pub(crate) use alloc::string::{String, ToString};
+pub(crate) use alloc::vec::Vec;
+pub(crate) use alloc::{format, vec};
@@ -20,14 +20,15 @@
 
 //! Scanners for fragments of CommonMark syntax
 
-use std::convert::TryInto;
-use std::{char, convert::TryFrom};
+use core::convert::TryInto;
+use core::{char, convert::TryFrom};
 
 use crate::parse::HtmlScanGuard;
+use crate::prelude::*;
 pub(crate) use crate::puncttable::{is_ascii_punctuation, is_punctuation};
 use crate::strings::CowStr;
 use crate::{entities, HeadingLevel};
-use crate::{Alignment, LinkType};
+use crate::{Alignment, LinkType, ListNumbering};
 
 use memchr::memchr;
 
@@ -202,10 +203,17 @@ impl<'a> LineStart<'a> {
 
     /// Scan a list marker.
     ///
-    /// Return value is the character, the start index, and the indent in spaces.
-    /// For ordered list markers, the character will be one of b'.' or b')'. For
-    /// bullet list markers, it will be one of b'-', b'+', or b'*'.
-    pub(crate) fn scan_list_marker(&mut self) -> Option<(u8, u64, usize)> {
+    /// Return value is the character, the start index, the numbering style and the
+    /// indent in spaces. For ordered list markers, the character will be one of b'.'
+    /// or b')', or b'(' for fancy list numbers enclosed in parentheses. For bullet
+    /// list markers, it will be one of b'-', b'+', or b'*'.
+    ///
+    /// When `fancy` is set, Pandoc-style alphabetic and roman list numbers are
+    /// recognized as well.
+    pub(crate) fn scan_list_marker(
+        &mut self,
+        fancy: bool,
+    ) -> Option<(u8, u64, ListNumbering, usize)> {
         let save = self.clone();
         let indent = self.scan_space_upto(4);
         if indent < 4 && self.ix < self.bytes.len() {
@@ -222,7 +230,7 @@ impl<'a> LineStart<'a> {
                 }
                 self.ix += 1;
                 if self.scan_space(1) || self.is_at_eol() {
-                    return self.finish_list_marker(c, 0, indent + 2);
+                    return self.finish_list_marker(c, 0, ListNumbering::Decimal, indent + 2);
                 }
             } else if c >= b'0' && c <= b'9' {
                 let start_ix = self.ix;
@@ -236,7 +244,12 @@ impl<'a> LineStart<'a> {
                     } else if c == b')' || c == b'.' {
                         self.ix = ix;
                         if self.scan_space(1) || self.is_at_eol() {
-                            return self.finish_list_marker(c, val, indent + self.ix - start_ix);
+                            return self.finish_list_marker(
+                                c,
+                                val,
+                                ListNumbering::Decimal,
+                                indent + self.ix - start_ix,
+                            );
                         } else {
                             break;
                         }
@@ -244,6 +257,25 @@ impl<'a> LineStart<'a> {
                         break;
                     }
                 }
+            } else if fancy {
+                if let Some((n, c, val, numbering)) = scan_fancy_list_number(&self.bytes[self.ix..])
+                {
+                    let start_ix = self.ix;
+                    let is_initial = is_fancy_list_initial(n, c, numbering);
+                    self.ix += n;
+                    if is_initial && !scan_fancy_list_initial_space(&self.bytes[self.ix..]) {
+                        *self = save;
+                        return None;
+                    }
+                    if self.scan_space(1) || (!is_initial && self.is_at_eol()) {
+                        return self.finish_list_marker(
+                            c,
+                            val,
+                            numbering,
+                            indent + self.ix - start_ix,
+                        );
+                    }
+                }
             }
         }
         *self = save;
@@ -254,13 +286,14 @@ impl<'a> LineStart<'a> {
         &mut self,
         c: u8,
         start: u64,
+        numbering: ListNumbering,
         mut indent: usize,
-    ) -> Option<(u8, u64, usize)> {
+    ) -> Option<(u8, u64, ListNumbering, usize)> {
         let save = self.clone();
 
         // skip the rest of the line if it's blank
         if scan_blank_line(&self.bytes[self.ix..]).is_some() {
-            return Some((c, start, indent));
+            return Some((c, start, numbering, indent));
         }
 
         let post_indent = self.scan_space_upto(4);
@@ -269,7 +302,7 @@ impl<'a> LineStart<'a> {
         } else {
             *self = save;
         }
-        Some((c, start, indent))
+        Some((c, start, numbering, indent))
     }
 
     /// Returns Some(is_checked) when a task list marker was found. Resets itself
@@ -626,7 +659,7 @@ pub(crate) fn scan_empty_list(data: &[u8]) -> bool {
 }
 
 // return number of bytes scanned, delimiter, start index, and indent
-pub(crate) fn scan_listitem(bytes: &[u8]) -> Option<(usize, u8, usize, usize)> {
+pub(crate) fn scan_listitem(bytes: &[u8], fancy: bool) -> Option<(usize, u8, usize, usize)> {
     let mut c = *bytes.get(0)?;
     let (w, start) = match c {
         b'-' | b'+' | b'*' => (1, 0),
@@ -638,6 +671,16 @@ pub(crate) fn scan_listitem(bytes: &[u8]) -> Option<(usize, u8, usize, usize)> {
             }
             (length + 1, start)
         }
+        _ if fancy => {
+            let (length, delim, start, numbering) = scan_fancy_list_number(bytes)?;
+            if is_fancy_list_initial(length, delim, numbering)
+                && !scan_fancy_list_initial_space(&bytes[length..])
+            {
+                return None;
+            }
+            c = delim;
+            (length, start as usize)
+        }
         _ => {
             return None;
         }
@@ -658,6 +701,114 @@ pub(crate) fn scan_listitem(bytes: &[u8]) -> Option<(usize, u8, usize, usize)> {
     Some((w + postn, c, start, w + postindent))
 }
 
+/// Scans the number and delimiter of a Pandoc-style "fancy" ordered list marker,
+/// such as `a.`, `IV)` or `(3)`.
+///
+/// Returns the number of bytes scanned, the delimiter (b'.', b')', or b'(' when the
+/// number is enclosed in parentheses), the value of the number and its numbering style.
+pub(crate) fn scan_fancy_list_number(bytes: &[u8]) -> Option<(usize, u8, u64, ListNumbering)> {
+    let enclosed = bytes.first() == Some(&b'(');
+    let number_start = if enclosed { 1 } else { 0 };
+    let number_len = scan_while(&bytes[number_start..], |b| b.is_ascii_alphanumeric());
+    let (value, numbering) =
+        parse_fancy_list_number(&bytes[number_start..(number_start + number_len)])?;
+    let delim_ix = number_start + number_len;
+    match (enclosed, *bytes.get(delim_ix)?) {
+        (true, b')') => Some((delim_ix + 1, b'(', value, numbering)),
+        (false, c @ b'.') | (false, c @ b')') => Some((delim_ix + 1, c, value, numbering)),
+        _ => None,
+    }
+}
+
+/// Parses the number of a fancy list marker.
+///
+/// Single letters are alphabetic, except for `i` and `I` which start roman
+/// numbered lists. Decimal numbers have at most 9 digits, like in CommonMark.
+fn parse_fancy_list_number(number: &[u8]) -> Option<(u64, ListNumbering)> {
+    match *number {
+        [] => None,
+        [b'i'] => Some((1, ListNumbering::LowerRoman)),
+        [b'I'] => Some((1, ListNumbering::UpperRoman)),
+        [c @ b'a'..=b'z'] => Some((u64::from(c - b'a') + 1, ListNumbering::LowerAlpha)),
+        [c @ b'A'..=b'Z'] => Some((u64::from(c - b'A') + 1, ListNumbering::UpperAlpha)),
+        _ if number.len() <= 9 && number.iter().all(|&b| is_digit(b)) => {
+            let (_, value) = parse_decimal(number);
+            Some((value as u64, ListNumbering::Decimal))
+        }
+        _ if number.iter().all(|b| b.is_ascii_lowercase()) => {
+            Some((parse_roman(number)?, ListNumbering::LowerRoman))
+        }
+        _ if number.iter().all(|b| b.is_ascii_uppercase()) => {
+            Some((parse_roman(number)?, ListNumbering::UpperRoman))
+        }
+        _ => None,
+    }
+}
+
+/// Parses a roman numeral in its canonical form, such as `xiv` or `MMXXII`.
+fn parse_roman(number: &[u8]) -> Option<u64> {
+    const NUMERALS: [(u64, &str); 13] = [
+        (1000, "m"),
+        (900, "cm"),
+        (500, "d"),
+        (400, "cd"),
+        (100, "c"),
+        (90, "xc"),
+        (50, "l"),
+        (40, "xl"),
+        (10, "x"),
+        (9, "ix"),
+        (5, "v"),
+        (4, "iv"),
+        (1, "i"),
+    ];
+
+    let mut value = 0;
+    let mut rest = number;
+    for &(numeral_value, numeral) in &NUMERALS {
+        while rest.len() >= numeral.len()
+            && rest[..numeral.len()].eq_ignore_ascii_case(numeral.as_bytes())
+        {
+            rest = &rest[numeral.len()..];
+            value += numeral_value;
+        }
+    }
+    if !rest.is_empty() || value == 0 || value >= 4000 {
+        return None;
+    }
+
+    // Reject non-canonical spellings like `iiii` or `ixi` by writing
+    // the value back out and comparing.
+    let mut canonical = String::with_capacity(number.len());
+    let mut remainder = value;
+    for &(numeral_value, numeral) in &NUMERALS {
+        while remainder >= numeral_value {
+            canonical.push_str(numeral);
+            remainder -= numeral_value;
+        }
+    }
+    if canonical.as_bytes().eq_ignore_ascii_case(number) {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+/// Whether a fancy list marker looks like an initial, like the `B.` in `B. Russell`.
+/// Those markers must be followed by at least two spaces to start a list item.
+fn is_fancy_list_initial(len: usize, delim: u8, numbering: ListNumbering) -> bool {
+    len == 2
+        && delim == b'.'
+        && matches!(
+            numbering,
+            ListNumbering::UpperAlpha | ListNumbering::UpperRoman
+        )
+}
+
+fn scan_fancy_list_initial_space(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"  ") || bytes.starts_with(b"\t")
+}
+
 // returns (number of bytes, parsed decimal)
 fn parse_decimal(bytes: &[u8]) -> (usize, usize) {
     match bytes
@@ -1013,7 +1164,7 @@ pub(crate) fn is_html_tag(tag: &[u8]) -> bool {
                     // We can compare case insensitively because the probes are
                     // all lower case alpha strings.
                     match a.cmp(&(b | 0x20)) {
-                        std::cmp::Ordering::Equal => None,
+                        core::cmp::Ordering::Equal => None,
                         inequality => Some(inequality),
                     }
                 })
@@ -1113,6 +1264,89 @@ pub(crate) fn scan_html_block_inner(
     }
 }
 
+/// Returns whether `data` starts with the `|` of a line of a line block, which
+/// must be followed by a space or the end of the line.
+pub(crate) fn scan_line_block_marker(data: &[u8]) -> bool {
+    data.first() == Some(&b'|') && matches!(data.get(1), None | Some(b' ' | b'\n' | b'\r'))
+}
+
+/// Scans a raw attribute of the form `{=format}` at the start of `text`.
+/// Returns its length and the format.
+pub(crate) fn scan_raw_attribute(text: &str) -> Option<(usize, &str)> {
+    let rest = text.strip_prefix("{=")?;
+    let format_len = rest
+        .bytes()
+        .take_while(|&b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        .count();
+    if format_len == 0 || rest.as_bytes().get(format_len) != Some(&b'}') {
+        return None;
+    }
+    Some((format_len + 3, &rest[..format_len]))
+}
+
+/// Returns whether a `#` following `byte` can start a hashtag. Tags start a
+/// word, but may directly follow an opening bracket or an emphasis delimiter.
+pub(crate) fn is_hashtag_boundary(byte: u8) -> bool {
+    is_ascii_whitespace(byte) || matches!(byte, b'(' | b'[' | b'*' | b'_' | b'~' | b'"' | b'\'')
+}
+
+/// Scans the name of a hashtag following a `#`. Names are made up of
+/// alphanumeric characters, `_`, `-` and `/` separating nested tags, and must
+/// not be all digits. Returns the length of the name, or 0 if there is none.
+pub(crate) fn scan_hashtag(text: &str) -> usize {
+    let len = text
+        .char_indices()
+        .find(|&(_, c)| !(c.is_alphanumeric() || matches!(c, '_' | '-' | '/')))
+        .map_or(text.len(), |(i, _)| i);
+    let name = text[..len].trim_end_matches('/');
+    if name.starts_with('/') || name.bytes().all(|b| b.is_ascii_digit()) {
+        0
+    } else {
+        name.len()
+    }
+}
+
+/// Scans a block id marker like ` ^abc-123` at the end of `text`. The id is
+/// made up of ASCII alphanumeric characters and `-`, and must be separated from
+/// preceding text by spaces or tabs. Returns the start of that whitespace.
+pub(crate) fn scan_block_id(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let id_len = bytes
+        .iter()
+        .rev()
+        .take_while(|&&b| b.is_ascii_alphanumeric() || b == b'-')
+        .count();
+    let caret = bytes.len().checked_sub(id_len + 1)?;
+    if id_len == 0 || bytes[caret] != b'^' {
+        return None;
+    }
+    let space_len = bytes[..caret]
+        .iter()
+        .rev()
+        .take_while(|&&b| is_ascii_whitespace_no_nl(b))
+        .count();
+    if space_len == 0 {
+        None
+    } else {
+        Some(caret - space_len)
+    }
+}
+
+/// Returns the path of an include directive of the form `{{#include path}}`,
+/// which must make up all of `text` apart from surrounding whitespace.
+pub(crate) fn scan_include_directive(text: &str) -> Option<&str> {
+    let path = text.trim().strip_prefix("{{#include")?.strip_suffix("}}")?;
+    if !path.starts_with([' ', '\t']) {
+        return None;
+    }
+    let path = path.trim();
+    if path.is_empty() || path.contains('\n') {
+        None
+    } else {
+        Some(path)
+    }
+}
+
 /// Returns (next_byte_offset, uri, type)
 pub(crate) fn scan_autolink(text: &str, start_ix: usize) -> Option<(usize, CowStr<'_>, LinkType)> {
     scan_uri(text, start_ix)
@@ -1304,13 +1538,99 @@ mod test {
     use super::*;
     #[test]
     fn overflow_list() {
-        assert!(
-            scan_listitem(b"4444444444444444444444444444444444444444444444444444444444!").is_none()
-        );
+        assert!(scan_listitem(
+            b"4444444444444444444444444444444444444444444444444444444444!",
+            false
+        )
+        .is_none());
     }
 
     #[test]
     fn overflow_by_addition() {
-        assert!(scan_listitem(b"1844674407370955161615!").is_none());
+        assert!(scan_listitem(b"1844674407370955161615!", false).is_none());
+    }
+
+    #[test]
+    fn roman_numerals() {
+        assert_eq!(Some(4), parse_roman(b"iv"));
+        assert_eq!(Some(14), parse_roman(b"XIV"));
+        assert_eq!(Some(1999), parse_roman(b"mcmxcix"));
+        assert_eq!(Some(3999), parse_roman(b"MMMCMXCIX"));
+        assert_eq!(None, parse_roman(b"iiii"));
+        assert_eq!(None, parse_roman(b"ixi"));
+        assert_eq!(None, parse_roman(b"vv"));
+        assert_eq!(None, parse_roman(b"mmmm"));
+        assert_eq!(None, parse_roman(b"abc"));
+    }
+
+    #[test]
+    fn fancy_list_numbers() {
+        assert_eq!(
+            Some((2, b'.', 1, ListNumbering::LowerAlpha)),
+            scan_fancy_list_number(b"a. foo")
+        );
+        assert_eq!(
+            Some((4, b'(', 12, ListNumbering::Decimal)),
+            scan_fancy_list_number(b"(12) foo")
+        );
+        assert_eq!(
+            Some((3, b')', 4, ListNumbering::UpperRoman)),
+            scan_fancy_list_number(b"IV) foo")
+        );
+        assert_eq!(None, scan_fancy_list_number(b"(iv. foo"));
+        assert_eq!(None, scan_fancy_list_number(b"ab. foo"));
+        assert!(scan_listitem(b"B. Russell", true).is_none());
+        assert!(scan_listitem(b"B.  Russell", true).is_some());
+        assert!(scan_listitem(b"b. Russell", false).is_none());
+    }
+
+    #[test]
+    fn raw_attributes() {
+        assert_eq!(Some((7, "html")), scan_raw_attribute("{=html}"));
+        assert_eq!(
+            Some((13, "open_xml-2")),
+            scan_raw_attribute("{=open_xml-2} text")
+        );
+        assert_eq!(None, scan_raw_attribute("{= html}"));
+        assert_eq!(None, scan_raw_attribute("{=}"));
+        assert_eq!(None, scan_raw_attribute("{=a|b}"));
+        assert_eq!(None, scan_raw_attribute("{=html"));
+        assert_eq!(None, scan_raw_attribute("{html}"));
+    }
+
+    #[test]
+    fn hashtags() {
+        assert_eq!(scan_hashtag("tag rest"), 3);
+        assert_eq!(scan_hashtag("nested/tag."), 10);
+        assert_eq!(scan_hashtag("trailing/ slash"), 8);
+        assert_eq!(scan_hashtag("été!"), "été".len());
+        assert_eq!(scan_hashtag("2024-review"), 11);
+        assert_eq!(scan_hashtag("123"), 0);
+        assert_eq!(scan_hashtag("/root"), 0);
+        assert_eq!(scan_hashtag(" space"), 0);
+    }
+
+    #[test]
+    fn block_ids() {
+        assert_eq!(scan_block_id("text ^abc-123"), Some(4));
+        assert_eq!(scan_block_id("text \t ^id"), Some(4));
+        assert_eq!(scan_block_id(" ^id"), Some(0));
+        assert_eq!(scan_block_id("text^id"), None);
+        assert_eq!(scan_block_id("text ^"), None);
+        assert_eq!(scan_block_id("text ^a_b"), None);
+        assert_eq!(scan_block_id("text ^id."), None);
+    }
+
+    #[test]
+    fn include_directives() {
+        assert_eq!(Some("a.md"), scan_include_directive("{{#include a.md}}"));
+        assert_eq!(
+            Some("dir/a b.md"),
+            scan_include_directive(" {{#include  dir/a b.md }}\n")
+        );
+        assert_eq!(None, scan_include_directive("{{#includea.md}}"));
+        assert_eq!(None, scan_include_directive("{{#include }}"));
+        assert_eq!(None, scan_include_directive("{{#include a.md}} text"));
+        assert_eq!(None, scan_include_directive("{{#include a.md\nb.md}}"));
     }
 }
@@ -0,0 +1,32 @@
+//! Runtime selection of the vector instructions used to scan text.
+//!
+//! The scanners for special bytes in the first pass and for HTML escaping
+//! have implementations for 128-bit SSSE3 and 256-bit AVX2 vectors. The widest
+//! one the processor supports is picked when they are called.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+static MAX_WIDTH: AtomicUsize = AtomicUsize::new(256);
+
+/// Returns the width in bits of the widest vectors the scanners can use on
+/// this processor, or 0 if they can't use any.
+pub(crate) fn width() -> usize {
+    let max_width = MAX_WIDTH.load(Ordering::Relaxed);
+    if max_width >= 256 && is_x86_feature_detected!("avx2") {
+        256
+    } else if max_width >= 128 && is_x86_feature_detected!("ssse3") {
+        128
+    } else {
+        0
+    }
+}
+
+/// Limits the width in bits of the vectors used to scan text, for all parsers
+/// of the process. Only available to the benchmarks comparing the
+/// implementations, through the `bench-simd-width` feature: 0 disables the
+/// vector implementations, 128 disables the AVX2 ones, and the default is 256.
+#[cfg(feature = "bench-simd-width")]
+#[doc(hidden)]
+pub fn set_max_simd_width(max_width: usize) {
+    MAX_WIDTH.store(max_width, Ordering::Relaxed);
+}
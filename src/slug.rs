@@ -0,0 +1,129 @@
+//! Generation of heading identifiers.
+
+use alloc::collections::BTreeMap;
+
+use crate::prelude::*;
+
+/// Turns the plain text content of a heading into a fragment identifier.
+///
+/// Used by the parser when [`Options::ENABLE_HEADING_IDS`](crate::Options::ENABLE_HEADING_IDS)
+/// is set. The result does not need to be unique: the parser appends `-1`, `-2`, etc.
+/// to identifiers that are already in use.
+///
+/// This trait is implemented for closures of type `FnMut(&str) -> String`.
+pub trait Slugifier {
+    fn slugify(&mut self, text: &str) -> String;
+}
+
+impl<F> Slugifier for F
+where
+    F: FnMut(&str) -> String,
+{
+    fn slugify(&mut self, text: &str) -> String {
+        self(text)
+    }
+}
+
+/// The slugifier used by GitHub to generate heading anchors, and the default.
+///
+/// The text is lowercased, spaces are replaced by `-`, and all other characters
+/// except letters, digits, `-` and `_` are dropped.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GitHubSlugifier;
+
+impl Slugifier for GitHubSlugifier {
+    fn slugify(&mut self, text: &str) -> String {
+        text.chars()
+            .filter_map(|c| match c {
+                ' ' => Some('-'),
+                '-' | '_' => Some(c),
+                _ if c.is_alphanumeric() => Some(c),
+                _ => None,
+            })
+            .flat_map(char::to_lowercase)
+            .collect()
+    }
+}
+
+/// Keeps track of the heading identifiers used in a document, and generates
+/// unique new ones.
+#[derive(Default)]
+pub(crate) struct HeadingIds<'callback> {
+    /// Maps each identifier in use to the number of times it was
+    /// requested again.
+    used: BTreeMap<String, usize>,
+    slugifier: Option<&'callback mut dyn Slugifier>,
+}
+
+impl<'callback> HeadingIds<'callback> {
+    pub(crate) fn set_slugifier(&mut self, slugifier: &'callback mut dyn Slugifier) {
+        self.slugifier = Some(slugifier);
+    }
+
+    /// Returns the slugifier set with `set_slugifier`.
+    pub(crate) fn slugifier(&mut self) -> Option<&mut dyn Slugifier> {
+        match &mut self.slugifier {
+            Some(slugifier) => Some(&mut **slugifier),
+            None => None,
+        }
+    }
+
+    /// Moves the identifiers in use by `other` to these.
+    pub(crate) fn extend(&mut self, other: &mut HeadingIds<'_>) {
+        for (id, count) in core::mem::take(&mut other.used) {
+            let used = self.used.entry(id).or_insert(0);
+            *used = core::cmp::max(*used, count);
+        }
+    }
+
+    /// Marks an identifier given explicitly by the author as in use.
+    pub(crate) fn reserve(&mut self, id: &str) {
+        if !self.used.contains_key(id) {
+            self.used.insert(id.to_owned(), 0);
+        }
+    }
+
+    /// Returns a new unique identifier for a heading with the given text.
+    pub(crate) fn generate(&mut self, text: &str) -> String {
+        let slug = match self.slugifier.as_mut() {
+            Some(slugifier) => slugifier.slugify(text),
+            None => GitHubSlugifier.slugify(text),
+        };
+        let mut id = slug.clone();
+        while self.used.contains_key(&id) {
+            // `slug` is in use, since it was the first `id` tried
+            let count = self.used.get_mut(&slug).unwrap();
+            *count += 1;
+            id = format!("{}-{}", slug, count);
+        }
+        self.used.insert(id.clone(), 0);
+        id
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn github_slugs() {
+        let mut slugifier = GitHubSlugifier;
+        assert_eq!("hello-world", slugifier.slugify("Hello World"));
+        assert_eq!("whats-new-in-v10", slugifier.slugify("What's new in v1.0?"));
+        assert_eq!(
+            "snake_case--kebab-case",
+            slugifier.slugify("snake_case & kebab-case")
+        );
+        assert_eq!("über-straße", slugifier.slugify("Über Straße"));
+    }
+
+    #[test]
+    fn unique_ids() {
+        let mut ids = HeadingIds::default();
+        ids.reserve("foo-1");
+        assert_eq!("foo", ids.generate("Foo"));
+        assert_eq!("foo-2", ids.generate("Foo"));
+        assert_eq!("foo-3", ids.generate("foo"));
+        assert_eq!("foo-1-1", ids.generate("Foo 1"));
+    }
+}
@@ -0,0 +1,295 @@
+//! Streaming parsing.
+//!
+//! A [`Parser`] needs the whole document in memory, and builds the block
+//! structure of all of it before returning the first event. A
+//! [`StreamParser`] reads the document from a [`BufRead`] in chunks instead,
+//! and returns the events of each top-level block as soon as the block is
+//! closed. It only keeps the blocks that are still open in memory, so
+//! arbitrarily large documents can be converted with memory proportional to
+//! the largest top-level block.
+//!
+//! The events don't borrow from the input, so all their strings are owned.
+//! Since a reference definition isn't known before it is read, links that
+//! refer to a definition further down in the document may not be resolved. A
+//! table of contents can't be generated either, so [`Options::ENABLE_TOC`] only
+//! enables heading identifiers, and [`Options::ENABLE_DIAGNOSTICS`] is ignored.
+//!
+//! # Example
+//! ```rust
+//! use pulldown_cmark::stream::StreamParser;
+//! use pulldown_cmark::html;
+//!
+//! # fn main() -> std::io::Result<()> {
+//! let markdown_input: &[u8] = b"[url]: /url\n\n# Log\n\n- a [link][url]\n- *b*\n";
+//! let mut result = Ok(());
+//! let events = StreamParser::new(markdown_input)
+//!     .with_chunk_size(16)
+//!     .filter_map(|event| event.map_err(|error| result = Err(error)).ok());
+//!
+//! let mut html_output = Vec::new();
+//! html::write_html(&mut html_output, events)?;
+//! result?;
+//!
+//! let expected_html = "<h1>Log</h1>\n<ul>\n<li>a <a href=\"/url\">link</a></li>\n<li><em>b</em></li>\n</ul>\n";
+//! assert_eq!(expected_html.as_bytes(), &html_output[..]);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`Parser`]: crate::Parser
+
+use std::cmp::max;
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+
+use crate::firstpass::run_first_pass_from;
+use crate::parse::Allocations;
+use crate::slug::HeadingIds;
+use crate::tree::{Tree, TreeIndex};
+use crate::{Event, Options, Parser, RefDefs};
+
+/// The default number of bytes read at a time.
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Markdown event iterator over a reader.
+///
+/// Returns an error when reading fails or the input isn't valid UTF-8, after
+/// which it returns no more events. Use [`BufReader`](std::io::BufReader) to
+/// parse from a reader that doesn't implement [`BufRead`].
+pub struct StreamParser<R> {
+    reader: R,
+    options: Options,
+    chunk_size: usize,
+    // complete lines read, from the start of the first block that isn't closed
+    buffer: String,
+    // whether the reader is exhausted
+    done: bool,
+    events: VecDeque<Event<'static>>,
+    refdefs: RefDefs<'static>,
+    heading_ids: HeadingIds<'static>,
+}
+
+impl<R: BufRead> StreamParser<R> {
+    /// Creates a new event iterator over a reader without any options enabled.
+    pub fn new(reader: R) -> Self {
+        StreamParser::new_ext(reader, Options::empty())
+    }
+
+    /// Creates a new event iterator over a reader with given options.
+    pub fn new_ext(reader: R, mut options: Options) -> Self {
+        options.remove(Options::ENABLE_DIAGNOSTICS);
+        if options.contains(Options::ENABLE_TOC) {
+            options.remove(Options::ENABLE_TOC);
+            options.insert(Options::ENABLE_HEADING_IDS);
+        }
+        StreamParser {
+            reader,
+            options,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            buffer: String::new(),
+            done: false,
+            events: VecDeque::new(),
+            refdefs: RefDefs::default(),
+            heading_ids: HeadingIds::default(),
+        }
+    }
+
+    /// Sets the number of bytes to read before parsing what has been read so
+    /// far. More is read when a block is longer than that. The default is 64 KiB.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = max(1, chunk_size);
+        self
+    }
+
+    /// Reads complete lines until at least `chunk_size` bytes or as many as
+    /// are buffered already have been read, so that a long block is parsed a
+    /// number of times that is only logarithmic in its length.
+    fn read_chunk(&mut self) -> io::Result<()> {
+        let target = self.buffer.len() + max(self.chunk_size, self.buffer.len());
+        while self.buffer.len() < target {
+            if self.reader.read_line(&mut self.buffer)? == 0 {
+                self.done = true;
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses the buffer, returns the events of the blocks that are closed, and
+    /// removes them from the buffer.
+    fn parse_chunk(&mut self) {
+        let end = self.parse_closed_blocks();
+        self.buffer.drain(..end);
+    }
+
+    /// Returns the events of the blocks of the buffer that are closed, and the
+    /// offset after them.
+    fn parse_closed_blocks(&mut self) -> usize {
+        let text = self.buffer.as_str();
+        let mut allocs = Allocations::new();
+        allocs.refdefs = self.refdefs.clone();
+        let tree = Tree::with_capacity(max(128, text.len() / 32));
+        let (mut tree, mut allocs, reparse) =
+            run_first_pass_from(text, self.options, tree, allocs, 0, |_| false);
+
+        // Before the start of the last line at which no container block is
+        // open, all blocks are closed and won't change when more is read.
+        let mut end = match reparse.boundaries.last() {
+            _ if self.done => text.len(),
+            Some(&end) => end,
+            None => return 0,
+        };
+        let new_definitions = (allocs.refdefs.0.iter())
+            .filter(|(label, _)| !self.refdefs.0.contains_key(*label))
+            .map(|(label, def)| (label.clone(), def.span.clone(), def.title.is_some()))
+            .collect::<Vec<_>>();
+        if !self.done {
+            // A definition without a title on the line before `end` can still
+            // get a title that starts at `end`, so it isn't closed either.
+            for (_, span, has_title) in &new_definitions {
+                if *has_title || span.end > end {
+                    continue;
+                }
+                let rest = text[span.end..end].trim_start_matches(&[' ', '\t'][..]);
+                if matches!(rest, "\n" | "\r\n" | "\r") {
+                    end = (reparse.boundaries.iter())
+                        .rev()
+                        .find(|&&boundary| boundary <= span.start)
+                        .map_or(0, |&boundary| boundary);
+                }
+            }
+        }
+        // Links are only resolved with the definitions of closed blocks, which
+        // are the only ones that are kept.
+        for (label, span, _) in &new_definitions {
+            if span.start >= end {
+                allocs.refdefs.0.remove(label);
+            }
+        }
+        let mut last = None;
+        let mut next = if tree.len() > 1 {
+            Some(TreeIndex::new(1))
+        } else {
+            None
+        };
+        while let Some(ix) = next.filter(|&ix| tree[ix].item.start < end) {
+            last = Some(ix);
+            next = tree[ix].next;
+        }
+        self.refdefs = allocs.refdefs.clone().into_owned();
+        if let Some(last) = last {
+            tree[last].next = None;
+            let mut parser = Parser::from_first_pass(text, self.options, tree, allocs, None);
+            parser.continue_heading_ids(&mut self.heading_ids);
+            self.events.extend((&mut parser).map(Event::into_static));
+            parser.save_heading_ids(&mut self.heading_ids);
+        }
+        end
+    }
+}
+
+impl<R: BufRead> Iterator for StreamParser<R> {
+    type Item = io::Result<Event<'static>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Some(Ok(event));
+            }
+            if self.done && self.buffer.is_empty() {
+                return None;
+            }
+            if let Err(error) = self.read_chunk() {
+                self.done = true;
+                self.buffer.clear();
+                return Some(Err(error));
+            }
+            self.parse_chunk();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn stream_events(text: &str, options: Options, chunk_size: usize) -> Vec<Event<'static>> {
+        StreamParser::new_ext(text.as_bytes(), options)
+            .with_chunk_size(chunk_size)
+            .collect::<io::Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn same_as_parser() {
+        let texts = [
+            "",
+            "\n\n",
+            "# a\n\nb\nc\n===\n\n- d\n\n  e\n- f\n\n> g\nlazy\n\n    code\n\n```\nfenced\n\n```\n",
+            "| a | b |\n|---|---|\n| 1 | 2 |\n\n[^1]: note\n\n  more\n\ntext[^1]\n",
+            "<div>\n\nhtml\n</div>\n\n1. x\n2. y\n\n3) z\r\n\r\n***\nlast",
+            "[a]: /a\n\n[b]: /b 'title'\n[a] [b]\n\n[A]: /c\n[a]\n",
+        ];
+        let options = Options::all() - Options::ENABLE_TOC;
+        for text in texts.iter() {
+            let expected = Parser::new_ext(text, options)
+                .map(Event::into_static)
+                .collect::<Vec<_>>();
+            for &chunk_size in [1, 7, 1000].iter() {
+                assert_eq!(
+                    expected,
+                    stream_events(text, options, chunk_size),
+                    "{:?}",
+                    text
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn definitions_across_chunks() {
+        // the definitions only get their titles from lines that are read later
+        let texts = [
+            "[a]: /a\n'title\ncontinues'\n\n[a]\n",
+            "> [b]: /b\n> 'title\n> continues'\n\n[b]\n",
+        ];
+        let options = Options::ENABLE_LINK_DEFINITION_EVENTS;
+        for text in texts.iter() {
+            let expected = Parser::new_ext(text, options)
+                .map(Event::into_static)
+                .collect::<Vec<_>>();
+            for chunk_size in 1..text.len() {
+                assert_eq!(
+                    expected,
+                    stream_events(text, options, chunk_size),
+                    "{:?} {}",
+                    text,
+                    chunk_size
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn heading_ids_across_chunks() {
+        let ids = stream_events("# a\n\n# a\n\n# a-1\n", Options::ENABLE_HEADING_IDS, 1)
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::Start(crate::Tag::Heading(_, id, _)) => id.map(|id| id.into_string()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["a", "a-1", "a-1-1"], ids);
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let mut stream = StreamParser::new(&b"a\n\nb\n\nc\xff\n"[..]).with_chunk_size(1);
+        assert_eq!(
+            Some(Event::Start(crate::Tag::Paragraph(None))),
+            stream.next().map(Result::unwrap)
+        );
+        assert!(stream.by_ref().any(|event| event.is_err()));
+        assert!(stream.next().is_none());
+    }
+}
@@ -1,11 +1,15 @@
-use std::borrow::{Borrow, Cow, ToOwned};
-use std::convert::{AsRef, TryFrom};
-use std::fmt;
-use std::hash::{Hash, Hasher};
-use std::ops::Deref;
-use std::str::from_utf8;
+use alloc::borrow::Cow;
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+use core::convert::{AsRef, TryFrom};
+use core::fmt;
+use core::hash::{Hash, Hasher};
+use core::ops::Deref;
+use core::str::from_utf8;
 
-const MAX_INLINE_STR_LEN: usize = 3 * std::mem::size_of::<isize>() - 1;
+use crate::prelude::*;
+
+const MAX_INLINE_STR_LEN: usize = 3 * core::mem::size_of::<isize>() - 1;
 
 /// Returned when trying to convert a `&str` into a `InlineStr`
 /// but it fails because it doesn't fit.
@@ -40,7 +44,7 @@ impl From<char> for InlineStr {
     }
 }
 
-impl<'a> std::cmp::PartialEq<InlineStr> for InlineStr {
+impl<'a> core::cmp::PartialEq<InlineStr> for InlineStr {
     fn eq(&self, other: &InlineStr) -> bool {
         self.deref() == other.deref()
     }
@@ -94,8 +98,8 @@ pub enum CowStr<'a> {
 #[cfg(feature = "serde")]
 mod serde_impl {
     use super::CowStr;
+    use core::fmt;
     use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
-    use std::fmt;
 
     impl<'a> Serialize for CowStr<'a> {
         fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -145,7 +149,7 @@ impl<'a> Hash for CowStr<'a> {
     }
 }
 
-impl<'a> std::clone::Clone for CowStr<'a> {
+impl<'a> core::clone::Clone for CowStr<'a> {
     fn clone(&self) -> Self {
         match self {
             CowStr::Boxed(s) => match InlineStr::try_from(&**s) {
@@ -158,12 +162,24 @@ impl<'a> std::clone::Clone for CowStr<'a> {
     }
 }
 
-impl<'a> std::cmp::PartialEq<CowStr<'a>> for CowStr<'a> {
+impl<'a> core::cmp::PartialEq<CowStr<'a>> for CowStr<'a> {
     fn eq(&self, other: &CowStr) -> bool {
         self.deref() == other.deref()
     }
 }
 
+impl<'a> PartialOrd for CowStr<'a> {
+    fn partial_cmp(&self, other: &CowStr) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> Ord for CowStr<'a> {
+    fn cmp(&self, other: &CowStr) -> Ordering {
+        self.deref().cmp(other.deref())
+    }
+}
+
 impl<'a> From<&'a str> for CowStr<'a> {
     fn from(s: &'a str) -> Self {
         CowStr::Borrowed(s)
@@ -233,6 +249,19 @@ impl<'a> CowStr<'a> {
             CowStr::Inlined(s) => s.deref().to_owned(),
         }
     }
+
+    /// Converts a borrowed string into an inlined or owned one, so that it no
+    /// longer borrows from the input.
+    pub fn into_static(self) -> CowStr<'static> {
+        match self {
+            CowStr::Boxed(b) => CowStr::Boxed(b),
+            CowStr::Borrowed(b) => match InlineStr::try_from(b) {
+                Ok(inline) => CowStr::Inlined(inline),
+                Err(_) => CowStr::Boxed(b.into()),
+            },
+            CowStr::Inlined(s) => CowStr::Inlined(s),
+        }
+    }
 }
 
 impl<'a> fmt::Display for CowStr<'a> {
@@ -259,8 +288,8 @@ mod test_special_string {
 
     #[test]
     fn cowstr_size() {
-        let size = std::mem::size_of::<CowStr>();
-        let word_size = std::mem::size_of::<isize>();
+        let size = core::mem::size_of::<CowStr>();
+        let word_size = core::mem::size_of::<isize>();
         assert_eq!(3 * word_size, size);
     }
 
@@ -368,6 +397,6 @@ mod test_special_string {
     }
 
     fn variant_eq<T>(a: &T, b: &T) -> bool {
-        std::mem::discriminant(a) == std::mem::discriminant(b)
+        core::mem::discriminant(a) == core::mem::discriminant(b)
     }
 }
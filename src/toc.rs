@@ -0,0 +1,110 @@
+//! Table of contents extraction.
+//!
+//! The outline of a document is returned by [`Parser::table_of_contents`]. It can also
+//! be inserted into the document in place of a `[TOC]` paragraph by setting
+//! [`Options::ENABLE_TOC`].
+//!
+//! [`Parser::table_of_contents`]: crate::Parser::table_of_contents
+//! [`Options::ENABLE_TOC`]: crate::Options::ENABLE_TOC
+//!
+//! # Example
+//! ```rust
+//! use pulldown_cmark::{Options, Parser};
+//!
+//! let markdown_input = "# Intro\n\n## Usage\n\n# License\n";
+//! let mut parser = Parser::new_ext(markdown_input, Options::ENABLE_HEADING_IDS);
+//! let toc = parser.table_of_contents();
+//!
+//! assert_eq!(2, toc.len());
+//! assert_eq!("Intro", toc[0].text);
+//! assert_eq!(Some("usage"), toc[0].children[0].id.as_deref());
+//! assert_eq!(0..8, toc[0].range);
+//! ```
+
+use core::ops::Range;
+
+use crate::prelude::*;
+use crate::{CowStr, HeadingLevel};
+
+/// A heading in the outline of a document.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TocEntry<'a> {
+    /// The level of the heading.
+    pub level: HeadingLevel,
+    /// The text content of the heading, without any markup.
+    pub text: String,
+    /// The identifier of the heading. Headings only have one if it is given
+    /// explicitly, or if [`Options::ENABLE_HEADING_IDS`](crate::Options::ENABLE_HEADING_IDS)
+    /// is set.
+    pub id: Option<CowStr<'a>>,
+    /// The byte range of the heading in the source.
+    pub range: Range<usize>,
+    /// The headings in the section introduced by this one.
+    pub children: Vec<TocEntry<'a>>,
+}
+
+/// Turns a list of headings in document order into an outline. Each heading
+/// becomes a child of the closest preceding heading of a lower level.
+pub(crate) fn nest(headings: Vec<TocEntry<'_>>) -> Vec<TocEntry<'_>> {
+    let mut roots = Vec::new();
+    let mut open: Vec<TocEntry> = Vec::new();
+    for heading in headings {
+        while let Some(last) = open.last() {
+            if last.level < heading.level {
+                break;
+            }
+            close_last(&mut open, &mut roots);
+        }
+        open.push(heading);
+    }
+    while !open.is_empty() {
+        close_last(&mut open, &mut roots);
+    }
+    roots
+}
+
+fn close_last<'a>(open: &mut Vec<TocEntry<'a>>, roots: &mut Vec<TocEntry<'a>>) {
+    let entry = open.pop().unwrap();
+    match open.last_mut() {
+        Some(parent) => parent.children.push(entry),
+        None => roots.push(entry),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(level: HeadingLevel, text: &str) -> TocEntry<'static> {
+        TocEntry {
+            level,
+            text: text.to_owned(),
+            id: None,
+            range: 0..0,
+            children: Vec::new(),
+        }
+    }
+
+    fn outline(entries: &[TocEntry]) -> String {
+        entries
+            .iter()
+            .map(|entry| format!("{}({})", entry.text, outline(&entry.children)))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[test]
+    fn nesting() {
+        use HeadingLevel::*;
+        let headings = vec![
+            entry(H2, "a"),
+            entry(H1, "b"),
+            entry(H3, "c"),
+            entry(H2, "d"),
+            entry(H4, "e"),
+            entry(H3, "f"),
+            entry(H1, "g"),
+        ];
+        assert_eq!("a() b(c() d(e() f())) g()", outline(&nest(headings)));
+    }
+}
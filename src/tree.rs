@@ -6,16 +6,17 @@
 
 //! A Vec-based container for a tree structure.
 
-use std::num::NonZeroUsize;
-use std::ops::{Add, Sub};
+use core::num::NonZeroUsize;
+use core::ops::{Add, Sub};
 
 use crate::parse::{Item, ItemBody};
+use crate::prelude::*;
 
-#[derive(Debug, Eq, PartialEq, Copy, Clone, PartialOrd)]
+#[derive(Debug, Eq, PartialEq, Copy, Clone, PartialOrd, Ord)]
 pub(crate) struct TreeIndex(NonZeroUsize);
 
 impl TreeIndex {
-    fn new(i: usize) -> Self {
+    pub(crate) fn new(i: usize) -> Self {
         TreeIndex(NonZeroUsize::new(i).unwrap())
     }
 
@@ -141,6 +142,11 @@ impl<T: Default> Tree<T> {
         self.nodes.len() <= 1
     }
 
+    /// Returns the number of nodes, including the dummy node at index zero.
+    pub(crate) fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
     /// Returns the length of the spine.
     pub(crate) fn spine_len(&self) -> usize {
         self.spine.len()
@@ -156,8 +162,27 @@ impl<T: Default> Tree<T> {
         self.spine.clear();
     }
 
+    /// Removes all nodes, keeping the allocated memory for reuse.
+    pub(crate) fn clear(&mut self) {
+        self.nodes.truncate(1);
+        self.spine.clear();
+        self.cur = None;
+    }
+
+    /// Returns the current position in the tree, so that it can be restored with
+    /// `set_position` after walking other parts of it.
+    pub(crate) fn position(&self) -> (Vec<TreeIndex>, Option<TreeIndex>) {
+        (self.spine.clone(), self.cur)
+    }
+
+    /// Moves focus back to a position returned by `position`.
+    pub(crate) fn set_position(&mut self, (spine, cur): (Vec<TreeIndex>, Option<TreeIndex>)) {
+        self.spine = spine;
+        self.cur = cur;
+    }
+
     /// Walks the spine from a root node up to, but not including, the current node.
-    pub(crate) fn walk_spine(&self) -> impl std::iter::DoubleEndedIterator<Item = &TreeIndex> {
+    pub(crate) fn walk_spine(&self) -> impl core::iter::DoubleEndedIterator<Item = &TreeIndex> {
         self.spine.iter()
     }
 
@@ -241,19 +266,19 @@ impl Tree<Item> {
     }
 }
 
-impl<T> std::fmt::Debug for Tree<T>
+impl<T> core::fmt::Debug for Tree<T>
 where
-    T: std::fmt::Debug,
+    T: core::fmt::Debug,
 {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         fn debug_tree<T>(
             tree: &Tree<T>,
             cur: TreeIndex,
             indent: usize,
-            f: &mut std::fmt::Formatter,
-        ) -> std::fmt::Result
+            f: &mut core::fmt::Formatter,
+        ) -> core::fmt::Result
         where
-            T: std::fmt::Debug,
+            T: core::fmt::Debug,
         {
             for _ in 0..indent {
                 write!(f, "  ")?;
@@ -277,7 +302,7 @@ where
     }
 }
 
-impl<T> std::ops::Index<TreeIndex> for Tree<T> {
+impl<T> core::ops::Index<TreeIndex> for Tree<T> {
     type Output = Node<T>;
 
     fn index(&self, ix: TreeIndex) -> &Self::Output {
@@ -285,7 +310,7 @@ impl<T> std::ops::Index<TreeIndex> for Tree<T> {
     }
 }
 
-impl<T> std::ops::IndexMut<TreeIndex> for Tree<T> {
+impl<T> core::ops::IndexMut<TreeIndex> for Tree<T> {
     fn index_mut(&mut self, ix: TreeIndex) -> &mut Node<T> {
         self.nodes.index_mut(ix.get())
     }
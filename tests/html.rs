@@ -248,3 +248,357 @@ fn html_test_broken_callback() {
 
     assert_eq!(expected, s);
 }
+
+#[test]
+fn html_test_tight_list_paragraphs() {
+    let original = "- a\n- b\n  > c\n\n1. d\n\n2. e\n";
+    let expected = r##"<ul>
+<li>a</li>
+<li>b
+<blockquote>
+<p>c</p>
+</blockquote>
+</li>
+</ul>
+<ol>
+<li>
+<p>d</p>
+</li>
+<li>
+<p>e</p>
+</li>
+</ol>
+"##;
+
+    let mut s = String::new();
+    html::push_html(&mut s, Parser::new(&original));
+    assert_eq!(expected, s);
+
+    let mut s = String::new();
+    let mut opts = Options::empty();
+    opts.insert(Options::ENABLE_TIGHT_LIST_PARAGRAPHS);
+    html::push_html(&mut s, Parser::new_ext(&original, opts));
+    assert_eq!(expected, s);
+}
+
+#[test]
+fn html_test_fancy_lists() {
+    let original = r##"(a) first
+(b) second
+    i. nested
+    ii. more
+
+C)  third
+
+B. Russell
+
+h. eight
+i. nine
+"##;
+    let expected = r##"<ol type="a">
+<li>first</li>
+<li>second
+<ol type="i">
+<li>nested</li>
+<li>more</li>
+</ol>
+</li>
+</ol>
+<ol type="A" start="3">
+<li>third</li>
+</ol>
+<p>B. Russell</p>
+<ol type="a" start="8">
+<li>eight</li>
+<li>nine</li>
+</ol>
+"##;
+
+    let mut s = String::new();
+    let mut opts = Options::empty();
+    opts.insert(Options::ENABLE_FANCY_LISTS);
+    html::push_html(&mut s, Parser::new_ext(&original, opts));
+    assert_eq!(expected, s);
+}
+
+#[test]
+fn html_test_heading_ids() {
+    let original = r##"# Hello *world*
+
+Hello world
+-----------
+
+## [Links](https://example.com) & `code`
+"##;
+    let expected = r##"<h1 id="hello-world">Hello <em>world</em></h1>
+<h2 id="hello-world-1">Hello world</h2>
+<h2 id="links--code"><a href="https://example.com">Links</a> &amp; <code>code</code></h2>
+"##;
+
+    let mut s = String::new();
+    let mut opts = Options::empty();
+    opts.insert(Options::ENABLE_HEADING_IDS);
+    html::push_html(&mut s, Parser::new_ext(&original, opts));
+    assert_eq!(expected, s);
+}
+
+#[test]
+fn html_test_toc() {
+    let original = r##"# Title
+
+[TOC]
+
+## First *section*
+
+### Details
+
+## Second section
+
+# Title
+"##;
+    let expected = r##"<h1 id="title">Title</h1>
+<ul>
+<li><a href="#title">Title</a>
+<ul>
+<li><a href="#first-section">First section</a>
+<ul>
+<li><a href="#details">Details</a></li>
+</ul>
+</li>
+<li><a href="#second-section">Second section</a></li>
+</ul>
+</li>
+<li><a href="#title-1">Title</a></li>
+</ul>
+<h2 id="first-section">First <em>section</em></h2>
+<h3 id="details">Details</h3>
+<h2 id="second-section">Second section</h2>
+<h1 id="title-1">Title</h1>
+"##;
+
+    let mut s = String::new();
+    let mut opts = Options::empty();
+    opts.insert(Options::ENABLE_TOC);
+    html::push_html(&mut s, Parser::new_ext(&original, opts));
+    assert_eq!(expected, s);
+}
+
+#[test]
+fn html_test_toc_reference_link() {
+    let original = r##"[TOC]
+
+[toc]: /contents
+"##;
+    let expected = r##"<p><a href="/contents">TOC</a></p>
+"##;
+
+    let mut s = String::new();
+    let mut opts = Options::empty();
+    opts.insert(Options::ENABLE_TOC);
+    html::push_html(&mut s, Parser::new_ext(&original, opts));
+    assert_eq!(expected, s);
+}
+
+#[test]
+fn html_test_include() {
+    let original = r##"# Manual
+
+{{#include intro.md}}
+
+{{#include missing.md}}
+
+> {{#include item.md}}
+"##;
+    let expected = r##"<h1>Manual</h1>
+<p>Welcome.</p>
+<blockquote>
+<p>Note: {{#include intro.md}}</p>
+<p>{{#include intro.md}}</p>
+</blockquote>
+<p>{{#include missing.md}}</p>
+<blockquote>
+<p>Quoted.</p>
+</blockquote>
+"##;
+
+    let mut resolver = |path: &str| match path {
+        "intro.md" => Some("Welcome.\n\n{{#include note.md}}\n".into()),
+        "note.md" => Some("> Note: {{#include intro.md}}\n>\n> {{#include intro.md}}\n".into()),
+        "item.md" => Some(format!("{}.\n", "Quoted").into()),
+        _ => None,
+    };
+    let mut s = String::new();
+    let parser = Parser::new(&original).with_include_resolver(&mut resolver);
+    html::push_html(&mut s, parser);
+    assert_eq!(expected, s);
+}
+
+#[test]
+fn html_test_raw_attributes() {
+    let original = r##"```{=html}
+<video src="demo.mp4"></video>
+```
+
+```{=latex}
+\newpage
+```
+
+Line`<br>`{=html}break and `\LaTeX`{=latex} and `{=html}`.
+
+``` {=html }
+not raw
+```
+"##;
+    let expected = r##"<video src="demo.mp4"></video>
+<p>Line<br>break and  and <code>{=html}</code>.</p>
+<pre><code class="language-{=html">not raw
+</code></pre>
+"##;
+
+    let mut s = String::new();
+    let mut opts = Options::empty();
+    opts.insert(Options::ENABLE_RAW_ATTRIBUTES);
+    html::push_html(&mut s, Parser::new_ext(&original, opts));
+    assert_eq!(expected, s);
+}
+
+#[test]
+fn html_test_line_blocks() {
+    let original = r##"| The Lobster Quadrille
+|
+|    "Will you walk a little *faster*?"
+| said a whiting to a snail,
+  "There's a porpoise close behind us"
+> | Jane Doe
+> | 12 Main St.
+|not a line block
+"##;
+    let expected = "<div class=\"line-block\">The Lobster Quadrille<br />
+<br />
+\u{a0}\u{a0}\u{a0}&quot;Will you walk a little <em>faster</em>?&quot;<br />
+said a whiting to a snail,
+&quot;There's a porpoise close behind us&quot;</div>
+<blockquote>
+<div class=\"line-block\">Jane Doe<br />
+12 Main St.</div>
+</blockquote>
+<p>|not a line block</p>
+";
+
+    let mut s = String::new();
+    let mut opts = Options::empty();
+    opts.insert(Options::ENABLE_LINE_BLOCKS);
+    html::push_html(&mut s, Parser::new_ext(&original, opts));
+    assert_eq!(expected, s);
+}
+
+#[test]
+fn html_test_line_blocks_and_tables() {
+    let original = r##"| a | b |
+| - | - |
+| c | d |
+
+| e | f |
+"##;
+    let expected = r##"<table><thead><tr><th>a</th><th>b</th></tr></thead><tbody>
+<tr><td>c</td><td>d</td></tr>
+</tbody></table>
+<div class="line-block">e | f |</div>
+"##;
+
+    let mut s = String::new();
+    let mut opts = Options::empty();
+    opts.insert(Options::ENABLE_LINE_BLOCKS);
+    opts.insert(Options::ENABLE_TABLES);
+    html::push_html(&mut s, Parser::new_ext(&original, opts));
+    assert_eq!(expected, s);
+}
+
+#[test]
+fn html_test_comments() {
+    let original = r##"Visible %%hidden *note*%% text, `%%code%%` and 5 %.
+
+%%
+A block comment.
+
+> Still hidden.
+%%
+
+> %% quoted
+> comment %%
+
+%% not a block %% but a paragraph
+"##;
+    let expected = r##"<p>Visible  text, <code>%%code%%</code> and 5 %.</p>
+<blockquote>
+</blockquote>
+<p> but a paragraph</p>
+"##;
+
+    let mut s = String::new();
+    let mut opts = Options::empty();
+    opts.insert(Options::ENABLE_COMMENTS);
+    html::push_html(&mut s, Parser::new_ext(&original, opts));
+    assert_eq!(expected, s);
+}
+
+#[test]
+fn html_test_strip_html_comments() {
+    let original = r##"Some <!-- inline --> text.
+
+<!-- a
+block comment --> <div>kept</div>
+
+<!-- unterminated
+"##;
+    let expected = r##"<p>Some  text.</p>
+ <div>kept</div>
+"##;
+
+    let mut s = String::new();
+    let mut opts = Options::empty();
+    opts.insert(Options::ENABLE_STRIP_HTML_COMMENTS);
+    html::push_html(&mut s, Parser::new_ext(&original, opts));
+    assert_eq!(expected, s);
+}
+
+#[test]
+fn html_test_hashtags() {
+    let original = r##"#tag and #nested/tag, but not `#code`, issue #12 or a.com/#frag
+
+#heading
+"##;
+    let expected = r##"<p><span class="hashtag">#tag</span> and <span class="hashtag">#nested/tag</span>, but not <code>#code</code>, issue #12 or a.com/#frag</p>
+<p><span class="hashtag">#heading</span></p>
+"##;
+
+    let mut s = String::new();
+    let mut opts = Options::empty();
+    opts.insert(Options::ENABLE_HASHTAGS);
+    html::push_html(&mut s, Parser::new_ext(&original, opts));
+    assert_eq!(expected, s);
+}
+
+#[test]
+fn html_test_block_ids() {
+    let original = r##"A paragraph. ^intro
+
+- first ^item-1
+- second
+
+Not^an-id
+"##;
+    let expected = r##"<p id="intro">A paragraph.</p>
+<ul>
+<li id="item-1">first</li>
+<li>second</li>
+</ul>
+<p>Not^an-id</p>
+"##;
+
+    let mut s = String::new();
+    let mut opts = Options::empty();
+    opts.insert(Options::ENABLE_BLOCK_IDS);
+    html::push_html(&mut s, Parser::new_ext(&original, opts));
+    assert_eq!(expected, s);
+}
@@ -2,7 +2,8 @@
 mod tests {
     use std::convert::TryInto;
 
-    use pulldown_cmark::CowStr;
+    use pulldown_cmark::ast::Document;
+    use pulldown_cmark::{CowStr, Options, Parser};
 
     #[test]
     fn cow_str_to_str_round_trip_bincode() {
@@ -75,4 +76,18 @@ mod tests {
         assert_eq!(decoded_str.as_ref(), str);
         assert_eq!(decoded_string.as_ref(), string);
     }
+
+    #[test]
+    fn ast_round_trip() {
+        let text = "# Title\n\n- [x] *a* `b`\n\n| c |\n|---|\n| [d](/e) |\n";
+        let document: Document = Parser::new_ext(text, Options::all()).collect();
+
+        let json: Document =
+            serde_json::from_str(&serde_json::to_string(&document).unwrap()).unwrap();
+        let bincode: Document =
+            bincode::deserialize(&bincode::serialize(&document).unwrap()).unwrap();
+
+        assert_eq!(document, json);
+        assert_eq!(document, bincode);
+    }
 }